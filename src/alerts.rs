@@ -0,0 +1,58 @@
+use std::collections::BTreeSet;
+
+use crate::structs::{BatteryStatus, ChargingState};
+
+/// Something worth telling the user about a pad's battery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatteryAlert {
+    /// Dropped to or below this threshold
+    Low(u8),
+    FullyCharged,
+}
+
+/// Remembers which alerts already went out so each fires once per
+/// discharge cycle
+#[derive(Debug, Default)]
+pub struct BatteryAlerts {
+    /// Thresholds already crossed since the pad was last charged
+    crossed: BTreeSet<u8>,
+    /// Seen charging since the last "fully charged" alert
+    charging: bool,
+}
+
+impl BatteryAlerts {
+    /// Records `status` and returns the alert it triggers, if any
+    ///
+    /// Dropping past several thresholds between two readings only reports
+    /// the lowest one
+    pub fn update(
+        &mut self,
+        status: &BatteryStatus,
+        thresholds: &[u8],
+        notify_when_charged: bool,
+    ) -> Option<BatteryAlert> {
+        match status.state {
+            ChargingState::Charging if status.level < 100 => {
+                self.crossed.clear();
+                self.charging = true;
+                None
+            }
+            ChargingState::Charging | ChargingState::Full => {
+                self.crossed.clear();
+                let was_charging = std::mem::take(&mut self.charging);
+                (was_charging && notify_when_charged).then_some(BatteryAlert::FullyCharged)
+            }
+            ChargingState::Discharging => {
+                self.charging = false;
+                let newly_crossed: Vec<u8> = thresholds
+                    .iter()
+                    .copied()
+                    .filter(|&t| status.level <= t && !self.crossed.contains(&t))
+                    .collect();
+                self.crossed.extend(&newly_crossed);
+                newly_crossed.into_iter().min().map(BatteryAlert::Low)
+            }
+            ChargingState::Unknown => None,
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use log::{error, info};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::devices::{DeviceHandle, DeviceRegistry};
+use crate::save::{load_profile, AppPaths};
+use crate::structs::{Controller, MatchOn, ProfileRule, Settings};
+use crate::worker::{DeviceCommand, Output, Worker};
+
+/// How often `/proc` is scanned for matching programs
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A running program, as read from `/proc/<pid>`
+#[derive(Debug, Clone)]
+pub struct Process {
+    /// `/proc/<pid>/comm`, cut off at 15 characters by the kernel
+    pub comm: String,
+    /// `/proc/<pid>/cmdline` split into its arguments
+    pub args: Vec<String>,
+}
+
+impl Process {
+    /// File name of the first argument, `/` or `\\` separated so Wine and
+    /// Proton paths work too
+    fn executable(&self) -> Option<&str> {
+        self.args.first()?.rsplit(['/', '\\']).next()
+    }
+}
+
+/// Every process we're allowed to look at, except our own
+///
+/// Copies of this program are skipped so a command line rule can't match
+/// the window, the daemon or `run --profile ... -- game`, whose arguments
+/// name the game too
+pub fn running_processes() -> Vec<Process> {
+    processes_in(Path::new("/proc"), env::current_exe().ok().as_deref())
+}
+
+/// The processes listed in a `/proc` style `dir`, skipping those running
+/// `own_exe`
+fn processes_in(dir: &Path, own_exe: Option<&Path>) -> Vec<Process> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .filter_map(|entry| {
+            let path = entry.path();
+            if own_exe.is_some() && fs::read_link(path.join("exe")).ok().as_deref() == own_exe {
+                return None;
+            }
+            // Gone already, or belongs to someone else
+            let comm = fs::read_to_string(path.join("comm")).ok()?;
+            let cmdline = fs::read(path.join("cmdline")).unwrap_or_default();
+            let args = cmdline
+                .split(|b| *b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect();
+            Some(Process {
+                comm: comm.trim_end().to_string(),
+                args,
+            })
+        })
+        .collect()
+}
+
+/// Case insensitive, an empty pattern never matches
+pub fn rule_matches(rule: &ProfileRule, process: &Process) -> bool {
+    let pattern = rule.pattern.trim().to_lowercase();
+    if pattern.is_empty() {
+        return false;
+    }
+
+    match rule.match_on {
+        MatchOn::Executable => {
+            process.comm.to_lowercase() == pattern
+                || process
+                    .executable()
+                    .is_some_and(|exe| exe.to_lowercase() == pattern)
+        }
+        MatchOn::CommandLine => process.args.join(" ").to_lowercase().contains(&pattern),
+    }
+}
+
+/// The highest priority rule matching any of `processes`, the earlier one
+/// on a tie
+pub fn best_rule<'a>(rules: &'a [ProfileRule], processes: &[Process]) -> Option<&'a ProfileRule> {
+    rules
+        .iter()
+        .filter(|rule| processes.iter().any(|process| rule_matches(rule, process)))
+        .fold(None, |best: Option<&ProfileRule>, rule| match best {
+            Some(best) if best.priority >= rule.priority => Some(best),
+            _ => Some(rule),
+        })
+}
+
+fn apply(worker: &Worker, device: &DeviceHandle, name: &str, state: Controller) {
+    match worker.execute(device, DeviceCommand::Apply(state)).result {
+        Ok(Output::Applied(report)) if !report.succeeded() => {
+            error!(
+                "Failed to apply '{}' to {}: {}",
+                name,
+                device.label(),
+                report
+            );
+        }
+        Ok(_) => info!("Applied '{}' to {}", name, device.label()),
+        Err(err) => error!("Failed to apply '{}' to {}: {}", name, device.label(), err),
+    }
+}
+
+/// Tracks which rule's profile is applied so it's only pushed on changes
+struct AutoSwitcher {
+    registry: Arc<DeviceRegistry>,
+    worker: Worker,
+    app_paths: Arc<AppPaths>,
+    /// Profile of the rule that matched last time
+    active: Option<String>,
+    /// State of each pad from before the first switch
+    previous: Vec<(DeviceHandle, Controller)>,
+}
+
+impl AutoSwitcher {
+    fn apply_profile(&self, name: &str) {
+        match load_profile(name, &self.app_paths) {
+            Ok(profile) => {
+                for device in self.registry.connected_handles() {
+                    apply(&self.worker, &device, name, profile.clone());
+                }
+            }
+            Err(err) => error!("Failed to load profile '{}': {}", name, err),
+        }
+    }
+
+    /// Back to the default profile, or what was there before if none is set
+    fn revert(&mut self, default_profile: Option<&str>) {
+        match default_profile {
+            Some(name) => self.apply_profile(name),
+            None => {
+                for (device, state) in &self.previous {
+                    apply(&self.worker, device, "previous state", state.clone());
+                }
+            }
+        }
+        self.active = None;
+        self.previous.clear();
+    }
+
+    fn tick(&mut self, settings: &Mutex<Settings>) {
+        let Ok((enabled, rules, default_profile)) = settings.lock().map(|s| {
+            (
+                s.auto_profile_enabled,
+                s.auto_profile_rules.clone(),
+                s.default_profile.clone(),
+            )
+        }) else {
+            return;
+        };
+
+        let wanted = if enabled && !rules.is_empty() {
+            best_rule(&rules, &running_processes()).map(|rule| rule.profile.clone())
+        } else {
+            None
+        };
+        if wanted == self.active {
+            return;
+        }
+
+        match wanted {
+            Some(name) => {
+                info!("Switching to profile '{}'", name);
+                if self.active.is_none() {
+                    self.previous = self
+                        .registry
+                        .connected_handles()
+                        .into_iter()
+                        .filter_map(|device| {
+                            let state = device.controller.lock().ok()?.clone();
+                            Some((device, state))
+                        })
+                        .collect();
+                }
+                self.apply_profile(&name);
+                self.active = Some(name);
+            }
+            None => {
+                info!("No matching program running, switching back");
+                self.revert(default_profile.as_deref());
+            }
+        }
+    }
+}
+
+/// Polls running programs and applies the profile of the best matching
+/// `Settings::auto_profile_rules` entry
+///
+/// Rules and the on/off switch are read on every poll so changes apply
+/// straight away
+pub fn spawn(
+    registry: Arc<DeviceRegistry>,
+    worker: Worker,
+    settings: Arc<Mutex<Settings>>,
+    app_paths: Arc<AppPaths>,
+) -> io::Result<()> {
+    let mut switcher = AutoSwitcher {
+        registry,
+        worker,
+        app_paths,
+        active: None,
+        previous: Vec::new(),
+    };
+
+    thread::Builder::new()
+        .name("autoswitch".to_string())
+        .spawn(move || loop {
+            switcher.tick(&settings);
+            thread::sleep(POLL_INTERVAL);
+        })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(comm: &str, args: &[&str]) -> Process {
+        Process {
+            comm: comm.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    fn rule(pattern: &str, match_on: MatchOn, profile: &str, priority: i32) -> ProfileRule {
+        ProfileRule {
+            pattern: pattern.to_string(),
+            match_on,
+            profile: profile.to_string(),
+            priority,
+        }
+    }
+
+    /// A Proton game, where `comm` is cut short and the path is Windows style
+    fn proton_game() -> Process {
+        process(
+            "eldenring.exe",
+            &["Z:\\games\\ELDEN RING\\Game\\eldenring.exe", "-windowed"],
+        )
+    }
+
+    #[test]
+    fn executable_rules() {
+        let game = proton_game();
+        assert!(rule_matches(
+            &rule("eldenring.exe", MatchOn::Executable, "", 0),
+            &game
+        ));
+        assert!(rule_matches(
+            &rule(" EldenRing.EXE ", MatchOn::Executable, "", 0),
+            &game
+        ));
+        // The whole name, not part of it
+        assert!(!rule_matches(
+            &rule("eldenring", MatchOn::Executable, "", 0),
+            &game
+        ));
+
+        // `comm` stops at 15 characters, the first argument has the full name
+        let long = process("supertuxkart-wr", &["/usr/bin/supertuxkart-wrapper"]);
+        assert!(rule_matches(
+            &rule("supertuxkart-wrapper", MatchOn::Executable, "", 0),
+            &long
+        ));
+        assert!(rule_matches(
+            &rule("supertuxkart-wr", MatchOn::Executable, "", 0),
+            &long
+        ));
+    }
+
+    #[test]
+    fn command_line_rules() {
+        let game = proton_game();
+        assert!(rule_matches(
+            &rule("elden ring", MatchOn::CommandLine, "", 0),
+            &game
+        ));
+        assert!(rule_matches(
+            &rule("-WINDOWED", MatchOn::CommandLine, "", 0),
+            &game
+        ));
+        assert!(!rule_matches(
+            &rule("-fullscreen", MatchOn::CommandLine, "", 0),
+            &game
+        ));
+    }
+
+    #[test]
+    fn empty_patterns_never_match() {
+        let game = proton_game();
+        for match_on in [MatchOn::Executable, MatchOn::CommandLine] {
+            assert!(!rule_matches(&rule("", match_on, "", 0), &game));
+            assert!(!rule_matches(&rule("  ", match_on, "", 0), &game));
+        }
+    }
+
+    #[test]
+    fn highest_priority_wins_then_the_earlier_rule() {
+        let processes = [proton_game(), process("steam", &["/usr/bin/steam"])];
+        let rules = [
+            rule("steam", MatchOn::Executable, "launcher", 0),
+            rule("eldenring.exe", MatchOn::Executable, "souls", 5),
+            rule("elden", MatchOn::CommandLine, "souls-tie", 5),
+            rule("firefox", MatchOn::Executable, "browser", 10),
+        ];
+        assert_eq!(best_rule(&rules, &processes).unwrap().profile, "souls");
+        assert_eq!(
+            best_rule(&rules[..1], &processes).unwrap().profile,
+            "launcher"
+        );
+        assert_eq!(best_rule(&rules[3..], &processes), None);
+        assert_eq!(best_rule(&rules, &[]), None);
+    }
+
+    #[test]
+    fn own_processes_are_skipped() {
+        let dir =
+            std::env::temp_dir().join(format!("dualsensectl-gui-test-{}-proc", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let own_exe = Path::new("/usr/bin/dualsensectl-gui");
+        let entries: [(&str, &str, &Path, &[u8]); 3] = [
+            (
+                "100",
+                "dualsensectl-gu",
+                own_exe,
+                b"dualsensectl-gui\0run\0--profile\0souls\0--\0eldenring.exe\0",
+            ),
+            (
+                "101",
+                "eldenring.exe",
+                Path::new("/usr/bin/wine64-preloader"),
+                b"Z:\\eldenring.exe\0",
+            ),
+            // Not a pid, never listed
+            ("self", "bash", Path::new("/usr/bin/bash"), b"bash\0"),
+        ];
+        for (pid, comm, exe, cmdline) in entries {
+            let process = dir.join(pid);
+            fs::create_dir_all(&process).unwrap();
+            fs::write(process.join("comm"), format!("{comm}\n")).unwrap();
+            fs::write(process.join("cmdline"), cmdline).unwrap();
+            std::os::unix::fs::symlink(exe, process.join("exe")).unwrap();
+        }
+
+        let processes = processes_in(&dir, Some(own_exe));
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].comm, "eldenring.exe");
+        assert_eq!(processes[0].args, ["Z:\\eldenring.exe"]);
+
+        // A rule naming the game on the command line only sees the game
+        let souls = rule("eldenring", MatchOn::CommandLine, "souls", 0);
+        assert_eq!(
+            processes.iter().filter(|p| rule_matches(&souls, p)).count(),
+            1
+        );
+        assert_eq!(processes_in(&dir, None).len(), 2);
+    }
+}
@@ -0,0 +1,101 @@
+pub mod capabilities;
+pub mod cli;
+pub mod hidraw;
+pub mod mock;
+
+use log::warn;
+use std::io::BufRead;
+use std::sync::Arc;
+
+use crate::backend::capabilities::Capabilities;
+use crate::error::DualsenseError;
+use crate::structs::{BatteryStatus, ConnectedDevice, DeviceInfo, Speaker, Trigger};
+
+/// Builds a backend by name: `cli` (default), `hidraw` or `mock`
+///
+/// `binary` overrides where the `cli` backend looks for `dualsensectl`
+pub fn from_name(name: &str, binary: Option<&str>) -> Arc<dyn ControllerBackend> {
+    match name {
+        "cli" | "dualsensectl" => Arc::new(cli::CliBackend::with_binary(binary)),
+        "hidraw" => Arc::new(hidraw::HidrawBackend::new()),
+        "mock" => Arc::new(mock::MockBackend::new()),
+        other => {
+            warn!("Unknown backend '{}', falling back to dualsensectl", other);
+            Arc::new(cli::CliBackend::with_binary(binary))
+        }
+    }
+}
+
+/// Everything the app can ask of a controller
+///
+/// `dualsensectl.rs` validates input and keeps `Controller` in sync, the
+/// backend only talks to the hardware (or pretends to)
+pub trait ControllerBackend: Send + Sync {
+    /// Short name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Lists connected pads
+    fn list_devices(&self) -> Result<Vec<ConnectedDevice>, DualsenseError>;
+
+    /// Same backend, but every call goes to the pad with this serial
+    fn for_device(&self, serial: &str) -> Arc<dyn ControllerBackend>;
+
+    /// What this backend can do, everything unless it says otherwise
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::all()
+    }
+
+    /// Turns the lightbar on/off without touching its colour
+    fn set_lightbar(&self, enabled: bool) -> Result<(), DualsenseError>;
+
+    /// Sets the lightbar colour, RGB BRIGHTNESS 0-255
+    fn set_lightbar_colour(
+        &self,
+        red: u8,
+        green: u8,
+        blue: u8,
+        brightness: u8,
+    ) -> Result<(), DualsenseError>;
+
+    /// Sets the player LED amount, 0-5
+    fn set_player_leds(&self, amount: u8) -> Result<(), DualsenseError>;
+
+    /// Enables/disables the microphone
+    fn set_microphone(&self, enabled: bool) -> Result<(), DualsenseError>;
+
+    /// Enables/disables the microphone LED
+    fn set_microphone_led(&self, enabled: bool) -> Result<(), DualsenseError>;
+
+    /// Selects the audio output
+    fn set_speaker(&self, speaker: &Speaker) -> Result<(), DualsenseError>;
+
+    /// Sets speaker/headphone volume, 0-255
+    fn set_volume(&self, volume: u8) -> Result<(), DualsenseError>;
+
+    /// Sets rumble and trigger attenuation, 0-7 each
+    fn set_attenuation(&self, rumble: u8, trigger: u8) -> Result<(), DualsenseError>;
+
+    /// Sets a trigger motor effect
+    fn set_trigger(&self, trigger: &Trigger) -> Result<(), DualsenseError>;
+
+    /// Reads the battery level and charging state
+    fn battery(&self) -> Result<BatteryStatus, DualsenseError>;
+
+    /// Turns the pad off, only works over Bluetooth
+    fn power_off(&self) -> Result<(), DualsenseError>;
+
+    /// Reads firmware and hardware details
+    fn info(&self) -> Result<DeviceInfo, DualsenseError>;
+
+    /// Live input report lines, see `monitor::parse_line` for the format
+    ///
+    /// Runs until `stop` is called or the output is dropped
+    fn monitor(&self) -> Result<MonitorStream, DualsenseError>;
+}
+
+/// A running monitor
+pub struct MonitorStream {
+    pub output: Box<dyn BufRead + Send>,
+    /// Ends the monitor from any thread, `output` reaches its end soon after
+    pub stop: Box<dyn FnOnce() + Send>,
+}
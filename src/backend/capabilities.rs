@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+
+/// Something a backend may or may not be able to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    PowerOff,
+    Battery,
+    Info,
+    Lightbar,
+    PlayerLeds,
+    Microphone,
+    MicrophoneLed,
+    Speaker,
+    Volume,
+    Attenuation,
+    Trigger,
+    Monitor,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 12] = [
+        Capability::PowerOff,
+        Capability::Battery,
+        Capability::Info,
+        Capability::Lightbar,
+        Capability::PlayerLeds,
+        Capability::Microphone,
+        Capability::MicrophoneLed,
+        Capability::Speaker,
+        Capability::Volume,
+        Capability::Attenuation,
+        Capability::Trigger,
+        Capability::Monitor,
+    ];
+
+    /// The `dualsensectl` subcommand behind this capability
+    pub fn subcommand(self) -> &'static str {
+        match self {
+            Capability::PowerOff => "power-off",
+            Capability::Battery => "battery",
+            Capability::Info => "info",
+            Capability::Lightbar => "lightbar",
+            Capability::PlayerLeds => "player-leds",
+            Capability::Microphone => "microphone",
+            Capability::MicrophoneLed => "microphone-led",
+            Capability::Speaker => "speaker",
+            Capability::Volume => "volume",
+            Capability::Attenuation => "attenuation",
+            Capability::Trigger => "trigger",
+            Capability::Monitor => "monitor",
+        }
+    }
+}
+
+/// What a backend supports, and which `dualsensectl` it found if any
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    pub version: Option<String>,
+    supported: HashSet<Capability>,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Self::from_iter(Capability::ALL)
+    }
+
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// True when nothing is supported, i.e. the binary wasn't found
+    pub fn is_empty(&self) -> bool {
+        self.supported.is_empty()
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.supported.contains(&capability)
+    }
+
+    pub fn with_version(mut self, version: Option<String>) -> Self {
+        self.version = version;
+        self
+    }
+}
+
+impl FromIterator<Capability> for Capabilities {
+    fn from_iter<I: IntoIterator<Item = Capability>>(iter: I) -> Self {
+        Self {
+            version: None,
+            supported: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Picks out the subcommands listed in `dualsensectl -h`
+///
+/// ```text
+/// Commands:
+///   power-off                Turn off the controller (BT only)
+///   microphone-led STATE     Enable (on) or disable (off) microphone LED
+/// ```
+pub fn parse_help(help: &str) -> Capabilities {
+    let commands: HashSet<&str> = help
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+
+    Capability::ALL
+        .into_iter()
+        .filter(|c| commands.contains(c.subcommand()))
+        .collect()
+}
+
+/// Version number from `dualsensectl -v`, e.g. `dualsensectl 0.7` -> `0.7`
+pub fn parse_version(output: &str) -> Option<String> {
+    output
+        .split_whitespace()
+        .find(|word| {
+            word.trim_start_matches('v')
+                .starts_with(|c: char| c.is_ascii_digit())
+        })
+        .map(|word| word.trim_start_matches('v').to_string())
+}
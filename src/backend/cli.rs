@@ -0,0 +1,497 @@
+use log::{error, info, warn};
+use std::env;
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backend::capabilities::{parse_help, parse_version, Capabilities, Capability};
+use crate::backend::{ControllerBackend, MonitorStream};
+use crate::error::DualsenseError;
+use crate::structs::{
+    BatteryStatus, ChargingState, ConnectedDevice, Connection, DeviceInfo, Speaker, Trigger,
+};
+
+const DEFAULT_BINARY: &str = "dualsensectl";
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Backend that shells out to the `dualsensectl` binary
+pub struct CliBackend {
+    binary: String,
+    device: Option<String>,
+    capabilities: Arc<OnceLock<Capabilities>>,
+}
+
+impl CliBackend {
+    pub fn new() -> Self {
+        Self::with_binary(None)
+    }
+
+    /// Uses `binary` if it exists, otherwise looks for `dualsensectl` on
+    /// `PATH`
+    pub fn with_binary(binary: Option<&str>) -> Self {
+        let binary = match find_binary(binary) {
+            Some(path) => {
+                info!("Using dualsensectl at {}", path.display());
+                path.display().to_string()
+            }
+            None => {
+                error!("dualsensectl not found, controller commands will fail");
+                DEFAULT_BINARY.to_string()
+            }
+        };
+
+        Self {
+            binary,
+            device: None,
+            capabilities: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Asks the binary for its version and subcommands
+    ///
+    /// If the binary runs but its help can't be read we assume it can do
+    /// everything rather than locking the user out
+    fn probe(&self) -> Capabilities {
+        let version = self
+            .output(&["-v".to_string()])
+            .ok()
+            .and_then(|(_, stdout, stderr)| parse_version(&format!("{stdout}\n{stderr}")));
+
+        match self.output(&["-h".to_string()]) {
+            Ok((_, stdout, stderr)) => {
+                let capabilities = parse_help(&format!("{stdout}\n{stderr}"));
+                if capabilities.is_empty() {
+                    warn!("Couldn't parse dualsensectl help, assuming every command works");
+                    Capabilities::all().with_version(version)
+                } else {
+                    capabilities.with_version(version)
+                }
+            }
+            Err(DualsenseError::BinaryMissing) => Capabilities::none(),
+            Err(err) => {
+                warn!("Couldn't probe dualsensectl: {}", err);
+                Capabilities::all().with_version(version)
+            }
+        }
+    }
+
+    /// Fails with `Unsupported` if the installed binary lacks `capability`
+    ///
+    /// A missing binary is left to `run` so it reports `BinaryMissing`
+    fn require(&self, capability: Capability) -> Result<(), DualsenseError> {
+        let capabilities = self.capabilities();
+        if capabilities.is_empty() || capabilities.supports(capability) {
+            Ok(())
+        } else {
+            Err(DualsenseError::Unsupported(
+                capability.subcommand().to_string(),
+            ))
+        }
+    }
+
+    /// Runs `dualsensectl` with `args` and returns its stdout
+    ///
+    /// Non-zero exits are turned into a `DualsenseError` from stderr, and
+    /// the process is killed if it runs past `COMMAND_TIMEOUT`
+    fn run(&self, args: &[String]) -> Result<String, DualsenseError> {
+        let mut full_args = Vec::with_capacity(args.len() + 2);
+        if let Some(device) = &self.device {
+            full_args.push("-d".to_string());
+            full_args.push(device.clone());
+        }
+        full_args.extend_from_slice(args);
+
+        let command = format!("{} {}", self.binary, full_args.join(" "));
+        let (status, stdout, stderr) = self.output(&full_args)?;
+
+        if status.success() {
+            Ok(stdout)
+        } else {
+            // dualsensectl prints some errors to stdout
+            let message = if stderr.trim().is_empty() {
+                &stdout
+            } else {
+                &stderr
+            };
+            error!(
+                "Command '{}' failed with status {}: {}",
+                command,
+                status,
+                message.trim()
+            );
+            Err(DualsenseError::from_stderr(message))
+        }
+    }
+
+    /// Runs the binary with exactly `args`, whatever its exit status
+    fn output(&self, args: &[String]) -> Result<(ExitStatus, String, String), DualsenseError> {
+        let command = format!("{} {}", self.binary, args.join(" "));
+        info!("Executing command: {}", command);
+
+        let mut child = Command::new(&self.binary)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                error!("Failed to execute command '{}': {}", command, err);
+                match err.kind() {
+                    io::ErrorKind::NotFound => DualsenseError::BinaryMissing,
+                    io::ErrorKind::PermissionDenied => DualsenseError::PermissionDenied,
+                    _ => DualsenseError::CommandFailed(err.to_string()),
+                }
+            })?;
+
+        // Read while it runs, a full pipe would block it until the timeout
+        let stdout = read_pipe(child.stdout.take());
+        let stderr = read_pipe(child.stderr.take());
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() > COMMAND_TIMEOUT {
+                let _ = child.kill();
+                let _ = child.wait();
+                error!("Command '{}' timed out", command);
+                return Err(DualsenseError::Timeout);
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        let stdout = String::from_utf8(stdout.join().unwrap_or_default()).map_err(|_| {
+            error!("Failed to parse command stdout as UTF-8");
+            DualsenseError::CommandFailed("Invalid UTF-8 output".to_string())
+        })?;
+        let stderr = String::from_utf8_lossy(&stderr.join().unwrap_or_default()).into_owned();
+
+        Ok((status, stdout, stderr))
+    }
+
+    fn run_unit(&self, args: &[String]) -> Result<(), DualsenseError> {
+        self.run(args).map(|_| ())
+    }
+}
+
+/// Reads `pipe` to the end on its own thread
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+/// stdout of a running `dualsensectl monitor`, killed on drop
+struct MonitorOutput {
+    child: Arc<Mutex<Child>>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Read for MonitorOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl BufRead for MonitorOutput {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.stdout.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.stdout.consume(amount);
+    }
+}
+
+impl Drop for MonitorOutput {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = child.kill();
+        let _ = child.wait();
+        info!("Stopped dualsensectl monitor.");
+    }
+}
+
+impl Default for CliBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// Finds the binary to run, `configured` wins if it points at an executable
+pub fn find_binary(configured: Option<&str>) -> Option<PathBuf> {
+    if let Some(configured) = configured.filter(|c| !c.trim().is_empty()) {
+        let path = PathBuf::from(configured.trim());
+        if is_executable(&path) {
+            return Some(path);
+        }
+        warn!(
+            "Configured dualsensectl '{}' isn't executable, searching PATH",
+            path.display()
+        );
+    }
+
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(DEFAULT_BINARY))
+            .find(|path| is_executable(path))
+    })
+}
+
+/// Parses `dualsensectl -l`
+///
+/// ```text
+/// Devices:
+///  84:30:95:aa:bb:cc (Bluetooth)
+/// ```
+pub fn parse_device_list(output: &str) -> Vec<ConnectedDevice> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (serial, rest) = line.trim().split_once(' ')?;
+            let connection = match rest.trim().trim_matches(|c| c == '(' || c == ')') {
+                "Bluetooth" => Connection::Bluetooth,
+                "USB" => Connection::Usb,
+                _ => return None,
+            };
+            Some(ConnectedDevice {
+                serial: serial.to_string(),
+                connection,
+            })
+        })
+        .collect()
+}
+
+/// Parses `dualsensectl battery`, e.g. `80 discharging`
+pub fn parse_battery_status(output: &str) -> Option<BatteryStatus> {
+    let mut parts = output.split_whitespace();
+    let level = parts.next()?.trim_end_matches('%').parse::<u8>().ok()?;
+    let state = match parts.next().map(str::to_lowercase).as_deref() {
+        Some("charging") => ChargingState::Charging,
+        Some("discharging") => ChargingState::Discharging,
+        Some("full") => ChargingState::Full,
+        _ => ChargingState::Unknown,
+    };
+
+    Some(BatteryStatus {
+        level: level.min(100),
+        state,
+    })
+}
+
+/// Parses `dualsensectl info`, one `Key: value` per line
+///
+/// ```text
+/// Hardware: 0x...
+/// Firmware: 0x...
+/// Build date: Jun 16 2023 05:23:15
+/// ```
+pub fn parse_device_info(output: &str) -> DeviceInfo {
+    let mut info = DeviceInfo::default();
+
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if value.is_empty() {
+            continue;
+        }
+
+        let lower = key.to_lowercase();
+        if lower.contains("serial") || lower.contains("mac") {
+            info.serial = Some(value.to_string());
+        } else if lower.contains("connection") || lower == "bus" {
+            info.connection = match value.to_lowercase().as_str() {
+                "usb" => Some(Connection::Usb),
+                "bluetooth" | "bt" => Some(Connection::Bluetooth),
+                _ => None,
+            };
+        } else if lower.contains("date") {
+            info.build_dates.push((key.to_string(), value.to_string()));
+        } else if lower.starts_with("hardware") {
+            info.hardware_version = Some(value.to_string());
+        } else if lower.starts_with("firmware") {
+            info.firmware_version = Some(value.to_string());
+        } else {
+            info.other.push((key.to_string(), value.to_string()));
+        }
+    }
+
+    info
+}
+
+fn on_off(state: bool) -> String {
+    if state { "on" } else { "off" }.to_string()
+}
+
+impl ControllerBackend for CliBackend {
+    fn name(&self) -> &'static str {
+        "dualsensectl"
+    }
+
+    fn list_devices(&self) -> Result<Vec<ConnectedDevice>, DualsenseError> {
+        let stdout = self.run(&["-l".to_string()])?;
+        Ok(parse_device_list(&stdout))
+    }
+
+    fn for_device(&self, serial: &str) -> Arc<dyn ControllerBackend> {
+        Arc::new(Self {
+            binary: self.binary.clone(),
+            device: Some(serial.to_string()),
+            capabilities: Arc::clone(&self.capabilities),
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities.get_or_init(|| self.probe()).clone()
+    }
+
+    fn set_lightbar(&self, enabled: bool) -> Result<(), DualsenseError> {
+        self.require(Capability::Lightbar)?;
+        self.run_unit(&["lightbar".to_string(), on_off(enabled)])
+    }
+
+    fn set_lightbar_colour(
+        &self,
+        red: u8,
+        green: u8,
+        blue: u8,
+        brightness: u8,
+    ) -> Result<(), DualsenseError> {
+        self.require(Capability::Lightbar)?;
+        self.run_unit(&[
+            "lightbar".to_string(),
+            red.to_string(),
+            green.to_string(),
+            blue.to_string(),
+            brightness.to_string(),
+        ])
+    }
+
+    fn set_player_leds(&self, amount: u8) -> Result<(), DualsenseError> {
+        self.require(Capability::PlayerLeds)?;
+        self.run_unit(&["player-leds".to_string(), amount.to_string()])
+    }
+
+    fn set_microphone(&self, enabled: bool) -> Result<(), DualsenseError> {
+        self.require(Capability::Microphone)?;
+        self.run_unit(&["microphone".to_string(), on_off(enabled)])
+    }
+
+    fn set_microphone_led(&self, enabled: bool) -> Result<(), DualsenseError> {
+        self.require(Capability::MicrophoneLed)?;
+        self.run_unit(&["microphone-led".to_string(), on_off(enabled)])
+    }
+
+    fn set_speaker(&self, speaker: &Speaker) -> Result<(), DualsenseError> {
+        self.require(Capability::Speaker)?;
+        let state = match speaker {
+            Speaker::Internal => "internal",
+            Speaker::Headphone => "headphone",
+            Speaker::Monoheadphone => "monoheadphone",
+            Speaker::Both => "both",
+        };
+        self.run_unit(&["speaker".to_string(), state.to_string()])
+    }
+
+    fn set_volume(&self, volume: u8) -> Result<(), DualsenseError> {
+        self.require(Capability::Volume)?;
+        self.run_unit(&["volume".to_string(), volume.to_string()])
+    }
+
+    fn set_attenuation(&self, rumble: u8, trigger: u8) -> Result<(), DualsenseError> {
+        self.require(Capability::Attenuation)?;
+        self.run_unit(&[
+            "attenuation".to_string(),
+            rumble.to_string(),
+            trigger.to_string(),
+        ])
+    }
+
+    fn set_trigger(&self, trigger: &Trigger) -> Result<(), DualsenseError> {
+        self.require(Capability::Trigger)?;
+        let args: Vec<String> = trigger
+            .to_command()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        let stdout = self.run(&args)?;
+        info!("Command executed successfully: {}", stdout.trim());
+        Ok(())
+    }
+
+    fn battery(&self) -> Result<BatteryStatus, DualsenseError> {
+        self.require(Capability::Battery)?;
+        let stdout = self.run(&["battery".to_string()])?;
+        info!("Command output: {}", stdout.trim());
+
+        parse_battery_status(&stdout).ok_or_else(|| {
+            DualsenseError::CommandFailed(format!("Unexpected output: {}", stdout.trim()))
+        })
+    }
+
+    fn power_off(&self) -> Result<(), DualsenseError> {
+        self.require(Capability::PowerOff)?;
+        self.run_unit(&["power-off".to_string()])
+    }
+
+    fn info(&self) -> Result<DeviceInfo, DualsenseError> {
+        self.require(Capability::Info)?;
+        let stdout = self.run(&["info".to_string()])?;
+        Ok(parse_device_info(&stdout))
+    }
+
+    fn monitor(&self) -> Result<MonitorStream, DualsenseError> {
+        self.require(Capability::Monitor)?;
+
+        let mut args = Vec::new();
+        if let Some(device) = &self.device {
+            args.push("-d".to_string());
+            args.push(device.clone());
+        }
+        args.push("monitor".to_string());
+        info!("Starting monitor: {} {}", self.binary, args.join(" "));
+
+        let mut child = Command::new(&self.binary)
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::NotFound => DualsenseError::BinaryMissing,
+                _ => DualsenseError::from(err),
+            })?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| DualsenseError::CommandFailed("Monitor has no stdout".to_string()))?;
+
+        // Killing closes stdout, which ends whoever is reading it
+        let child = Arc::new(Mutex::new(child));
+        Ok(MonitorStream {
+            output: Box::new(MonitorOutput {
+                child: Arc::clone(&child),
+                stdout: BufReader::new(stdout),
+            }),
+            stop: Box::new(move || {
+                let _ = child.lock().unwrap_or_else(|e| e.into_inner()).kill();
+            }),
+        })
+    }
+}
@@ -0,0 +1,1112 @@
+use log::{error, info};
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use crate::backend::capabilities::{Capabilities, Capability};
+use crate::backend::{ControllerBackend, MonitorStream};
+use crate::error::DualsenseError;
+use crate::structs::{
+    BatteryStatus, ChargingState, ConnectedDevice, Connection, DeviceInfo, Speaker, Trigger,
+    TriggerEffect,
+};
+
+const HIDRAW_CLASS_PATH: &str = "/sys/class/hidraw";
+
+const SONY_VENDOR_ID: u32 = 0x054c;
+const DUALSENSE_PRODUCT_ID: u32 = 0x0ce6;
+const DUALSENSE_EDGE_PRODUCT_ID: u32 = 0x0df2;
+
+const BUS_USB: u32 = 0x03;
+const BUS_BLUETOOTH: u32 = 0x05;
+
+const OUTPUT_REPORT_USB: u8 = 0x02;
+const OUTPUT_REPORT_USB_SIZE: usize = 63;
+const OUTPUT_REPORT_BT: u8 = 0x31;
+const OUTPUT_REPORT_BT_SIZE: usize = 78;
+const OUTPUT_TAG: u8 = 0x10;
+const OUTPUT_CRC32_SEED: u8 = 0xa2;
+
+const INPUT_REPORT_USB: u8 = 0x01;
+const INPUT_REPORT_USB_SIZE: usize = 64;
+const INPUT_REPORT_BT: u8 = 0x31;
+const INPUT_REPORT_BT_SIZE: usize = 78;
+const INPUT_STATUS_OFFSET: usize = 52;
+
+const COMMON_SIZE: usize = 47;
+
+const VALID_FLAG0_RIGHT_TRIGGER_MOTOR_ENABLE: u8 = 1 << 2;
+const VALID_FLAG0_LEFT_TRIGGER_MOTOR_ENABLE: u8 = 1 << 3;
+const VALID_FLAG0_HEADPHONE_VOLUME_ENABLE: u8 = 1 << 4;
+const VALID_FLAG0_SPEAKER_VOLUME_ENABLE: u8 = 1 << 5;
+const VALID_FLAG0_AUDIO_CONTROL_ENABLE: u8 = 1 << 7;
+
+const VALID_FLAG1_MIC_MUTE_LED_CONTROL_ENABLE: u8 = 1 << 0;
+const VALID_FLAG1_POWER_SAVE_CONTROL_ENABLE: u8 = 1 << 1;
+const VALID_FLAG1_LIGHTBAR_CONTROL_ENABLE: u8 = 1 << 2;
+const VALID_FLAG1_PLAYER_INDICATOR_CONTROL_ENABLE: u8 = 1 << 4;
+const VALID_FLAG1_VIBRATION_ATTENUATION_ENABLE: u8 = 1 << 6;
+
+const VALID_FLAG2_LIGHTBAR_SETUP_CONTROL_ENABLE: u8 = 1 << 1;
+
+const POWER_SAVE_CONTROL_MIC_MUTE: u8 = 1 << 4;
+const LIGHTBAR_SETUP_LIGHT_ON: u8 = 1 << 0;
+const LIGHTBAR_SETUP_LIGHT_OUT: u8 = 1 << 1;
+
+/// Player LED patterns for 1-5 players, centre outwards like the PS5 does
+const PLAYER_LED_PATTERNS: [u8; 5] = [0b00100, 0b01010, 0b10101, 0b11011, 0b11111];
+
+const TRIGGER_MODE_OFF: u8 = 0x05;
+const TRIGGER_MODE_FEEDBACK: u8 = 0x21;
+const TRIGGER_MODE_BOW: u8 = 0x22;
+const TRIGGER_MODE_GALLOPING: u8 = 0x23;
+const TRIGGER_MODE_WEAPON: u8 = 0x25;
+const TRIGGER_MODE_VIBRATION: u8 = 0x26;
+const TRIGGER_MODE_MACHINE: u8 = 0x27;
+
+/// A DualSense found under `/sys/class/hidraw`
+#[derive(Debug, Clone)]
+pub struct HidrawDevice {
+    pub node: PathBuf,
+    pub connection: Connection,
+    /// `None` until the driver has filled in `HID_UNIQ`
+    pub serial: Option<String>,
+}
+
+/// Parses a hidraw `device/uevent` file, returns `None` for anything that
+/// isn't a DualSense
+///
+/// `HID_ID=0005:0000054C:00000CE6` is bus:vendor:product in hex, the
+/// serial is `None` if `HID_UNIQ` is missing or still empty
+pub fn parse_uevent(contents: &str) -> Option<(Connection, Option<String>)> {
+    let mut connection = None;
+    let mut serial = None;
+
+    for line in contents.lines() {
+        if let Some(id) = line.strip_prefix("HID_ID=") {
+            let fields: Vec<u32> = id
+                .split(':')
+                .filter_map(|f| u32::from_str_radix(f, 16).ok())
+                .collect();
+            if fields.len() != 3 || fields[1] != SONY_VENDOR_ID {
+                return None;
+            }
+            if fields[2] != DUALSENSE_PRODUCT_ID && fields[2] != DUALSENSE_EDGE_PRODUCT_ID {
+                return None;
+            }
+            connection = match fields[0] {
+                BUS_USB => Some(Connection::Usb),
+                BUS_BLUETOOTH => Some(Connection::Bluetooth),
+                _ => None,
+            };
+        } else if let Some(uniq) = line.strip_prefix("HID_UNIQ=") {
+            serial = Some(uniq.trim().to_string()).filter(|uniq| !uniq.is_empty());
+        }
+    }
+
+    connection.map(|c| (c, serial))
+}
+
+/// The DualSense behind hidraw node `name` (e.g. `hidraw3`), `None` if it
+/// isn't one
+pub fn read_device(name: &OsStr) -> Option<HidrawDevice> {
+    let uevent = fs::read_to_string(
+        Path::new(HIDRAW_CLASS_PATH)
+            .join(name)
+            .join("device/uevent"),
+    )
+    .ok()?;
+    let (connection, serial) = parse_uevent(&uevent)?;
+    Some(HidrawDevice {
+        node: Path::new("/dev").join(name),
+        connection,
+        serial,
+    })
+}
+
+/// Lists every DualSense hidraw node
+pub fn find_devices() -> Vec<HidrawDevice> {
+    let Ok(entries) = fs::read_dir(HIDRAW_CLASS_PATH) else {
+        return Vec::new();
+    };
+
+    let mut devices: Vec<HidrawDevice> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| read_device(&entry.file_name()))
+        .collect();
+
+    devices.sort_by(|a, b| a.node.cmp(&b.node));
+    devices
+}
+
+/// Fields of the output report shared by USB and Bluetooth
+///
+/// Only the parts we drive are exposed; `valid_flag*` tell the pad which
+/// sections to apply, everything else is ignored
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputReport {
+    pub valid_flag0: u8,
+    pub valid_flag1: u8,
+    pub valid_flag2: u8,
+    pub headphone_volume: u8,
+    pub speaker_volume: u8,
+    pub audio_flags: u8,
+    pub mute_button_led: u8,
+    pub power_save_control: u8,
+    pub right_trigger: [u8; 11],
+    pub left_trigger: [u8; 11],
+    pub reduce_motor_power: u8,
+    pub lightbar_setup: u8,
+    pub player_leds: u8,
+    pub lightbar: [u8; 3],
+}
+
+impl OutputReport {
+    /// Lightbar on/off, keeps the current colour
+    pub fn lightbar(enabled: bool) -> Self {
+        Self {
+            valid_flag2: VALID_FLAG2_LIGHTBAR_SETUP_CONTROL_ENABLE,
+            lightbar_setup: if enabled {
+                LIGHTBAR_SETUP_LIGHT_ON
+            } else {
+                LIGHTBAR_SETUP_LIGHT_OUT
+            },
+            ..Self::default()
+        }
+    }
+
+    /// Lightbar colour, RGB are scaled by brightness like `dualsensectl`
+    pub fn lightbar_colour(red: u8, green: u8, blue: u8, brightness: u8) -> Self {
+        let scale = |c: u8| (u16::from(c) * u16::from(brightness) / 255) as u8;
+        Self {
+            valid_flag1: VALID_FLAG1_LIGHTBAR_CONTROL_ENABLE,
+            lightbar: [scale(red), scale(green), scale(blue)],
+            ..Self::default()
+        }
+    }
+
+    /// Player LEDs, 0-5
+    pub fn player_leds(amount: u8) -> Self {
+        Self {
+            valid_flag1: VALID_FLAG1_PLAYER_INDICATOR_CONTROL_ENABLE,
+            player_leds: match amount {
+                0 => 0,
+                n => PLAYER_LED_PATTERNS[usize::from(n.min(5)) - 1],
+            },
+            ..Self::default()
+        }
+    }
+
+    pub fn microphone(enabled: bool) -> Self {
+        Self {
+            valid_flag1: VALID_FLAG1_POWER_SAVE_CONTROL_ENABLE,
+            power_save_control: if enabled {
+                0
+            } else {
+                POWER_SAVE_CONTROL_MIC_MUTE
+            },
+            ..Self::default()
+        }
+    }
+
+    pub fn microphone_led(enabled: bool) -> Self {
+        Self {
+            valid_flag1: VALID_FLAG1_MIC_MUTE_LED_CONTROL_ENABLE,
+            mute_button_led: u8::from(enabled),
+            ..Self::default()
+        }
+    }
+
+    pub fn speaker(speaker: &Speaker) -> Self {
+        let path: u8 = match speaker {
+            Speaker::Headphone => 0,
+            Speaker::Monoheadphone => 1,
+            Speaker::Both => 2,
+            Speaker::Internal => 3,
+        };
+        Self {
+            valid_flag0: VALID_FLAG0_AUDIO_CONTROL_ENABLE,
+            audio_flags: path << 4,
+            ..Self::default()
+        }
+    }
+
+    /// Volume 0-255, headphones only go up to 0x7f so they're scaled down
+    pub fn volume(volume: u8) -> Self {
+        Self {
+            valid_flag0: VALID_FLAG0_HEADPHONE_VOLUME_ENABLE | VALID_FLAG0_SPEAKER_VOLUME_ENABLE,
+            headphone_volume: (u16::from(volume) * 0x7f / 255) as u8,
+            speaker_volume: volume,
+            ..Self::default()
+        }
+    }
+
+    /// Rumble and trigger attenuation, 0-7 each
+    pub fn attenuation(rumble: u8, trigger: u8) -> Self {
+        Self {
+            valid_flag1: VALID_FLAG1_VIBRATION_ATTENUATION_ENABLE,
+            reduce_motor_power: (rumble & 0x07) | ((trigger & 0x07) << 4),
+            ..Self::default()
+        }
+    }
+
+    /// Trigger effect on "left", "right" or "both"
+    pub fn trigger(trigger: &Trigger) -> Result<Self, DualsenseError> {
+        let effect = trigger_effect_bytes(&trigger.effect)?;
+        let mut report = Self::default();
+
+        if matches!(trigger.side.as_str(), "right" | "both") {
+            report.valid_flag0 |= VALID_FLAG0_RIGHT_TRIGGER_MOTOR_ENABLE;
+            report.right_trigger = effect;
+        }
+        if matches!(trigger.side.as_str(), "left" | "both") {
+            report.valid_flag0 |= VALID_FLAG0_LEFT_TRIGGER_MOTOR_ENABLE;
+            report.left_trigger = effect;
+        }
+        if report.valid_flag0 == 0 {
+            return Err(DualsenseError::InvalidArgument(format!(
+                "Invalid trigger side: {}",
+                trigger.side
+            )));
+        }
+
+        Ok(report)
+    }
+
+    /// The 47 byte block both transports wrap
+    fn common(&self) -> [u8; COMMON_SIZE] {
+        let mut buf = [0u8; COMMON_SIZE];
+        buf[0] = self.valid_flag0;
+        buf[1] = self.valid_flag1;
+        // 2-3 are DS4 compatible rumble motors
+        buf[4] = self.headphone_volume;
+        buf[5] = self.speaker_volume;
+        // 6 is microphone volume
+        buf[7] = self.audio_flags;
+        buf[8] = self.mute_button_led;
+        buf[9] = self.power_save_control;
+        buf[10..21].copy_from_slice(&self.right_trigger);
+        buf[21..32].copy_from_slice(&self.left_trigger);
+        // 32-35 reserved
+        buf[36] = self.reduce_motor_power;
+        // 37 is speaker pre-gain
+        buf[38] = self.valid_flag2;
+        // 39-40 reserved
+        buf[41] = self.lightbar_setup;
+        // 42 is LED brightness
+        buf[43] = self.player_leds;
+        buf[44..47].copy_from_slice(&self.lightbar);
+        buf
+    }
+
+    /// USB output report, ID 0x02
+    pub fn to_usb(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; OUTPUT_REPORT_USB_SIZE];
+        buf[0] = OUTPUT_REPORT_USB;
+        buf[1..=COMMON_SIZE].copy_from_slice(&self.common());
+        buf
+    }
+
+    /// Bluetooth output report, ID 0x31, with sequence tag and CRC32
+    pub fn to_bluetooth(&self, sequence: u8) -> Vec<u8> {
+        let mut buf = vec![0u8; OUTPUT_REPORT_BT_SIZE];
+        buf[0] = OUTPUT_REPORT_BT;
+        buf[1] = (sequence & 0x0f) << 4;
+        buf[2] = OUTPUT_TAG;
+        buf[3..3 + COMMON_SIZE].copy_from_slice(&self.common());
+
+        let crc = bluetooth_crc32(&buf[..OUTPUT_REPORT_BT_SIZE - 4]);
+        buf[OUTPUT_REPORT_BT_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+}
+
+/// CRC32 of a Bluetooth output report, seeded with the HID output header
+pub fn bluetooth_crc32(report: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&[OUTPUT_CRC32_SEED]);
+    hasher.update(report);
+    hasher.finalize()
+}
+
+/// Sets bits `start..10` in the zone mask and `force` in each 3 bit slot
+fn zones_from(start: u8, force: u8) -> (u16, u32) {
+    let mut active_zones = 0u16;
+    let mut force_zones = 0u32;
+    for i in start.min(10)..10 {
+        active_zones |= 1 << i;
+        force_zones |= u32::from(force & 0x07) << (3 * i);
+    }
+    (active_zones, force_zones)
+}
+
+/// Same as `zones_from` but with a value per zone, 0 leaves a zone off
+fn zones_from_raw(values: &[u8; 10]) -> (u16, u32) {
+    let mut active_zones = 0u16;
+    let mut force_zones = 0u32;
+    for (i, &value) in values.iter().enumerate() {
+        if value > 0 {
+            active_zones |= 1 << i;
+            force_zones |= u32::from((value - 1) & 0x07) << (3 * i);
+        }
+    }
+    (active_zones, force_zones)
+}
+
+fn start_stop_zones(start: u8, stop: u8) -> [u8; 2] {
+    let zones = (1u16 << start.min(15)) | (1u16 << stop.min(15));
+    zones.to_le_bytes()
+}
+
+/// Parses a `Mode` param, accepts decimal or `0x` hex
+fn parse_mode_param(param: &str) -> Result<u8, DualsenseError> {
+    let param = param.trim();
+    let parsed = match param.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => param.parse::<u8>(),
+    };
+    parsed.map_err(|_| DualsenseError::InvalidArgument(format!("Trigger mode param: {param}")))
+}
+
+/// Encodes a trigger effect into mode byte + 10 params
+///
+/// Mirrors the effect factory `dualsensectl` uses so both backends feel the
+/// same
+pub fn trigger_effect_bytes(effect: &TriggerEffect) -> Result<[u8; 11], DualsenseError> {
+    let mut buf = [0u8; 11];
+
+    match effect {
+        TriggerEffect::Off
+        | TriggerEffect::Feedback { strength: 0, .. }
+        | TriggerEffect::Vibration { amplitude: 0, .. } => buf[0] = TRIGGER_MODE_OFF,
+        TriggerEffect::Feedback { position, strength } => {
+            let (active, force) = zones_from(*position, strength.saturating_sub(1));
+            buf[0] = TRIGGER_MODE_FEEDBACK;
+            buf[1..3].copy_from_slice(&active.to_le_bytes());
+            buf[3..7].copy_from_slice(&force.to_le_bytes());
+        }
+        TriggerEffect::Weapon {
+            start,
+            stop,
+            strength,
+        } => {
+            buf[0] = TRIGGER_MODE_WEAPON;
+            buf[1..3].copy_from_slice(&start_stop_zones(*start, *stop));
+            buf[3] = strength.saturating_sub(1) & 0x07;
+        }
+        TriggerEffect::Bow {
+            start,
+            stop,
+            strength,
+            snapforce,
+        } => {
+            let force_pair = u16::from(strength.saturating_sub(1) & 0x07)
+                | (u16::from(snapforce.saturating_sub(1) & 0x07) << 3);
+            buf[0] = TRIGGER_MODE_BOW;
+            buf[1..3].copy_from_slice(&start_stop_zones(*start, *stop));
+            buf[3..5].copy_from_slice(&force_pair.to_le_bytes());
+        }
+        TriggerEffect::Galloping {
+            start,
+            stop,
+            first_foot,
+            second_foot,
+            frequency,
+        } => {
+            buf[0] = TRIGGER_MODE_GALLOPING;
+            buf[1..3].copy_from_slice(&start_stop_zones(*start, *stop));
+            buf[3] = (second_foot & 0x07) | ((first_foot & 0x07) << 3);
+            buf[4] = *frequency;
+        }
+        TriggerEffect::Machine {
+            start,
+            stop,
+            strength_a,
+            strength_b,
+            frequency,
+            period,
+        } => {
+            buf[0] = TRIGGER_MODE_MACHINE;
+            buf[1..3].copy_from_slice(&start_stop_zones(*start, *stop));
+            buf[3] = (strength_a & 0x07) | ((strength_b & 0x07) << 3);
+            buf[4] = *frequency;
+            buf[5] = *period;
+        }
+        TriggerEffect::Vibration {
+            position,
+            amplitude,
+            frequency,
+        } => {
+            let (active, force) = zones_from(*position, amplitude.saturating_sub(1));
+            buf[0] = TRIGGER_MODE_VIBRATION;
+            buf[1..3].copy_from_slice(&active.to_le_bytes());
+            buf[3..7].copy_from_slice(&force.to_le_bytes());
+            buf[9] = *frequency;
+        }
+        TriggerEffect::FeedbackRaw { strength } => {
+            let (active, force) = zones_from_raw(strength);
+            buf[0] = TRIGGER_MODE_FEEDBACK;
+            buf[1..3].copy_from_slice(&active.to_le_bytes());
+            buf[3..7].copy_from_slice(&force.to_le_bytes());
+        }
+        TriggerEffect::VibrationRaw {
+            amplitude,
+            frequency,
+        } => {
+            let (active, force) = zones_from_raw(amplitude);
+            buf[0] = TRIGGER_MODE_VIBRATION;
+            buf[1..3].copy_from_slice(&active.to_le_bytes());
+            buf[3..7].copy_from_slice(&force.to_le_bytes());
+            buf[9] = *frequency;
+        }
+        TriggerEffect::Mode { params } => {
+            if params.is_empty() || params.len() > buf.len() {
+                return Err(DualsenseError::InvalidArgument(format!(
+                    "Trigger mode needs 1-{} params, got {}",
+                    buf.len(),
+                    params.len()
+                )));
+            }
+            for (slot, param) in buf.iter_mut().zip(params) {
+                *slot = parse_mode_param(param)?;
+            }
+        }
+    }
+
+    Ok(buf)
+}
+
+/// Battery status from a raw input report
+///
+/// Low nibble of the status byte is charge in tenths, high nibble is the
+/// charging state (0 discharging, 1 charging, 2 full)
+pub fn parse_battery(report: &[u8]) -> Result<BatteryStatus, DualsenseError> {
+    let offset = match (report.first(), report.len()) {
+        (Some(&INPUT_REPORT_USB), INPUT_REPORT_USB_SIZE) => 1,
+        (Some(&INPUT_REPORT_BT), INPUT_REPORT_BT_SIZE) => 2,
+        (Some(id), len) => {
+            return Err(DualsenseError::CommandFailed(format!(
+                "Unexpected input report {id:#04x} ({len} bytes), battery unavailable"
+            )))
+        }
+        (None, _) => {
+            return Err(DualsenseError::CommandFailed(
+                "Empty input report".to_string(),
+            ))
+        }
+    };
+
+    let status = report[offset + INPUT_STATUS_OFFSET];
+    let level = status & 0x0f;
+    let charging = (status & 0xf0) >> 4;
+
+    Ok(match charging {
+        0x00 => BatteryStatus {
+            level: (level * 10 + 5).min(100),
+            state: ChargingState::Discharging,
+        },
+        0x01 => BatteryStatus {
+            level: (level * 10 + 5).min(100),
+            state: ChargingState::Charging,
+        },
+        0x02 => BatteryStatus {
+            level: 100,
+            state: ChargingState::Full,
+        },
+        _ => BatteryStatus {
+            level: 0,
+            state: ChargingState::Unknown,
+        },
+    })
+}
+
+/// Backend that writes output reports straight to `/dev/hidrawN`
+///
+/// Needs read/write access to the node, usually via a udev rule
+pub struct HidrawBackend {
+    sequence: AtomicU8,
+    serial: Option<String>,
+}
+
+impl HidrawBackend {
+    pub fn new() -> Self {
+        Self {
+            sequence: AtomicU8::new(0),
+            serial: None,
+        }
+    }
+
+    /// The targeted pad, or the first one found when none was picked
+    fn device(&self) -> Result<HidrawDevice, DualsenseError> {
+        find_devices()
+            .into_iter()
+            .find(|d| self.serial.is_none() || d.serial == self.serial)
+            .ok_or(DualsenseError::NoDevice)
+    }
+
+    fn send(&self, report: &OutputReport) -> Result<(), DualsenseError> {
+        let device = self.device()?;
+        let buf = match device.connection {
+            Connection::Usb => report.to_usb(),
+            Connection::Bluetooth => {
+                let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+                report.to_bluetooth(sequence)
+            }
+        };
+
+        info!(
+            "Writing {} byte output report to {}",
+            buf.len(),
+            device.node.display()
+        );
+
+        OpenOptions::new()
+            .write(true)
+            .open(&device.node)
+            .and_then(|mut file| file.write_all(&buf))
+            .map_err(|err| {
+                error!("Failed to write to {}: {}", device.node.display(), err);
+                DualsenseError::from(err)
+            })
+    }
+}
+
+impl Default for HidrawBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ControllerBackend for HidrawBackend {
+    fn name(&self) -> &'static str {
+        "hidraw"
+    }
+
+    fn list_devices(&self) -> Result<Vec<ConnectedDevice>, DualsenseError> {
+        // Pads without a serial yet can still be reached as the default
+        // device, listing them would give them an empty one
+        Ok(find_devices()
+            .into_iter()
+            .filter_map(|d| {
+                Some(ConnectedDevice {
+                    serial: d.serial?,
+                    connection: d.connection,
+                })
+            })
+            .collect())
+    }
+
+    fn for_device(&self, serial: &str) -> Arc<dyn ControllerBackend> {
+        Arc::new(Self {
+            sequence: AtomicU8::new(0),
+            serial: Some(serial.to_string()),
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capability::ALL
+            .into_iter()
+            .filter(|c| {
+                !matches!(
+                    c,
+                    Capability::PowerOff | Capability::Info | Capability::Monitor
+                )
+            })
+            .collect()
+    }
+
+    fn set_lightbar(&self, enabled: bool) -> Result<(), DualsenseError> {
+        self.send(&OutputReport::lightbar(enabled))
+    }
+
+    fn set_lightbar_colour(
+        &self,
+        red: u8,
+        green: u8,
+        blue: u8,
+        brightness: u8,
+    ) -> Result<(), DualsenseError> {
+        self.send(&OutputReport::lightbar_colour(red, green, blue, brightness))
+    }
+
+    fn set_player_leds(&self, amount: u8) -> Result<(), DualsenseError> {
+        self.send(&OutputReport::player_leds(amount))
+    }
+
+    fn set_microphone(&self, enabled: bool) -> Result<(), DualsenseError> {
+        self.send(&OutputReport::microphone(enabled))
+    }
+
+    fn set_microphone_led(&self, enabled: bool) -> Result<(), DualsenseError> {
+        self.send(&OutputReport::microphone_led(enabled))
+    }
+
+    fn set_speaker(&self, speaker: &Speaker) -> Result<(), DualsenseError> {
+        self.send(&OutputReport::speaker(speaker))
+    }
+
+    fn set_volume(&self, volume: u8) -> Result<(), DualsenseError> {
+        self.send(&OutputReport::volume(volume))
+    }
+
+    fn set_attenuation(&self, rumble: u8, trigger: u8) -> Result<(), DualsenseError> {
+        self.send(&OutputReport::attenuation(rumble, trigger))
+    }
+
+    fn set_trigger(&self, trigger: &Trigger) -> Result<(), DualsenseError> {
+        self.send(&OutputReport::trigger(trigger)?)
+    }
+
+    fn battery(&self) -> Result<BatteryStatus, DualsenseError> {
+        let device = self.device()?;
+        let mut buf = [0u8; INPUT_REPORT_BT_SIZE];
+
+        let len = fs::File::open(&device.node)
+            .and_then(|mut file| file.read(&mut buf))
+            .map_err(|err| {
+                error!("Failed to read {}: {}", device.node.display(), err);
+                DualsenseError::from(err)
+            })?;
+
+        parse_battery(&buf[..len])
+    }
+
+    /// Needs a Bluetooth HCI disconnect, which a hidraw node can't do
+    fn power_off(&self) -> Result<(), DualsenseError> {
+        Err(DualsenseError::Unsupported("power-off".to_string()))
+    }
+
+    fn info(&self) -> Result<DeviceInfo, DualsenseError> {
+        Err(DualsenseError::Unsupported("info".to_string()))
+    }
+
+    fn monitor(&self) -> Result<MonitorStream, DualsenseError> {
+        Err(DualsenseError::Unsupported("monitor".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reports as `dualsensectl` and the kernel's hid-playstation driver lay
+    /// them out, written as hex dumps
+    fn hex(dump: &str) -> Vec<u8> {
+        dump.split_whitespace()
+            .map(|byte| u8::from_str_radix(byte, 16).expect("valid hex"))
+            .collect()
+    }
+
+    /// Compares both transports byte for byte, Bluetooth with sequence 5
+    fn assert_reports(report: &OutputReport, usb: &str, bluetooth: &str) {
+        assert_eq!(report.to_usb(), hex(usb), "USB report");
+        assert_eq!(report.to_bluetooth(5), hex(bluetooth), "Bluetooth report");
+    }
+
+    fn right(effect: TriggerEffect) -> OutputReport {
+        OutputReport::trigger(&Trigger {
+            side: "right".to_string(),
+            effect,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn uevent_bluetooth() {
+        let uevent = "DRIVER=playstation\nHID_ID=0005:0000054C:00000CE6\n\
+                      HID_NAME=DualSense Wireless Controller\nHID_UNIQ=84:30:95:aa:bb:cc\n";
+        assert_eq!(
+            parse_uevent(uevent),
+            Some((Connection::Bluetooth, Some("84:30:95:aa:bb:cc".to_string())))
+        );
+    }
+
+    #[test]
+    fn uevent_without_serial() {
+        let edge = "HID_ID=0003:0000054C:00000DF2\nHID_UNIQ=\n";
+        assert_eq!(parse_uevent(edge), Some((Connection::Usb, None)));
+        let usb = "HID_ID=0003:0000054C:00000CE6\n";
+        assert_eq!(parse_uevent(usb), Some((Connection::Usb, None)));
+    }
+
+    #[test]
+    fn uevent_other_devices() {
+        // DualShock 4
+        assert_eq!(parse_uevent("HID_ID=0003:0000054C:000009CC\n"), None);
+        assert_eq!(parse_uevent("HID_ID=0003:0000046D:00000CE6\n"), None);
+        assert_eq!(parse_uevent("HID_UNIQ=84:30:95:aa:bb:cc\n"), None);
+    }
+
+    #[test]
+    fn report_sizes() {
+        let report = OutputReport::player_leds(1);
+        assert_eq!(report.to_usb().len(), OUTPUT_REPORT_USB_SIZE);
+        assert_eq!(report.to_bluetooth(0).len(), OUTPUT_REPORT_BT_SIZE);
+    }
+
+    #[test]
+    fn bluetooth_sequence_wraps() {
+        let report = OutputReport::player_leds(1);
+        assert_eq!(report.to_bluetooth(0x15)[1], 0x50);
+    }
+
+    #[test]
+    fn trigger_rejects_unknown_side() {
+        let trigger = Trigger {
+            side: "middle".to_string(),
+            effect: TriggerEffect::Off,
+        };
+        assert!(OutputReport::trigger(&trigger).is_err());
+    }
+
+    #[test]
+    fn lightbar_colour() {
+        assert_reports(
+            &OutputReport::lightbar_colour(255, 128, 0, 255),
+            "
+            02 00 04 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 ff 80 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 00 04 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 ff
+            80 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 9e 98 d8 56",
+        );
+    }
+
+    #[test]
+    fn lightbar_colour_dimmed() {
+        assert_reports(
+            &OutputReport::lightbar_colour(255, 128, 0, 128),
+            "
+            02 00 04 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 80 40 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 00 04 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 80
+            40 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 5c f1 ae 90",
+        );
+    }
+
+    #[test]
+    fn lightbar_off() {
+        assert_reports(
+            &OutputReport::lightbar(false),
+            "
+            02 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 02 00 00 02 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 02 00 00 02 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 0f c6 8f 61",
+        );
+    }
+
+    #[test]
+    fn player_leds() {
+        assert_reports(
+            &OutputReport::player_leds(3),
+            "
+            02 00 10 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 15 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 00 10 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 15 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 37 6c a2 fe",
+        );
+    }
+
+    #[test]
+    fn trigger_off() {
+        assert_reports(
+            &right(TriggerEffect::Off),
+            "
+            02 04 00 00 00 00 00 00 00 00 00 05 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 04 00 00 00 00 00 00 00 00 00 05 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 59 29 27 fb",
+        );
+    }
+
+    #[test]
+    fn trigger_feedback() {
+        assert_reports(
+            &right(TriggerEffect::Feedback {
+                position: 3,
+                strength: 6,
+            }),
+            "
+            02 04 00 00 00 00 00 00 00 00 00 21 f8 03 00 da
+            b6 2d 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 04 00 00 00 00 00 00 00 00 00 21 f8 03
+            00 da b6 2d 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 43 ad b9 d7",
+        );
+    }
+
+    #[test]
+    fn trigger_without_force_is_off() {
+        let effects = [
+            TriggerEffect::Feedback {
+                position: 3,
+                strength: 0,
+            },
+            TriggerEffect::Vibration {
+                position: 2,
+                amplitude: 0,
+                frequency: 30,
+            },
+        ];
+        for effect in effects {
+            assert_reports(
+                &right(effect),
+                "
+                02 04 00 00 00 00 00 00 00 00 00 05 00 00 00 00
+                00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+                00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+                00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+                "
+                31 50 10 04 00 00 00 00 00 00 00 00 00 05 00 00
+                00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+                00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+                00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+                00 00 00 00 00 00 00 00 00 00 59 29 27 fb",
+            );
+        }
+    }
+
+    #[test]
+    fn trigger_weapon() {
+        assert_reports(
+            &right(TriggerEffect::Weapon {
+                start: 2,
+                stop: 7,
+                strength: 5,
+            }),
+            "
+            02 04 00 00 00 00 00 00 00 00 00 25 84 00 04 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 04 00 00 00 00 00 00 00 00 00 25 84 00
+            04 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 9b 35 a8 f6",
+        );
+    }
+
+    #[test]
+    fn trigger_bow() {
+        assert_reports(
+            &right(TriggerEffect::Bow {
+                start: 1,
+                stop: 4,
+                strength: 6,
+                snapforce: 3,
+            }),
+            "
+            02 04 00 00 00 00 00 00 00 00 00 22 12 00 15 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 04 00 00 00 00 00 00 00 00 00 22 12 00
+            15 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 40 4f 6d 70",
+        );
+    }
+
+    #[test]
+    fn trigger_galloping() {
+        assert_reports(
+            &right(TriggerEffect::Galloping {
+                start: 0,
+                stop: 9,
+                first_foot: 2,
+                second_foot: 5,
+                frequency: 10,
+            }),
+            "
+            02 04 00 00 00 00 00 00 00 00 00 23 01 02 15 0a
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 04 00 00 00 00 00 00 00 00 00 23 01 02
+            15 0a 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 9e 7b 1d b4",
+        );
+    }
+
+    #[test]
+    fn trigger_machine() {
+        assert_reports(
+            &right(TriggerEffect::Machine {
+                start: 1,
+                stop: 9,
+                strength_a: 3,
+                strength_b: 5,
+                frequency: 8,
+                period: 12,
+            }),
+            "
+            02 04 00 00 00 00 00 00 00 00 00 27 02 02 2b 08
+            0c 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 04 00 00 00 00 00 00 00 00 00 27 02 02
+            2b 08 0c 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 75 cd 46 70",
+        );
+    }
+
+    #[test]
+    fn trigger_vibration() {
+        assert_reports(
+            &right(TriggerEffect::Vibration {
+                position: 2,
+                amplitude: 5,
+                frequency: 30,
+            }),
+            "
+            02 04 00 00 00 00 00 00 00 00 00 26 fc 03 00 49
+            92 24 00 00 1e 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 04 00 00 00 00 00 00 00 00 00 26 fc 03
+            00 49 92 24 00 00 1e 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 98 0b 08 9d",
+        );
+    }
+
+    #[test]
+    fn trigger_feedback_raw() {
+        assert_reports(
+            &right(TriggerEffect::FeedbackRaw {
+                strength: [0, 1, 2, 3, 4, 5, 6, 7, 8, 8],
+            }),
+            "
+            02 04 00 00 00 00 00 00 00 00 00 21 fe 03 40 34
+            d6 3f 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 04 00 00 00 00 00 00 00 00 00 21 fe 03
+            40 34 d6 3f 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 8a 41 e5 4d",
+        );
+    }
+
+    #[test]
+    fn trigger_vibration_raw() {
+        assert_reports(
+            &right(TriggerEffect::VibrationRaw {
+                amplitude: [8, 0, 8, 0, 8, 0, 8, 0, 8, 0],
+                frequency: 40,
+            }),
+            "
+            02 04 00 00 00 00 00 00 00 00 00 26 55 01 c7 71
+            1c 07 00 00 28 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 04 00 00 00 00 00 00 00 00 00 26 55 01
+            c7 71 1c 07 00 00 28 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 f2 21 8d 04",
+        );
+    }
+
+    #[test]
+    fn trigger_left_mode() {
+        assert_reports(
+            &OutputReport::trigger(&Trigger {
+                side: "left".to_string(),
+                effect: TriggerEffect::Mode {
+                    params: vec!["0x21".to_string(), "255".to_string(), "3".to_string()],
+                },
+            })
+            .unwrap(),
+            "
+            02 08 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 21 ff 03 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 08 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 21 ff 03 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 a9 c2 e2 55",
+        );
+    }
+
+    #[test]
+    fn trigger_both_feedback() {
+        assert_reports(
+            &OutputReport::trigger(&Trigger {
+                side: "both".to_string(),
+                effect: TriggerEffect::Feedback {
+                    position: 3,
+                    strength: 6,
+                },
+            })
+            .unwrap(),
+            "
+            02 0c 00 00 00 00 00 00 00 00 00 21 f8 03 00 da
+            b6 2d 00 00 00 00 21 f8 03 00 da b6 2d 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            "
+            31 50 10 0c 00 00 00 00 00 00 00 00 00 21 f8 03
+            00 da b6 2d 00 00 00 00 21 f8 03 00 da b6 2d 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 00 00 00 00 00 00 00 00 00 20 51 eb de",
+        );
+    }
+}
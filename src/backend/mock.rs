@@ -0,0 +1,264 @@
+use log::info;
+use std::io::{self, BufRead, Cursor, Read};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::backend::{ControllerBackend, MonitorStream};
+use crate::error::DualsenseError;
+use crate::structs::{
+    BatteryStatus, ChargingState, ConnectedDevice, Connection, DeviceInfo, Speaker, Trigger,
+};
+
+/// Backend that never touches hardware and records every call instead
+///
+/// Calls are stored in `dualsensectl` argument form, e.g. `volume 120`,
+/// prefixed with `-d SERIAL` when targeting a device. Backends made by
+/// `for_device` share one call log
+pub struct MockBackend {
+    calls: Arc<Mutex<Vec<String>>>,
+    devices: Vec<ConnectedDevice>,
+    device: Option<String>,
+    battery: BatteryStatus,
+    failures: Vec<Failure>,
+    monitor_fixture: String,
+}
+
+/// Calls starting with `prefix` fail, as unsupported or as a failed command
+#[derive(Clone)]
+struct Failure {
+    prefix: String,
+    unsupported: bool,
+}
+
+/// Recorded `dualsensectl monitor` session played back by `monitor()`
+const MONITOR_FIXTURE: &str = include_str!("../../resources/monitor-fixture.txt");
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self {
+            calls: Arc::new(Mutex::new(Vec::new())),
+            devices: Vec::new(),
+            device: None,
+            battery: BatteryStatus {
+                level: 100,
+                state: ChargingState::Discharging,
+            },
+            failures: Vec::new(),
+            monitor_fixture: MONITOR_FIXTURE.to_string(),
+        }
+    }
+
+    /// Adds a fake Bluetooth pad to `list_devices()`
+    pub fn with_device(mut self, serial: &str) -> Self {
+        self.devices.push(ConnectedDevice {
+            serial: serial.to_string(),
+            connection: Connection::Bluetooth,
+        });
+        self
+    }
+
+    /// Battery status reported by `battery()`
+    pub fn with_battery(mut self, level: u8, state: ChargingState) -> Self {
+        self.battery = BatteryStatus { level, state };
+        self
+    }
+
+    /// Lines `monitor()` plays back instead of the bundled recording
+    pub fn with_monitor_fixture(mut self, fixture: &str) -> Self {
+        self.monitor_fixture = fixture.to_string();
+        self
+    }
+
+    /// Makes every call fail after being recorded
+    pub fn failing(self) -> Self {
+        self.failing_on("")
+    }
+
+    /// Makes calls starting with `prefix`, e.g. `volume`, fail after being
+    /// recorded
+    pub fn failing_on(mut self, prefix: &str) -> Self {
+        self.failures.push(Failure {
+            prefix: prefix.to_string(),
+            unsupported: false,
+        });
+        self
+    }
+
+    /// Makes calls starting with `prefix` fail as unsupported, like a
+    /// `dualsensectl` without that command
+    pub fn unsupported(mut self, prefix: &str) -> Self {
+        self.failures.push(Failure {
+            prefix: prefix.to_string(),
+            unsupported: true,
+        });
+        self
+    }
+
+    /// Everything sent so far, oldest first
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    fn record(&self, call: String) -> Result<(), DualsenseError> {
+        let failure = self
+            .failures
+            .iter()
+            .find(|failure| call.starts_with(&failure.prefix))
+            .map(|failure| failure.unsupported);
+        let call = match &self.device {
+            Some(device) => format!("-d {device} {call}"),
+            None => call,
+        };
+        info!("Mock backend: {}", call);
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.push(call.clone());
+        }
+
+        match failure {
+            None => Ok(()),
+            Some(true) => Err(DualsenseError::Unsupported(call)),
+            Some(false) => Err(DualsenseError::CommandFailed(format!(
+                "Mock failure: {call}"
+            ))),
+        }
+    }
+}
+
+/// Plays the fixture back, then waits like a live monitor until stopped
+struct MockMonitor {
+    fixture: Cursor<Vec<u8>>,
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Read for MockMonitor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut available = self.fill_buf()?;
+        let amount = available.read(buf)?;
+        self.consume(amount);
+        Ok(amount)
+    }
+}
+
+impl BufRead for MockMonitor {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.fixture.position() as usize >= self.fixture.get_ref().len() {
+            let (stopped, changed) = &*self.stopped;
+            let mut stopped = stopped.lock().unwrap_or_else(|e| e.into_inner());
+            while !*stopped {
+                stopped = changed.wait(stopped).unwrap_or_else(|e| e.into_inner());
+            }
+        }
+        self.fixture.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.fixture.consume(amount);
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ControllerBackend for MockBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn list_devices(&self) -> Result<Vec<ConnectedDevice>, DualsenseError> {
+        Ok(self.devices.clone())
+    }
+
+    fn for_device(&self, serial: &str) -> Arc<dyn ControllerBackend> {
+        Arc::new(Self {
+            calls: Arc::clone(&self.calls),
+            devices: self.devices.clone(),
+            device: Some(serial.to_string()),
+            battery: self.battery,
+            failures: self.failures.clone(),
+            monitor_fixture: self.monitor_fixture.clone(),
+        })
+    }
+
+    fn set_lightbar(&self, enabled: bool) -> Result<(), DualsenseError> {
+        self.record(format!("lightbar {}", if enabled { "on" } else { "off" }))
+    }
+
+    fn set_lightbar_colour(
+        &self,
+        red: u8,
+        green: u8,
+        blue: u8,
+        brightness: u8,
+    ) -> Result<(), DualsenseError> {
+        self.record(format!("lightbar {red} {green} {blue} {brightness}"))
+    }
+
+    fn set_player_leds(&self, amount: u8) -> Result<(), DualsenseError> {
+        self.record(format!("player-leds {amount}"))
+    }
+
+    fn set_microphone(&self, enabled: bool) -> Result<(), DualsenseError> {
+        self.record(format!("microphone {}", if enabled { "on" } else { "off" }))
+    }
+
+    fn set_microphone_led(&self, enabled: bool) -> Result<(), DualsenseError> {
+        self.record(format!(
+            "microphone-led {}",
+            if enabled { "on" } else { "off" }
+        ))
+    }
+
+    fn set_speaker(&self, speaker: &Speaker) -> Result<(), DualsenseError> {
+        self.record(format!("speaker {speaker:?}").to_lowercase())
+    }
+
+    fn set_volume(&self, volume: u8) -> Result<(), DualsenseError> {
+        self.record(format!("volume {volume}"))
+    }
+
+    fn set_attenuation(&self, rumble: u8, trigger: u8) -> Result<(), DualsenseError> {
+        self.record(format!("attenuation {rumble} {trigger}"))
+    }
+
+    fn set_trigger(&self, trigger: &Trigger) -> Result<(), DualsenseError> {
+        self.record(trigger.to_command())
+    }
+
+    fn battery(&self) -> Result<BatteryStatus, DualsenseError> {
+        self.record("battery".to_string())?;
+        Ok(self.battery)
+    }
+
+    fn power_off(&self) -> Result<(), DualsenseError> {
+        self.record("power-off".to_string())
+    }
+
+    fn info(&self) -> Result<DeviceInfo, DualsenseError> {
+        self.record("info".to_string())?;
+        Ok(DeviceInfo {
+            serial: self.device.clone(),
+            connection: Some(Connection::Bluetooth),
+            hardware_version: Some("mock".to_string()),
+            firmware_version: Some("mock".to_string()),
+            ..DeviceInfo::default()
+        })
+    }
+
+    fn monitor(&self) -> Result<MonitorStream, DualsenseError> {
+        self.record("monitor".to_string())?;
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        Ok(MonitorStream {
+            output: Box::new(MockMonitor {
+                fixture: Cursor::new(self.monitor_fixture.clone().into_bytes()),
+                stopped: Arc::clone(&stopped),
+            }),
+            stop: Box::new(move || {
+                let (stopped, changed) = &*stopped;
+                *stopped.lock().unwrap_or_else(|e| e.into_inner()) = true;
+                changed.notify_all();
+            }),
+        })
+    }
+}
@@ -0,0 +1,151 @@
+use log::{error, info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::alerts::BatteryAlert;
+use crate::backend::hidraw::HidrawDevice;
+use crate::devices::{DeviceHandle, DeviceRegistry};
+use crate::error::DualsenseError;
+use crate::hotplug::{settled_serial, spawn_watcher, HotplugEvent, RECONNECT_DELAY};
+use crate::save::{load_default_profile, AppPaths};
+use crate::structs::{Controller, Settings};
+use crate::worker::{restore_device, DeviceCommand, Output, Worker};
+
+/// How often the daemon wakes up to check for signals, hotplug and battery
+const TICK: Duration = Duration::from_millis(250);
+
+/// The unit shipped in resources/, `ExecStart` is rewritten per install
+const SERVICE_TEMPLATE: &str = include_str!("../resources/dualsensectl-gui.service");
+
+/// systemd user unit that runs `exe` as a daemon
+pub fn service_file(exe: &Path) -> String {
+    SERVICE_TEMPLATE
+        .lines()
+        .map(|line| match line.strip_prefix("ExecStart=") {
+            Some(_) => format!("ExecStart={} --daemon\n", exe.display()),
+            None => format!("{}\n", line),
+        })
+        .collect()
+}
+
+fn restore(worker: &Worker, device: &DeviceHandle, profile: Option<Controller>) {
+    restore_device(device, profile, |command, done| {
+        done(worker.execute(device, command))
+    });
+}
+
+/// Reads every connected pad's battery and logs threshold crossings
+fn poll_batteries(registry: &DeviceRegistry, worker: &Worker, settings: &Settings) {
+    for device in registry.connected_handles() {
+        let status = match worker.execute(&device, DeviceCommand::Battery).result {
+            Ok(Output::Battery(status)) => status,
+            Ok(_) => continue,
+            Err(err) => {
+                error!("Failed to read battery of {}: {}", device.label(), err);
+                continue;
+            }
+        };
+
+        let alert = device.battery_alerts.lock().ok().and_then(|mut alerts| {
+            alerts.update(
+                &status,
+                &settings.low_battery_thresholds,
+                settings.notify_when_charged,
+            )
+        });
+        match alert {
+            Some(BatteryAlert::Low(threshold)) => warn!(
+                "{} battery below {}% ({}%)",
+                device.label(),
+                threshold,
+                status.level
+            ),
+            Some(BatteryAlert::FullyCharged) => info!("{} fully charged", device.label()),
+            None => {}
+        }
+    }
+}
+
+/// Keeps pads at their saved state without the window open
+///
+/// Restores every pad on start and whenever one reconnects, polls the
+/// battery and saves state on SIGTERM/SIGINT
+///
+/// `main` starts the same services as for the GUI first, so D-Bus, the RPC
+/// socket, the enabled DSX/OSC/HTTP servers and automatic profile switching
+/// all keep working without the window
+pub fn run(
+    registry: &DeviceRegistry,
+    worker: &Worker,
+    settings: &Arc<Mutex<Settings>>,
+    app_paths: &Arc<AppPaths>,
+) -> Result<(), DualsenseError> {
+    let terminate = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        signal_hook::flag::register(signal, Arc::clone(&terminate))?;
+    }
+
+    info!("Daemon started, config in {}", app_paths.config.display());
+
+    let default_profile = settings
+        .lock()
+        .ok()
+        .and_then(|s| load_default_profile(&s, app_paths));
+    if let Some((name, _)) = &default_profile {
+        info!("Applying default profile '{}'", name);
+    }
+    for device in registry.devices() {
+        restore(
+            worker,
+            &registry.handle(Some(&device.serial)),
+            default_profile.as_ref().map(|(_, profile)| profile.clone()),
+        );
+    }
+
+    let (sender, hotplug) = async_channel::unbounded();
+    if let Err(err) = spawn_watcher(sender) {
+        error!("Hotplug detection unavailable: {}", err);
+    }
+
+    // Pads that showed up and get their state once they've settled
+    let mut reconnected: Vec<(Instant, HidrawDevice)> = Vec::new();
+    let mut last_battery_poll: Option<Instant> = None;
+
+    while !terminate.load(Ordering::SeqCst) {
+        while let Ok(event) = hotplug.try_recv() {
+            if let HotplugEvent::Added(device) = event {
+                reconnected.push((Instant::now() + RECONNECT_DELAY, device));
+            }
+        }
+
+        let now = Instant::now();
+        let (due, waiting) = reconnected.into_iter().partition(|(due, _)| *due <= now);
+        reconnected = waiting;
+        for (_, device) in due {
+            match settled_serial(&device) {
+                Some(serial) => restore(worker, &registry.handle(Some(&serial)), None),
+                None => warn!(
+                    "{} has no serial, not restoring its state",
+                    device.node.display()
+                ),
+            }
+        }
+
+        let settings = settings.lock().map(|s| s.clone()).unwrap_or_default();
+        let interval = Duration::from_secs(settings.battery_poll_interval.max(1).into());
+        if last_battery_poll.is_none_or(|last| last.elapsed() >= interval) {
+            last_battery_poll = Some(Instant::now());
+            poll_batteries(registry, worker, &settings);
+        }
+
+        thread::sleep(TICK);
+    }
+
+    info!("Daemon stopping, saving state.");
+    registry.save_all();
+    Ok(())
+}
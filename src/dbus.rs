@@ -0,0 +1,378 @@
+use log::{error, info};
+use std::sync::Arc;
+use zbus::object_server::SignalEmitter;
+use zbus::{blocking, fdo, interface};
+
+use crate::devices::{DeviceHandle, DeviceRegistry};
+use crate::error::DualsenseError;
+use crate::save::{load_profile, AppPaths};
+use crate::structs::Trigger;
+use crate::worker::{DeviceCommand, DeviceEvent, Output, Worker};
+
+/// Well-known name of the service
+///
+/// Lives under `APP_ID` rather than on it, GApplication already owns
+/// `APP_ID` itself while the window is open
+pub const BUS_NAME: &str = "org.gtk_rs.Dualsensectl.Controller";
+pub const OBJECT_PATH: &str = "/org/gtk_rs/Dualsensectl/Controller";
+
+fn to_fdo(err: DualsenseError) -> fdo::Error {
+    match err {
+        DualsenseError::InvalidArgument(msg) => fdo::Error::InvalidArgs(msg),
+        DualsenseError::Unsupported(what) => fdo::Error::NotSupported(what),
+        err => fdo::Error::Failed(err.to_string()),
+    }
+}
+
+/// Controller methods on the session bus
+///
+/// Every method takes the pad's serial first, empty means the first
+/// connected one
+struct ControllerService {
+    registry: Arc<DeviceRegistry>,
+    worker: Worker,
+    app_paths: Arc<AppPaths>,
+}
+
+impl ControllerService {
+    fn device(&self, serial: &str) -> DeviceHandle {
+        if serial.is_empty() {
+            self.registry.connected_handles().remove(0)
+        } else {
+            self.registry.handle(Some(serial))
+        }
+    }
+
+    async fn run(&self, serial: &str, command: DeviceCommand) -> fdo::Result<Output> {
+        let device = self.device(serial);
+        let reply = self
+            .worker
+            .send(&device, command)
+            .recv()
+            .await
+            .map_err(|_| fdo::Error::Failed("Device worker is gone".to_string()))?;
+        reply.result.map_err(to_fdo)
+    }
+}
+
+#[interface(name = "org.gtk_rs.Dualsensectl.Controller")]
+impl ControllerService {
+    /// Lightbar colour and brightness, 0-255, turns the lightbar on
+    async fn set_lightbar(
+        &self,
+        serial: &str,
+        red: u8,
+        green: u8,
+        blue: u8,
+        brightness: u8,
+    ) -> fdo::Result<()> {
+        let colour = vec![red, green, blue, brightness];
+        self.run(serial, DeviceCommand::LightbarColour(colour))
+            .await
+            .map(|_| ())
+    }
+
+    /// Same arguments as `dualsensectl trigger SIDE MODE [PARAMS...]`
+    async fn set_trigger(
+        &self,
+        serial: &str,
+        side: &str,
+        mode: &str,
+        params: Vec<String>,
+    ) -> fdo::Result<()> {
+        let trigger = Trigger::parse(side, mode, &params).map_err(to_fdo)?;
+        self.run(serial, DeviceCommand::Trigger(trigger))
+            .await
+            .map(|_| ())
+    }
+
+    /// Pushes a saved profile, returns how each field went
+    async fn apply_profile(&self, serial: &str, name: &str) -> fdo::Result<String> {
+        let profile = load_profile(name, &self.app_paths)
+            .map_err(|err| fdo::Error::InvalidArgs(format!("Profile '{name}': {err}")))?;
+        match self.run(serial, DeviceCommand::Apply(profile)).await? {
+            Output::Applied(report) if !report.succeeded() => {
+                Err(fdo::Error::Failed(report.to_string()))
+            }
+            Output::Applied(report) => Ok(report.to_string()),
+            _ => Ok(String::new()),
+        }
+    }
+
+    /// The pad's state as JSON, same as `get state --json`
+    async fn get_state(&self, serial: &str) -> fdo::Result<String> {
+        let state = self
+            .device(serial)
+            .controller
+            .lock()
+            .map(|c| c.clone())
+            .unwrap_or_default();
+        serde_json::to_string(&state).map_err(|err| fdo::Error::Failed(err.to_string()))
+    }
+
+    /// Battery level in percent and charging state
+    async fn get_battery(&self, serial: &str) -> fdo::Result<(u8, String)> {
+        match self.run(serial, DeviceCommand::Battery).await? {
+            Output::Battery(status) => Ok((status.level, status.state.to_string())),
+            _ => Err(fdo::Error::Failed("No battery reading".to_string())),
+        }
+    }
+
+    #[zbus(signal)]
+    async fn battery_changed(
+        emitter: &SignalEmitter<'_>,
+        serial: &str,
+        level: u8,
+        state: &str,
+    ) -> zbus::Result<()>;
+
+    /// `state` is JSON like `GetState`
+    #[zbus(signal)]
+    async fn state_changed(
+        emitter: &SignalEmitter<'_>,
+        serial: &str,
+        state: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// Publishes the controller API on the session bus
+///
+/// Uses `DBUS_SESSION_BUS_ADDRESS`, so pointing that at a private
+/// `dbus-daemon --session --print-address` is enough to test against.
+/// The service runs until the returned connection is dropped
+pub fn serve(
+    registry: Arc<DeviceRegistry>,
+    worker: Worker,
+    app_paths: Arc<AppPaths>,
+) -> zbus::Result<blocking::Connection> {
+    serve_on(
+        blocking::connection::Builder::session()?,
+        registry,
+        worker,
+        app_paths,
+    )
+}
+
+/// `serve` on whichever bus `builder` connects to
+fn serve_on(
+    builder: blocking::connection::Builder,
+    registry: Arc<DeviceRegistry>,
+    worker: Worker,
+    app_paths: Arc<AppPaths>,
+) -> zbus::Result<blocking::Connection> {
+    let events = worker.subscribe();
+    let service = ControllerService {
+        registry,
+        worker,
+        app_paths,
+    };
+
+    let connection = builder
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, service)?
+        .build()?;
+
+    let emitter = SignalEmitter::new(connection.inner(), OBJECT_PATH)?.into_owned();
+    connection
+        .inner()
+        .executor()
+        .spawn(
+            async move {
+                while let Ok(event) = events.recv().await {
+                    let result = match event {
+                        DeviceEvent::Battery { serial, status } => {
+                            ControllerService::battery_changed(
+                                &emitter,
+                                serial.as_deref().unwrap_or_default(),
+                                status.level,
+                                &status.state.to_string(),
+                            )
+                            .await
+                        }
+                        DeviceEvent::StateChanged { serial, state } => {
+                            let state = serde_json::to_string(&state).unwrap_or_default();
+                            ControllerService::state_changed(
+                                &emitter,
+                                serial.as_deref().unwrap_or_default(),
+                                &state,
+                            )
+                            .await
+                        }
+                    };
+                    if let Err(err) = result {
+                        error!("Failed to emit D-Bus signal: {}", err);
+                    }
+                }
+            },
+            "dbus-signals",
+        )
+        .detach();
+
+    info!("D-Bus service available as {}", BUS_NAME);
+    Ok(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use crate::devices::{mock_call, MockDevices, MOCK_SERIAL as SERIAL};
+    use crate::save::save_profile;
+    use crate::structs::{ChargingState, Controller};
+    use serde_json::Value;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    const INTERFACE: &str = "org.gtk_rs.Dualsensectl.Controller";
+
+    /// A private session bus, killed on drop
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--print-address", "--nofork"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("dbus-daemon is needed for this test");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Self {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+
+        fn connect(&self) -> blocking::connection::Builder<'_> {
+            blocking::connection::Builder::address(self.address.as_str()).unwrap()
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// Collects `signal` on a thread so a missing one fails instead of hanging
+    fn signals(proxy: &blocking::Proxy<'static>, signal: &'static str) -> mpsc::Receiver<Value> {
+        let mut iterator = proxy.receive_signal(signal).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for message in &mut iterator {
+                let body: Value = match signal {
+                    "StateChanged" => {
+                        let (serial, state): (String, String) =
+                            message.body().deserialize().unwrap();
+                        serde_json::json!({
+                            "serial": serial,
+                            "state": serde_json::from_str::<Value>(&state).unwrap(),
+                        })
+                    }
+                    _ => {
+                        let (serial, level, state): (String, u8, String) =
+                            message.body().deserialize().unwrap();
+                        serde_json::json!({ "serial": serial, "level": level, "state": state })
+                    }
+                };
+                if sender.send(body).is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon, run with --ignored"]
+    fn methods_and_signals() {
+        let bus = Bus::start();
+
+        let devices = MockDevices::with_backend(
+            "dbus",
+            MockBackend::new()
+                .with_device(SERIAL)
+                .with_battery(40, ChargingState::Charging),
+        );
+        let _service = serve_on(
+            bus.connect(),
+            Arc::clone(&devices.registry),
+            devices.worker.clone(),
+            Arc::clone(&devices.app_paths),
+        )
+        .unwrap();
+
+        let client = bus.connect().build().unwrap();
+        let proxy = blocking::Proxy::new(&client, BUS_NAME, OBJECT_PATH, INTERFACE).unwrap();
+        let state_changed = signals(&proxy, "StateChanged");
+        let battery_changed = signals(&proxy, "BatteryChanged");
+        let timeout = Duration::from_secs(5);
+
+        proxy
+            .call::<_, _, ()>("SetLightbar", &(SERIAL, 10u8, 20u8, 30u8, 255u8))
+            .unwrap();
+        let signal = state_changed.recv_timeout(timeout).unwrap();
+        assert_eq!(signal["serial"], SERIAL);
+        assert_eq!(
+            signal["state"]["lightbar_colour"],
+            serde_json::json!([10, 20, 30, 255])
+        );
+
+        proxy
+            .call::<_, _, ()>("SetTrigger", &(SERIAL, "left", "feedback", vec!["3", "6"]))
+            .unwrap();
+        let signal = state_changed.recv_timeout(timeout).unwrap();
+        assert_eq!(signal["state"]["trigger"]["side"], "left");
+
+        let err = proxy
+            .call::<_, _, ()>("SetTrigger", &(SERIAL, "left", "feedback", vec!["x"]))
+            .unwrap_err();
+        assert!(matches!(err, zbus::Error::MethodError(ref name, _, _)
+            if name.as_str() == "org.freedesktop.DBus.Error.InvalidArgs"));
+
+        let state: String = proxy.call("GetState", &(SERIAL,)).unwrap();
+        let state: Value = serde_json::from_str(&state).unwrap();
+        assert_eq!(
+            state["lightbar_colour"],
+            serde_json::json!([10, 20, 30, 255])
+        );
+
+        let (level, charging): (u8, String) = proxy.call("GetBattery", &(SERIAL,)).unwrap();
+        assert_eq!((level, charging.as_str()), (40, "charging"));
+        let signal = battery_changed.recv_timeout(timeout).unwrap();
+        assert_eq!(signal["level"], 40);
+
+        let profile = Controller {
+            volume: 42,
+            ..Controller::default()
+        };
+        save_profile("quiet", &profile, &devices.app_paths).unwrap();
+        let report: String = proxy.call("ApplyProfile", &(SERIAL, "quiet")).unwrap();
+        assert!(!report.is_empty());
+        let signal = state_changed.recv_timeout(timeout).unwrap();
+        assert_eq!(signal["state"]["volume"], 42);
+        assert!(proxy
+            .call::<_, _, String>("ApplyProfile", &(SERIAL, "missing"))
+            .is_err());
+
+        let calls = devices.new_calls();
+        assert!(
+            calls.contains(&mock_call("lightbar 10 20 30 255")),
+            "{calls:?}"
+        );
+        assert!(
+            calls.contains(&mock_call("trigger left feedback 3 6")),
+            "{calls:?}"
+        );
+        assert!(calls.contains(&mock_call("volume 42")), "{calls:?}");
+    }
+}
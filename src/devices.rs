@@ -0,0 +1,232 @@
+use log::error;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::alerts::BatteryAlerts;
+use crate::backend::ControllerBackend;
+use crate::save::{load_device_state, save_device_state, AppPaths};
+use crate::structs::{ConnectedDevice, Controller};
+
+/// One pad: where its commands go and what we think it's set to
+///
+/// `serial` is `None` for the implicit "whatever is connected" device used
+/// when enumeration finds nothing
+#[derive(Clone)]
+pub struct DeviceHandle {
+    pub serial: Option<String>,
+    pub backend: Arc<dyn ControllerBackend>,
+    pub controller: Arc<Mutex<Controller>>,
+    /// Low/full battery alerts already sent for this pad
+    pub battery_alerts: Arc<Mutex<BatteryAlerts>>,
+}
+
+impl DeviceHandle {
+    pub fn label(&self) -> String {
+        self.serial
+            .clone()
+            .unwrap_or_else(|| "Default device".to_string())
+    }
+
+    /// Saves `controller` as this device's state
+    ///
+    /// Takes the state rather than locking so it can be called with the
+    /// lock already held
+    pub fn save(&self, controller: &Controller, app_paths: &Arc<AppPaths>) -> io::Result<()> {
+        save_device_state(controller, self.serial.as_deref(), app_paths)
+    }
+}
+
+/// How long `DeviceCache` reuses the list of connected pads
+const DEVICE_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Connected pads for servers that get many packets a second, enumerating
+/// for each one would be slow
+pub struct DeviceCache {
+    registry: Arc<DeviceRegistry>,
+    handles: Vec<DeviceHandle>,
+    connected: bool,
+    refreshed: Option<Instant>,
+}
+
+impl DeviceCache {
+    pub fn new(registry: Arc<DeviceRegistry>) -> Self {
+        Self {
+            registry,
+            handles: Vec::new(),
+            connected: false,
+            refreshed: None,
+        }
+    }
+
+    /// Like `DeviceRegistry::connected_handles`, never empty
+    pub fn handles(&mut self) -> &[DeviceHandle] {
+        if self
+            .refreshed
+            .is_none_or(|at| at.elapsed() >= DEVICE_CACHE_TTL)
+        {
+            self.connected = !self.registry.devices().is_empty();
+            self.handles = self.registry.connected_handles();
+            self.refreshed = Some(Instant::now());
+        }
+        &self.handles
+    }
+
+    /// Whether any pad was listed at the last refresh
+    pub fn connected(&self) -> bool {
+        self.connected
+    }
+}
+
+/// Keeps one `DeviceHandle` per pad for the lifetime of the app
+pub struct DeviceRegistry {
+    backend: Arc<dyn ControllerBackend>,
+    app_paths: Arc<AppPaths>,
+    handles: Mutex<HashMap<Option<String>, DeviceHandle>>,
+}
+
+impl DeviceRegistry {
+    pub fn new(backend: Arc<dyn ControllerBackend>, app_paths: Arc<AppPaths>) -> Self {
+        Self {
+            backend,
+            app_paths,
+            handles: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Backend that isn't bound to a device
+    pub fn backend(&self) -> &Arc<dyn ControllerBackend> {
+        &self.backend
+    }
+
+    /// Currently connected pads, empty if enumeration fails
+    pub fn devices(&self) -> Vec<ConnectedDevice> {
+        self.backend.list_devices().unwrap_or_else(|err| {
+            error!("Failed to list devices: {}", err);
+            Vec::new()
+        })
+    }
+
+    /// Handle for `serial`, loading its saved state the first time
+    ///
+    /// An empty serial is the default device, it would otherwise end up as
+    /// `-d ""` and `state-.json`
+    pub fn handle(&self, serial: Option<&str>) -> DeviceHandle {
+        let serial = serial.filter(|serial| !serial.is_empty());
+        let key = serial.map(str::to_string);
+        let mut handles = self.handles.lock().unwrap_or_else(|e| e.into_inner());
+
+        handles
+            .entry(key)
+            .or_insert_with(|| DeviceHandle {
+                serial: serial.map(str::to_string),
+                backend: match serial {
+                    Some(serial) => self.backend.for_device(serial),
+                    None => Arc::clone(&self.backend),
+                },
+                controller: Arc::new(Mutex::new(load_device_state(serial, &self.app_paths))),
+                battery_alerts: Arc::new(Mutex::new(BatteryAlerts::default())),
+            })
+            .clone()
+    }
+
+    /// Handles for every connected pad, or the default one if none are listed
+    pub fn connected_handles(&self) -> Vec<DeviceHandle> {
+        let devices = self.devices();
+        if devices.is_empty() {
+            vec![self.handle(None)]
+        } else {
+            devices
+                .iter()
+                .map(|d| self.handle(Some(&d.serial)))
+                .collect()
+        }
+    }
+
+    /// Saves the state of every device we've touched
+    pub fn save_all(&self) {
+        let handles = self.handles.lock().unwrap_or_else(|e| e.into_inner());
+        for handle in handles.values() {
+            match handle.controller.lock() {
+                Ok(controller) => {
+                    if let Err(err) = handle.save(&controller, &self.app_paths) {
+                        error!("Failed to save controller state: {err}");
+                    }
+                }
+                Err(_) => error!("Failed to lock controller for saving state."),
+            }
+        }
+    }
+}
+
+/// Serial of the pad `MockDevices` sets up
+#[cfg(test)]
+pub const MOCK_SERIAL: &str = "84:30:95:aa:bb:cc";
+
+/// A mock pad with a registry and worker around it, for driving the
+/// servers in tests
+#[cfg(test)]
+pub struct MockDevices {
+    pub backend: Arc<crate::backend::mock::MockBackend>,
+    pub registry: Arc<DeviceRegistry>,
+    pub worker: crate::worker::Worker,
+    pub app_paths: Arc<AppPaths>,
+    /// Backend calls `new_calls` has returned already
+    seen: std::cell::Cell<usize>,
+}
+
+#[cfg(test)]
+impl MockDevices {
+    /// One pad called `MOCK_SERIAL`, files go to a temporary dir for `name`
+    pub fn new(name: &str) -> Self {
+        Self::with_backend(
+            name,
+            crate::backend::mock::MockBackend::new().with_device(MOCK_SERIAL),
+        )
+    }
+
+    pub fn with_backend(name: &str, backend: crate::backend::mock::MockBackend) -> Self {
+        let app_paths = Arc::new(AppPaths::temporary(name));
+        let backend = Arc::new(backend);
+        let registry = Arc::new(DeviceRegistry::new(
+            Arc::clone(&backend) as Arc<dyn ControllerBackend>,
+            Arc::clone(&app_paths),
+        ));
+        let worker = crate::worker::Worker::spawn(Arc::clone(&app_paths), 1000);
+        Self {
+            backend,
+            registry,
+            worker,
+            app_paths,
+            seen: std::cell::Cell::new(0),
+        }
+    }
+
+    pub fn device(&self) -> DeviceHandle {
+        self.registry.handle(Some(MOCK_SERIAL))
+    }
+
+    pub fn state(&self) -> Controller {
+        self.device().controller.lock().unwrap().clone()
+    }
+
+    /// Backend calls since the last time, once everything queued has run
+    pub fn new_calls(&self) -> Vec<String> {
+        // Anything that isn't coalesced runs after what's pending
+        self.worker
+            .execute(&self.device(), crate::worker::DeviceCommand::Info);
+        let calls = self.backend.calls();
+        calls[self.seen.replace(calls.len())..]
+            .iter()
+            .filter(|call| !call.ends_with(" info"))
+            .cloned()
+            .collect()
+    }
+}
+
+/// `command` as the mock records it for `MOCK_SERIAL`
+#[cfg(test)]
+pub fn mock_call(command: &str) -> String {
+    format!("-d {MOCK_SERIAL} {command}")
+}
@@ -0,0 +1,416 @@
+use log::{debug, error, info};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+
+use crate::devices::{DeviceCache, DeviceRegistry};
+use crate::error::DualsenseError;
+use crate::structs::{Trigger, TriggerEffect};
+use crate::worker::{DeviceCommand, Worker};
+
+/// Port DSX listens on, what mods send to by default
+pub const DEFAULT_PORT: u16 = 6969;
+
+const INSTRUCTION_TRIGGER_UPDATE: i64 = 1;
+const INSTRUCTION_RGB_UPDATE: i64 = 2;
+const INSTRUCTION_PLAYER_LED: i64 = 3;
+const INSTRUCTION_TRIGGER_THRESHOLD: i64 = 4;
+const INSTRUCTION_MIC_LED: i64 = 5;
+const INSTRUCTION_PLAYER_LED_NEW_REVISION: i64 = 6;
+const INSTRUCTION_RESET_TO_USER_SETTINGS: i64 = 7;
+
+const TRIGGER_LEFT: i64 = 1;
+const TRIGGER_RIGHT: i64 = 2;
+
+const MIC_LED_OFF: i64 = 2;
+const PLAYER_LED_ALL_OFF: i64 = 5;
+
+/// `{"instructions":[{"type":1,"parameters":[0,2,13,3,6]}]}`
+#[derive(Deserialize, Debug)]
+struct Packet {
+    instructions: Vec<Instruction>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Instruction {
+    #[serde(rename = "type")]
+    kind: i64,
+    #[serde(default)]
+    parameters: Vec<Value>,
+}
+
+/// Mods send numbers, bools and sometimes numeric strings
+fn int(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_i64().or_else(|| n.as_f64().map(|f| f as i64)),
+        Value::Bool(b) => Some(i64::from(*b)),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Parameter `index` clamped to a byte, 0 if missing
+fn byte(params: &[i64], index: usize) -> u8 {
+    params
+        .get(index)
+        .map(|p| (*p).clamp(0, 255) as u8)
+        .unwrap_or(0)
+}
+
+/// Maps a DSX `TriggerMode` and its parameters onto our effects
+///
+/// The fixed presets have no parameters of their own, they're approximated
+/// with a feedback or vibration effect
+fn trigger_effect(mode: i64, params: &[i64]) -> Result<TriggerEffect, DualsenseError> {
+    let p = |index| byte(params, index);
+    let feedback = |strength| TriggerEffect::Feedback {
+        position: 0,
+        strength,
+    };
+    let vibration = |amplitude, frequency| TriggerEffect::Vibration {
+        position: 0,
+        amplitude,
+        frequency,
+    };
+
+    Ok(match mode {
+        // Normal
+        0 => TriggerEffect::Off,
+        // GameCube, a click near the end of the pull
+        1 => TriggerEffect::Weapon {
+            start: 5,
+            stop: 7,
+            strength: 8,
+        },
+        2 => feedback(1),                                     // VerySoft
+        3 => feedback(3),                                     // Soft
+        4 => feedback(6),                                     // Hard
+        5 => feedback(7),                                     // VeryHard
+        6 => feedback(8),                                     // Hardest
+        7 => feedback(8),                                     // Rigid
+        8 => vibration(8, if p(0) == 0 { 40 } else { p(0) }), // VibrateTrigger
+        9 => vibration(4, 20),                                // Choppy
+        10 => feedback(4),                                    // Medium
+        11 => vibration(8, 10),                               // VibrateTriggerPulse
+        // Resistance: start, force
+        13 => TriggerEffect::Feedback {
+            position: p(0),
+            strength: p(1),
+        },
+        // Bow: start, end, force, snap force
+        14 => TriggerEffect::Bow {
+            start: p(0),
+            stop: p(1),
+            strength: p(2),
+            snapforce: p(3),
+        },
+        // Galloping: start, end, first foot, second foot, frequency
+        15 => TriggerEffect::Galloping {
+            start: p(0),
+            stop: p(1),
+            first_foot: p(2),
+            second_foot: p(3),
+            frequency: p(4),
+        },
+        // SemiAutomaticGun: start, end, force
+        16 => TriggerEffect::Weapon {
+            start: p(0),
+            stop: p(1),
+            strength: p(2),
+        },
+        // AutomaticGun: start, strength, frequency
+        17 => TriggerEffect::Vibration {
+            position: p(0),
+            amplitude: p(1),
+            frequency: p(2),
+        },
+        // Machine: start, end, strength A, strength B, frequency, period
+        18 => TriggerEffect::Machine {
+            start: p(0),
+            stop: p(1),
+            strength_a: p(2),
+            strength_b: p(3),
+            frequency: p(4),
+            period: p(5),
+        },
+        // VIBRATE_TRIGGER_10Hz
+        19 => vibration(8, 10),
+        mode => {
+            return Err(DualsenseError::Unsupported(format!(
+                "DSX trigger mode {mode}"
+            )))
+        }
+    })
+}
+
+/// Turns one instruction into a command for controller `index`
+///
+/// `None` for instructions we have nothing to map onto
+fn instruction_command(
+    instruction: &Instruction,
+) -> Result<Option<(usize, DeviceCommand)>, DualsenseError> {
+    // Skipping one would shift the rest into the wrong place
+    let params = instruction
+        .parameters
+        .iter()
+        .map(int)
+        .collect::<Option<Vec<i64>>>()
+        .ok_or_else(|| {
+            DualsenseError::InvalidArgument(format!(
+                "DSX instruction {}: parameters {:?} aren't all numbers",
+                instruction.kind, instruction.parameters
+            ))
+        })?;
+    let index = params.first().map(|i| (*i).max(0) as usize).unwrap_or(0);
+    let p = |i| byte(&params, i);
+
+    let command = match instruction.kind {
+        INSTRUCTION_TRIGGER_UPDATE => {
+            let side = match params.get(1) {
+                Some(&TRIGGER_LEFT) => "left",
+                Some(&TRIGGER_RIGHT) => "right",
+                _ => {
+                    return Err(DualsenseError::InvalidArgument(format!(
+                        "DSX trigger: {:?}",
+                        params.get(1)
+                    )))
+                }
+            };
+            let mode = params.get(2).copied().unwrap_or(0);
+            DeviceCommand::Trigger(Trigger {
+                side: side.to_string(),
+                effect: trigger_effect(mode, params.get(3..).unwrap_or_default())?,
+            })
+        }
+        // index, R, G, B and optionally brightness
+        INSTRUCTION_RGB_UPDATE => {
+            let brightness = if params.len() > 4 { p(4) } else { 255 };
+            DeviceCommand::LightbarColour(vec![p(1), p(2), p(3), brightness])
+        }
+        // index and five on/off LEDs, we only do an amount
+        INSTRUCTION_PLAYER_LED => {
+            let lit = params.iter().skip(1).take(5).filter(|on| **on != 0).count();
+            DeviceCommand::PlayerLeds(lit as u8)
+        }
+        // index and One..Five or AllOff
+        INSTRUCTION_PLAYER_LED_NEW_REVISION => match params.get(1) {
+            Some(&PLAYER_LED_ALL_OFF) | None => DeviceCommand::PlayerLeds(0),
+            Some(leds) => DeviceCommand::PlayerLeds((*leds).clamp(0, 4) as u8 + 1),
+        },
+        // index and On, Pulse or Off, we can't pulse
+        INSTRUCTION_MIC_LED => DeviceCommand::MicrophoneLed(params.get(1) != Some(&MIC_LED_OFF)),
+        INSTRUCTION_RESET_TO_USER_SETTINGS => DeviceCommand::Restore,
+        INSTRUCTION_TRIGGER_THRESHOLD => return Ok(None),
+        kind => {
+            debug!("Ignoring DSX instruction type {}", kind);
+            return Ok(None);
+        }
+    };
+
+    Ok(Some((index, command)))
+}
+
+/// Commands for every instruction in a DSX packet we understand, paired
+/// with the controller index they're for
+pub fn parse_packet(packet: &[u8]) -> Result<Vec<(usize, DeviceCommand)>, DualsenseError> {
+    let packet: Packet = serde_json::from_slice(packet)
+        .map_err(|err| DualsenseError::InvalidArgument(format!("DSX packet: {err}")))?;
+
+    let mut commands = Vec::new();
+    for instruction in &packet.instructions {
+        match instruction_command(instruction) {
+            Ok(Some(command)) => commands.push(command),
+            Ok(None) => {}
+            Err(err) => error!("Skipping DSX instruction {:?}: {}", instruction, err),
+        }
+    }
+    Ok(commands)
+}
+
+/// Queues every command in `packet` and builds the reply DSX would send
+///
+/// Doesn't wait for the commands, the worker only keeps the newest pending
+/// trigger and colour values so a fast sender can't build up a backlog.
+/// They're transient so the game's effects never end up in the user's
+/// saved state, and `RESET_TO_USER_SETTINGS` puts that state back
+fn handle_packet(devices: &mut DeviceCache, worker: &Worker, packet: &[u8]) -> Value {
+    let handles = devices.handles();
+
+    match parse_packet(packet) {
+        Ok(commands) => {
+            for (index, command) in commands {
+                let device = handles.get(index).unwrap_or(&handles[0]);
+                worker.send_transient(device, command);
+            }
+        }
+        Err(err) => error!("{}", err),
+    }
+
+    let battery = handles[0]
+        .controller
+        .lock()
+        .map(|c| c.battery_percentage)
+        .unwrap_or_default();
+
+    json!({
+        "Status": "DSX Received UDP Instructions",
+        "TimeReceived": chrono::Local::now().to_rfc3339(),
+        "isControllerConnected": devices.connected(),
+        "BatteryLevel": battery,
+    })
+}
+
+/// Listens for DSX packets on `127.0.0.1:port`
+///
+/// Each packet is answered with a status reply like DSX does, some mods
+/// wait for it
+pub fn serve(registry: Arc<DeviceRegistry>, worker: Worker, port: u16) -> io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, port))?;
+
+    thread::Builder::new()
+        .name("dsx".to_string())
+        .spawn(move || {
+            let mut devices = DeviceCache::new(registry);
+            let mut buffer = [0u8; 8192];
+            loop {
+                let (len, sender) = match socket.recv_from(&mut buffer) {
+                    Ok(received) => received,
+                    Err(err) => {
+                        error!("Failed to receive DSX packet: {}", err);
+                        continue;
+                    }
+                };
+
+                let reply = handle_packet(&mut devices, &worker, &buffer[..len]);
+                if let Err(err) = socket.send_to(reply.to_string().as_bytes(), sender) {
+                    debug!("Failed to reply to {}: {}", sender, err);
+                }
+            }
+        })?;
+
+    info!("DSX server listening on 127.0.0.1:{}", port);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{mock_call, MockDevices};
+    use std::fs;
+    use std::time::Duration;
+
+    /// `serve` on a free loopback port with a mock pad behind it
+    struct Server {
+        devices: MockDevices,
+        client: UdpSocket,
+    }
+
+    impl Server {
+        fn start(name: &str) -> Self {
+            let devices = MockDevices::new(name);
+            let port = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+                .and_then(|socket| socket.local_addr())
+                .unwrap()
+                .port();
+            serve(Arc::clone(&devices.registry), devices.worker.clone(), port).unwrap();
+
+            let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            client.connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+
+            Self { devices, client }
+        }
+
+        /// Sends `packet` and waits for the status reply
+        fn send(&self, packet: &str) -> Value {
+            self.client.send(packet.as_bytes()).unwrap();
+            let mut buffer = [0u8; 1024];
+            let len = self.client.recv(&mut buffer).expect("no DSX reply");
+            serde_json::from_slice(&buffer[..len]).unwrap()
+        }
+    }
+
+    #[test]
+    fn trigger_and_lightbar() {
+        let server = Server::start("dsx-trigger");
+        let reply = server.send(
+            r#"{"instructions":[
+                {"type":1,"parameters":[0,2,13,3,6]},
+                {"type":1,"parameters":[0,1,16,2,7,5]},
+                {"type":2,"parameters":[0,10,20,30]},
+                {"type":6,"parameters":[0,2]}
+            ]}"#,
+        );
+        assert_eq!(reply["Status"], "DSX Received UDP Instructions");
+        assert_eq!(reply["isControllerConnected"], true);
+
+        let mut calls = server.devices.new_calls();
+        calls.sort();
+        let mut expected = vec![
+            mock_call("trigger right feedback 3 6"),
+            mock_call("trigger left weapon 2 7 5"),
+            mock_call("lightbar 10 20 30 255"),
+            mock_call("player-leds 3"),
+        ];
+        expected.sort();
+        assert_eq!(calls, expected);
+    }
+
+    #[test]
+    fn reset_restores_user_settings() {
+        let server = Server::start("dsx-reset");
+        let user = server.devices.state();
+
+        server.send(r#"{"instructions":[{"type":2,"parameters":[0,10,20,30]}]}"#);
+        assert_eq!(
+            server.devices.new_calls(),
+            [mock_call("lightbar 10 20 30 255")]
+        );
+
+        // The game's colour is neither kept nor saved
+        assert_eq!(server.devices.state().lightbar_colour, user.lightbar_colour);
+        assert_eq!(
+            fs::read_dir(&server.devices.app_paths.config)
+                .unwrap()
+                .count(),
+            0
+        );
+
+        server.send(r#"{"instructions":[{"type":7,"parameters":[0]}]}"#);
+        let calls = server.devices.new_calls();
+        assert!(
+            calls.contains(&mock_call("lightbar 255 255 255 255")),
+            "{calls:?}"
+        );
+        assert!(calls.contains(&mock_call("trigger both off")), "{calls:?}");
+    }
+
+    #[test]
+    fn malformed_packets() {
+        let server = Server::start("dsx-malformed");
+
+        // Still answered, mods wait for the reply
+        let reply = server.send("not json");
+        assert_eq!(reply["Status"], "DSX Received UDP Instructions");
+        assert!(server.devices.new_calls().is_empty());
+
+        server.send(r#"{"instructions":{"type":2}}"#);
+        assert!(server.devices.new_calls().is_empty());
+
+        // A bad parameter drops its instruction, not the packet
+        server.send(
+            r#"{"instructions":[
+                {"type":2,"parameters":[0,"red",20,30]},
+                {"type":1,"parameters":[0,3,13,3,6]},
+                {"type":1,"parameters":[0,2,99]},
+                {"type":3,"parameters":[0,true,"1",false,0,0]}
+            ]}"#,
+        );
+        assert_eq!(server.devices.new_calls(), [mock_call("player-leds 2")]);
+    }
+}
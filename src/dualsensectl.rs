@@ -1,157 +1,154 @@
 use log::{error, info};
-use std::process::Command;
+use std::fmt;
 
-use crate::structs::{Controller, Speaker, Trigger};
+use crate::backend::ControllerBackend;
+use crate::error::DualsenseError;
+use crate::structs::{BatteryStatus, Controller, DeviceInfo, Speaker, Trigger};
 
 /// Enables/disables the lightbar
-pub fn toggle_lightbar(state: bool, controller: &mut Controller) {
-    let command = if state {
-        format!(
-            "dualsensectl lightbar {} {} {} {}",
+pub fn toggle_lightbar(
+    backend: &dyn ControllerBackend,
+    state: bool,
+    controller: &mut Controller,
+) -> Result<(), DualsenseError> {
+    let result = if state {
+        backend.set_lightbar_colour(
             controller.lightbar_colour[0],
             controller.lightbar_colour[1],
             controller.lightbar_colour[2],
-            controller.lightbar_colour[3]
+            controller.lightbar_colour[3],
         )
     } else {
-        "dualsensectl lightbar off".to_string()
+        backend.set_lightbar(false)
     };
 
-    info!("Executing command: {}", command);
-
-    match Command::new("sh").arg("-c").arg(command.clone()).output() {
-        Ok(_) => {
+    match result {
+        Ok(()) => {
             controller.lightbar_enabled = state;
             info!(
                 "Successfully executed lightbar toggle command. State: {}",
                 if state { "On" } else { "Off" }
             );
+            Ok(())
         }
         Err(err) => {
-            error!("Failed to execute command '{}': {}", command, err);
+            error!("Failed to toggle lightbar: {}", err);
+            Err(err)
         }
     }
 }
 
 /// Changes the player LED amount, 0-5
-pub fn change_playerleds_amount(state: u8, controller: &mut Controller) {
+pub fn change_playerleds_amount(
+    backend: &dyn ControllerBackend,
+    state: u8,
+    controller: &mut Controller,
+) -> Result<(), DualsenseError> {
     if !(0..=5).contains(&state) {
         error!(
             "Invalid player LED state: {}. Must be between 0 and 5.",
             state
         );
-        return;
+        return Err(DualsenseError::InvalidArgument(format!(
+            "Player LEDs must be between 0 and 5, got {state}"
+        )));
     }
 
-    let command = format!("dualsensectl player-leds {state}");
-    info!("Executing command: {}", command);
-
-    if let Err(err) = Command::new("sh").arg("-c").arg(&command).output() {
-        error!("Failed to execute command '{}': {}", command, err);
-        return;
+    if let Err(err) = backend.set_player_leds(state) {
+        error!("Failed to change player LEDs: {}", err);
+        return Err(err);
     }
 
     controller.playerleds = state;
+    Ok(())
 }
 
 /// Changes the speaker output
 ///
 /// Internal, Headphone, Monoheadphone (left side), Both (Internal &
 /// Headphone)
-pub fn toggle_speaker(state: Speaker, controller: &mut Controller) {
-    let mut _command = String::new();
-
-    match state {
-        Speaker::Internal => _command = "dualsensectl speaker internal".to_string(),
-        Speaker::Headphone => _command = "dualsensectl speaker headphone".to_string(),
-        Speaker::Monoheadphone => _command = "dualsensectl speaker monoheadphone".to_string(),
-        Speaker::Both => _command = "dualsensectl speaker both".to_string(),
-    }
-
-    info!("Executing command: {}", _command);
-
-    if let Err(err) = Command::new("sh").arg("-c").arg(&_command).output() {
-        error!("Failed to execute command '{}': {}", _command, err);
-        return;
+pub fn toggle_speaker(
+    backend: &dyn ControllerBackend,
+    state: Speaker,
+    controller: &mut Controller,
+) -> Result<(), DualsenseError> {
+    if let Err(err) = backend.set_speaker(&state) {
+        error!("Failed to change speaker: {}", err);
+        return Err(err);
     }
 
     controller.speaker = state;
+    Ok(())
 }
 
 /// Changes the lightbar colour with RGB BRIGHTNESS, 0-255
-pub fn change_lightbar_colour(state: Vec<u8>, controller: &mut Controller) {
+pub fn change_lightbar_colour(
+    backend: &dyn ControllerBackend,
+    state: Vec<u8>,
+    controller: &mut Controller,
+) -> Result<(), DualsenseError> {
     if state.len() != 4 {
         error!(
             "Invalid lightbar state: Expected 4 values (R, G, B, Brightness), got {}",
             state.len()
         );
-        return;
+        return Err(DualsenseError::InvalidArgument(format!(
+            "Lightbar needs 4 values (R, G, B, Brightness), got {}",
+            state.len()
+        )));
     }
 
-    let command = format!(
-        "dualsensectl lightbar {} {} {} {}",
-        state[0], state[1], state[2], state[3]
-    );
-    info!("Executing command: {}", command);
-
-    if let Err(err) = Command::new("sh").arg("-c").arg(&command).output() {
-        error!("Failed to execute command '{}': {}", command, err);
-        return;
+    if let Err(err) = backend.set_lightbar_colour(state[0], state[1], state[2], state[3]) {
+        error!("Failed to change lightbar colour: {}", err);
+        return Err(err);
     }
 
     controller.lightbar_colour = state;
     controller.lightbar_enabled = true;
     info!("Lightbar colour changed and enabled.");
+    Ok(())
 }
 
 /// Enables/disables the microphone
-pub fn toggle_microphone(controller: &mut Controller) {
-    let command = if controller.microphone {
-        "dualsensectl microphone off".to_string()
-    } else {
-        "dualsensectl microphone on".to_string()
-    };
-
-    info!("Executing command: {}", command);
-
-    match Command::new("sh").arg("-c").arg(command.clone()).output() {
-        Ok(_) => {
-            controller.microphone = !controller.microphone;
+pub fn toggle_microphone(
+    backend: &dyn ControllerBackend,
+    state: bool,
+    controller: &mut Controller,
+) -> Result<(), DualsenseError> {
+    match backend.set_microphone(state) {
+        Ok(()) => {
+            controller.microphone = state;
             info!(
                 "Successfully executed microphone toggle command. State: {}",
-                if !controller.microphone { "On" } else { "Off" }
+                if state { "On" } else { "Off" }
             );
+            Ok(())
         }
         Err(err) => {
-            error!("Failed to execute command '{}': {}", command, err);
+            error!("Failed to toggle microphone: {}", err);
+            Err(err)
         }
     }
 }
 
 /// Enables/disables the microphone LED
-pub fn toggle_microphone_led(controller: &mut Controller) {
-    let command = if controller.microphone_led {
-        "dualsensectl microphone-led off".to_string()
-    } else {
-        "dualsensectl microphone-led on".to_string()
-    };
-
-    info!("Executing command: {}", command);
-
-    match Command::new("sh").arg("-c").arg(command.clone()).output() {
-        Ok(_) => {
-            controller.microphone_led = !controller.microphone_led;
+pub fn toggle_microphone_led(
+    backend: &dyn ControllerBackend,
+    state: bool,
+    controller: &mut Controller,
+) -> Result<(), DualsenseError> {
+    match backend.set_microphone_led(state) {
+        Ok(()) => {
+            controller.microphone_led = state;
             info!(
                 "Successfully executed microphone-led toggle command. State: {}",
-                if !controller.microphone_led {
-                    "On"
-                } else {
-                    "Off"
-                }
+                if state { "On" } else { "Off" }
             );
+            Ok(())
         }
         Err(err) => {
-            error!("Failed to execute command '{}': {}", command, err);
+            error!("Failed to toggle microphone LED: {}", err);
+            Err(err)
         }
     }
 }
@@ -161,99 +158,382 @@ pub fn toggle_microphone_led(controller: &mut Controller) {
 /// 150+ is audible on Internal
 ///
 /// 50+ is audible on Headphones
-pub fn change_volume(volume: u8, controller: &mut Controller) {
-    let command = format!("dualsensectl volume {volume}");
-
-    info!("Executing command: {}", command);
-
-    match Command::new("sh").arg("-c").arg(command.clone()).output() {
-        Ok(_) => {
+pub fn change_volume(
+    backend: &dyn ControllerBackend,
+    volume: u8,
+    controller: &mut Controller,
+) -> Result<(), DualsenseError> {
+    match backend.set_volume(volume) {
+        Ok(()) => {
             controller.volume = volume;
             info!(
                 "Successfully executed volume toggle command. State: {}",
                 controller.volume
             );
+            Ok(())
         }
         Err(err) => {
-            error!("Failed to execute command '{}': {}", command, err);
+            error!("Failed to change volume: {}", err);
+            Err(err)
         }
     }
 }
 
 /// Changes attenuation amount, (RUMBLE, TRIGGER) 0-7
-pub fn change_attenuation_amount(attenuation: Vec<u8>, controller: &mut Controller) {
-    if !(0..=7).contains(&attenuation[0]) || !(0..=7).contains(&attenuation[1]) {
+pub fn change_attenuation_amount(
+    backend: &dyn ControllerBackend,
+    attenuation: Vec<u8>,
+    controller: &mut Controller,
+) -> Result<(), DualsenseError> {
+    if attenuation.len() != 2 || attenuation.iter().any(|a| !(0..=7).contains(a)) {
         error!(
-            "Invalid player attentuation attenuation: {} {}. RUMBLE and TRIGGER must be between 0 and 7.",
-            attenuation[0], attenuation[1]
+            "Invalid attenuation: {:?}. RUMBLE and TRIGGER must be between 0 and 7.",
+            attenuation
         );
-        return;
+        return Err(DualsenseError::InvalidArgument(format!(
+            "Attenuation RUMBLE and TRIGGER must be between 0 and 7, got {attenuation:?}"
+        )));
     }
 
-    let command = format!(
-        "dualsensectl attenuation {} {}",
-        attenuation[0], attenuation[1]
-    );
-    info!("Executing command: {}", command);
-
-    if let Err(err) = Command::new("sh").arg("-c").arg(&command).output() {
-        error!("Failed to execute command '{}': {}", command, err);
-        return;
+    if let Err(err) = backend.set_attenuation(attenuation[0], attenuation[1]) {
+        error!("Failed to change attenuation: {}", err);
+        return Err(err);
     }
 
     controller.attenuation = attenuation;
+    Ok(())
 }
 
 /// Changes trigger motor profile
-pub fn change_triggers(trigger: &Trigger) {
-    let command = format!("dualsensectl {}", trigger.to_command());
+pub fn change_triggers(
+    backend: &dyn ControllerBackend,
+    trigger: Trigger,
+    controller: &mut Controller,
+) -> Result<(), DualsenseError> {
+    if let Err(err) = backend.set_trigger(&trigger) {
+        error!("Failed to change triggers: {}", err);
+        return Err(err);
+    }
 
-    info!("Executing command: {}", command);
+    info!("Updated trigger: {:?}", trigger);
+    controller.trigger = trigger;
+    Ok(())
+}
 
-    match Command::new("sh").arg("-c").arg(&command).output() {
-        Ok(output) => {
-            if let Ok(stdout) = String::from_utf8(output.stdout) {
-                info!("Command executed successfully: {}", stdout.trim());
-            } else {
-                error!("Failed to parse command stdout as UTF-8");
-            }
+/// Turns the controller off
+pub fn power_off(backend: &dyn ControllerBackend) -> Result<(), DualsenseError> {
+    match backend.power_off() {
+        Ok(()) => {
+            info!("Successfully executed power-off command.");
+            Ok(())
         }
         Err(err) => {
-            error!("Failed to execute command '{}': {}", command, err);
+            error!("Failed to power off controller: {}", err);
+            Err(err)
         }
     }
 }
 
-/// Reports battery level
+/// Reads firmware/hardware details
+///
+/// Fills in the connection type from device enumeration when `info`
+/// doesn't report it
+pub fn device_info(
+    backend: &dyn ControllerBackend,
+    serial: Option<&str>,
+) -> Result<DeviceInfo, DualsenseError> {
+    let mut info = backend.info().map_err(|err| {
+        error!("Failed to read device info: {}", err);
+        err
+    })?;
+
+    if info.connection.is_none() {
+        let serial = serial.or(info.serial.as_deref());
+        let devices = backend.list_devices().unwrap_or_default();
+        info.connection = devices
+            .iter()
+            .find(|d| serial.is_some_and(|s| d.serial.eq_ignore_ascii_case(s)))
+            .or(devices.first().filter(|_| devices.len() == 1))
+            .map(|d| d.connection);
+    }
+
+    info!("Read device info: {:?}", info);
+    Ok(info)
+}
+
+/// Reports battery level in percent and whether it's charging
+pub fn report_battery(
+    backend: &dyn ControllerBackend,
+    controller: &mut Controller,
+) -> Result<BatteryStatus, DualsenseError> {
+    match backend.battery() {
+        Ok(status) => {
+            controller.battery_percentage = status.level;
+            Ok(status)
+        }
+        Err(err) => {
+            error!("Failed to read battery: {}", err);
+            Err(err)
+        }
+    }
+}
+
+/// One setting `apply_controller` writes, in the order it writes them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerField {
+    Lightbar,
+    PlayerLeds,
+    Microphone,
+    MicrophoneLed,
+    Speaker,
+    Volume,
+    Attenuation,
+    Trigger,
+}
+
+impl ControllerField {
+    pub const ORDER: [ControllerField; 8] = [
+        ControllerField::Lightbar,
+        ControllerField::PlayerLeds,
+        ControllerField::Microphone,
+        ControllerField::MicrophoneLed,
+        ControllerField::Speaker,
+        ControllerField::Volume,
+        ControllerField::Attenuation,
+        ControllerField::Trigger,
+    ];
+}
+
+/// How each field of an `apply_controller` went
 ///
-/// Returns string 'u8%'
-pub fn report_battery(controller: &mut Controller) -> String {
-    let command = "dualsensectl battery";
+/// Fields after the first failure aren't attempted and don't appear in
+/// `results`
+#[derive(Debug, Clone)]
+pub struct ApplyReport {
+    pub results: Vec<(ControllerField, Result<(), DualsenseError>)>,
+    /// The pad was put back to its previous state after a failure
+    pub rolled_back: bool,
+}
 
-    info!("Executing command: {}", command);
+impl ApplyReport {
+    /// The field that failed, skipped (unsupported) fields don't count
+    pub fn failure(&self) -> Option<(ControllerField, &DualsenseError)> {
+        self.results
+            .iter()
+            .find_map(|(field, result)| match result {
+                Err(DualsenseError::Unsupported(_)) | Ok(()) => None,
+                Err(err) => Some((*field, err)),
+            })
+    }
 
-    match Command::new("sh").arg("-c").arg(command).output() {
-        Ok(output) => {
-            if let Ok(stdout) = String::from_utf8(output.stdout) {
-                info!("Command output: {}", stdout.trim());
+    pub fn succeeded(&self) -> bool {
+        self.failure().is_none()
+    }
+}
 
-                let battery_percentage = stdout
-                    .split_whitespace()
-                    .next()
-                    .and_then(|s| s.parse::<u8>().ok())
-                    .unwrap_or(0);
+impl fmt::Display for ApplyReport {
+    /// `Lightbar: ok, PlayerLeds: failed (...), rolled back`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<String> = self
+            .results
+            .iter()
+            .map(|(field, result)| match result {
+                Ok(()) => format!("{field:?}: ok"),
+                Err(DualsenseError::Unsupported(_)) => format!("{field:?}: skipped"),
+                Err(err) => format!("{field:?}: failed ({err})"),
+            })
+            .collect();
+        write!(f, "{}", fields.join(", "))?;
+        if self.rolled_back {
+            write!(f, ", rolled back")?;
+        }
+        Ok(())
+    }
+}
 
-                controller.battery_percentage = battery_percentage;
+/// Writes `field` from `target`, updating `controller` on success
+fn apply_field(
+    backend: &dyn ControllerBackend,
+    field: ControllerField,
+    target: &Controller,
+    controller: &mut Controller,
+) -> Result<(), DualsenseError> {
+    match field {
+        ControllerField::Lightbar if target.lightbar_enabled => {
+            change_lightbar_colour(backend, target.lightbar_colour.clone(), controller)
+        }
+        ControllerField::Lightbar => toggle_lightbar(backend, false, controller).map(|()| {
+            controller.lightbar_colour = target.lightbar_colour.clone();
+        }),
+        ControllerField::PlayerLeds => {
+            change_playerleds_amount(backend, target.playerleds, controller)
+        }
+        ControllerField::Microphone => toggle_microphone(backend, target.microphone, controller),
+        ControllerField::MicrophoneLed => {
+            toggle_microphone_led(backend, target.microphone_led, controller)
+        }
+        ControllerField::Speaker => toggle_speaker(backend, target.speaker.clone(), controller),
+        ControllerField::Volume => change_volume(backend, target.volume, controller),
+        ControllerField::Attenuation => {
+            change_attenuation_amount(backend, target.attenuation.clone(), controller)
+        }
+        ControllerField::Trigger => change_triggers(backend, target.trigger.clone(), controller),
+    }
+}
+
+/// Pushes every setting in `target` to the pad, e.g. a profile or the
+/// saved state after a reconnect
+///
+/// Stops at the first failure and writes the fields already applied back
+/// to what `controller` had before, so the pad and `controller` never end
+/// up half way between two states. Settings the backend doesn't support
+/// are skipped
+pub fn apply_controller(
+    backend: &dyn ControllerBackend,
+    target: &Controller,
+    controller: &mut Controller,
+) -> ApplyReport {
+    let previous = controller.clone();
+    let mut report = ApplyReport {
+        results: Vec::new(),
+        rolled_back: false,
+    };
+
+    for field in ControllerField::ORDER {
+        let result = apply_field(backend, field, target, controller);
+        let failed = matches!(&result, Err(err) if !matches!(err, DualsenseError::Unsupported(_)));
+        report.results.push((field, result));
+        if failed {
+            break;
+        }
+    }
 
-                format!("{battery_percentage}%")
-            } else {
-                error!("Failed to parse command stdout as UTF-8");
-                "Error: Invalid UTF-8 output".to_string()
+    if let Some((field, err)) = report.failure() {
+        error!("Applying {:?} failed ({}), rolling back.", field, err);
+        let applied: Vec<ControllerField> = report
+            .results
+            .iter()
+            .filter(|(_, result)| result.is_ok())
+            .map(|(f, _)| *f)
+            .collect();
+
+        for field in applied.into_iter().rev() {
+            if let Err(err) = apply_field(backend, field, &previous, controller) {
+                error!("Failed to roll back {:?}: {}", field, err);
             }
         }
-        Err(err) => {
-            error!("Failed to execute command '{}': {}", command, err);
-            format!("Error: {err}")
+
+        *controller = previous;
+        report.rolled_back = true;
+    } else {
+        info!("Applied controller state.");
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    fn target() -> Controller {
+        Controller {
+            lightbar_colour: vec![10, 20, 30, 255],
+            playerleds: 3,
+            microphone: true,
+            microphone_led: true,
+            speaker: Speaker::Headphone,
+            volume: 60,
+            ..Controller::default()
         }
     }
+
+    fn json(controller: &Controller) -> serde_json::Value {
+        serde_json::to_value(controller).unwrap()
+    }
+
+    #[test]
+    fn apply_writes_every_field_in_order() {
+        let backend = MockBackend::new().unsupported("microphone-led");
+        let mut controller = Controller::default();
+
+        let report = apply_controller(&backend, &target(), &mut controller);
+        assert!(report.succeeded());
+        assert!(!report.rolled_back);
+        assert_eq!(
+            backend.calls(),
+            [
+                "lightbar 10 20 30 255",
+                "player-leds 3",
+                "microphone on",
+                "microphone-led on",
+                "speaker headphone",
+                "volume 60",
+                "attenuation 0 0",
+                "trigger both off",
+            ]
+        );
+        // Skipped fields keep what we had
+        let expected = Controller {
+            microphone_led: false,
+            ..target()
+        };
+        assert_eq!(json(&controller), json(&expected));
+    }
+
+    #[test]
+    fn apply_rolls_back_in_reverse_after_a_failure() {
+        let backend = MockBackend::new()
+            .unsupported("microphone-led")
+            .failing_on("volume");
+        let mut controller = Controller::default();
+        let previous = controller.clone();
+
+        let report = apply_controller(&backend, &target(), &mut controller);
+        assert!(!report.succeeded());
+        assert!(report.rolled_back);
+        assert!(matches!(
+            report.failure(),
+            Some((ControllerField::Volume, DualsenseError::CommandFailed(_)))
+        ));
+        // Nothing after the failure is attempted
+        assert_eq!(report.results.len(), 6);
+
+        assert_eq!(
+            backend.calls(),
+            [
+                // Forward pass up to the failing field
+                "lightbar 10 20 30 255",
+                "player-leds 3",
+                "microphone on",
+                "microphone-led on",
+                "speaker headphone",
+                "volume 60",
+                // Applied fields back to what they were, last first, the
+                // unsupported one is left alone
+                "speaker internal",
+                "microphone off",
+                "player-leds 1",
+                "lightbar 255 255 255 255",
+            ]
+        );
+        assert_eq!(json(&controller), json(&previous));
+        assert_eq!(
+            report.to_string(),
+            "Lightbar: ok, PlayerLeds: ok, Microphone: ok, MicrophoneLed: skipped, \
+             Speaker: ok, Volume: failed (Command failed: Mock failure: volume 60), rolled back"
+        );
+    }
+
+    #[test]
+    fn apply_rolls_back_nothing_when_the_first_field_fails() {
+        let backend = MockBackend::new().failing();
+        let mut controller = Controller::default();
+
+        let report = apply_controller(&backend, &target(), &mut controller);
+        assert!(report.rolled_back);
+        assert_eq!(backend.calls(), ["lightbar 10 20 30 255"]);
+        assert_eq!(json(&controller), json(&Controller::default()));
+    }
 }
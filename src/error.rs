@@ -0,0 +1,65 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong talking to a controller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DualsenseError {
+    /// `dualsensectl` isn't installed or not on `PATH`
+    BinaryMissing,
+    /// No DualSense connected (or not the one we asked for)
+    NoDevice,
+    /// The hidraw node or binary isn't accessible to this user
+    PermissionDenied,
+    /// A value was out of range or not understood
+    InvalidArgument(String),
+    /// The command didn't finish in time
+    Timeout,
+    /// The backend (or installed `dualsensectl`) can't do this
+    Unsupported(String),
+    /// Anything else, with whatever the backend told us
+    CommandFailed(String),
+}
+
+impl DualsenseError {
+    /// Classifies a failed `dualsensectl` run from its stderr
+    pub fn from_stderr(stderr: &str) -> Self {
+        let lower = stderr.to_lowercase();
+
+        if lower.contains("no device") {
+            DualsenseError::NoDevice
+        } else if lower.contains("permission denied") {
+            DualsenseError::PermissionDenied
+        } else if lower.contains("invalid") || lower.contains("usage") {
+            DualsenseError::InvalidArgument(stderr.trim().to_string())
+        } else {
+            DualsenseError::CommandFailed(stderr.trim().to_string())
+        }
+    }
+}
+
+impl From<io::Error> for DualsenseError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => DualsenseError::NoDevice,
+            io::ErrorKind::PermissionDenied => DualsenseError::PermissionDenied,
+            io::ErrorKind::TimedOut => DualsenseError::Timeout,
+            _ => DualsenseError::CommandFailed(err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for DualsenseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DualsenseError::BinaryMissing => write!(f, "dualsensectl binary not found"),
+            DualsenseError::NoDevice => write!(f, "No device found"),
+            DualsenseError::PermissionDenied => write!(f, "Permission denied"),
+            DualsenseError::InvalidArgument(msg) => write!(f, "Invalid argument: {msg}"),
+            DualsenseError::Timeout => write!(f, "Timed out"),
+            DualsenseError::Unsupported(what) => write!(f, "Not supported: {what}"),
+            DualsenseError::CommandFailed(msg) => write!(f, "Command failed: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for DualsenseError {}
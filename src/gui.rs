@@ -1,3 +1,8 @@
+pub mod auto_profiles;
+pub mod battery;
+pub mod device_info;
+pub mod monitor;
+pub mod preferences;
 pub mod presets;
 pub mod profiles;
 pub mod ui;
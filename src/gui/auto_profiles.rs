@@ -0,0 +1,252 @@
+use gtk::{
+    prelude::*, Adjustment, Box, Button, DropDown, Entry, Label, ListBox, Orientation,
+    SelectionMode, SpinButton, StringList, Switch,
+};
+use std::sync::{Arc, Mutex};
+
+use crate::gui::preferences::update_settings;
+use crate::save::{list_profiles, AppPaths};
+use crate::structs::{MatchOn, ProfileRule, Settings};
+
+const MATCH_ON: [(MatchOn, &str); 2] = [
+    (MatchOn::Executable, "Executable"),
+    (MatchOn::CommandLine, "Command line"),
+];
+
+/// Saved profile names, plus `current` if its file has gone missing so a
+/// rule still shows what it points at
+fn profile_names(app_paths: &Arc<AppPaths>, current: &str) -> Vec<String> {
+    let mut names = list_profiles(app_paths);
+    if !current.is_empty() && !names.iter().any(|name| name == current) {
+        names.push(current.to_string());
+    }
+    names
+}
+
+fn string_dropdown(names: &[String], selected: &str) -> DropDown {
+    let model = StringList::new(&names.iter().map(String::as_str).collect::<Vec<_>>());
+    let selected = names.iter().position(|name| name == selected).unwrap_or(0);
+    DropDown::builder()
+        .model(&model)
+        .selected(selected as u32)
+        .build()
+}
+
+fn selected_string(dropdown: &DropDown) -> Option<String> {
+    dropdown
+        .model()?
+        .downcast_ref::<StringList>()?
+        .string(dropdown.selected())
+        .map(String::from)
+}
+
+/// Runs `update` on rule `index` and saves
+fn update_rule(
+    settings: &Arc<Mutex<Settings>>,
+    app_paths: &Arc<AppPaths>,
+    index: usize,
+    update: impl FnOnce(&mut ProfileRule),
+) {
+    update_settings(settings, app_paths, |s| {
+        if let Some(rule) = s.auto_profile_rules.get_mut(index) {
+            update(rule);
+        }
+    });
+}
+
+fn create_rule_row(
+    list: &ListBox,
+    index: usize,
+    rule: &ProfileRule,
+    settings: &Arc<Mutex<Settings>>,
+    app_paths: &Arc<AppPaths>,
+) -> Box {
+    let row = Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+
+    let pattern_entry = Entry::builder()
+        .text(&rule.pattern)
+        .placeholder_text("e.g. eldenring.exe")
+        .hexpand(true)
+        .build();
+    pattern_entry.connect_changed({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |entry| {
+            let pattern = entry.text().to_string();
+            update_rule(&settings, &app_paths, index, |rule| rule.pattern = pattern);
+        }
+    });
+
+    let match_on_dropdown = DropDown::from_strings(&MATCH_ON.map(|(_, label)| label));
+    match_on_dropdown.set_selected(
+        MATCH_ON
+            .iter()
+            .position(|(match_on, _)| *match_on == rule.match_on)
+            .unwrap_or(0) as u32,
+    );
+    match_on_dropdown.connect_selected_notify({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |dropdown| {
+            let Some((match_on, _)) = MATCH_ON.get(dropdown.selected() as usize) else {
+                return;
+            };
+            update_rule(&settings, &app_paths, index, |rule| {
+                rule.match_on = *match_on
+            });
+        }
+    });
+
+    let profile_dropdown = string_dropdown(&profile_names(app_paths, &rule.profile), &rule.profile);
+    profile_dropdown.connect_selected_notify({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |dropdown| {
+            if let Some(profile) = selected_string(dropdown) {
+                update_rule(&settings, &app_paths, index, |rule| rule.profile = profile);
+            }
+        }
+    });
+
+    let priority_spin = SpinButton::builder()
+        .adjustment(&Adjustment::new(
+            f64::from(rule.priority),
+            -100.0,
+            100.0,
+            1.0,
+            10.0,
+            0.0,
+        ))
+        .tooltip_text("Higher wins when several rules match")
+        .build();
+    priority_spin.connect_value_changed({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |spin| {
+            let priority = spin.value_as_int();
+            update_rule(&settings, &app_paths, index, |rule| {
+                rule.priority = priority
+            });
+        }
+    });
+
+    let remove_button = Button::from_icon_name("list-remove-symbolic");
+    remove_button.set_tooltip_text(Some("Remove rule"));
+    remove_button.connect_clicked({
+        let list = list.clone();
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |_| {
+            update_settings(&settings, &app_paths, |s| {
+                if index < s.auto_profile_rules.len() {
+                    s.auto_profile_rules.remove(index);
+                }
+            });
+            // Later rows moved up one, their indices are stale now
+            populate_rules(&list, &settings, &app_paths);
+        }
+    });
+
+    row.append(&pattern_entry);
+    row.append(&match_on_dropdown);
+    row.append(&profile_dropdown);
+    row.append(&priority_spin);
+    row.append(&remove_button);
+    row
+}
+
+/// Rebuilds the rule rows, e.g. after profiles were renamed
+pub fn populate_rules(list: &ListBox, settings: &Arc<Mutex<Settings>>, app_paths: &Arc<AppPaths>) {
+    list.remove_all();
+
+    let rules = settings
+        .lock()
+        .map(|s| s.auto_profile_rules.clone())
+        .unwrap_or_default();
+    for (index, rule) in rules.iter().enumerate() {
+        list.append(&create_rule_row(list, index, rule, settings, app_paths));
+    }
+}
+
+/// Switch and rule list for automatic profile switching, and the list so
+/// it can be refreshed with `populate_rules`
+pub fn create_auto_profiles_editor(
+    settings: &Arc<Mutex<Settings>>,
+    app_paths: &Arc<AppPaths>,
+) -> (Box, ListBox) {
+    let editor = Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(12)
+        .build();
+
+    let enabled = settings
+        .lock()
+        .map(|s| s.auto_profile_enabled)
+        .unwrap_or_default();
+
+    let enabled_switch = Switch::builder()
+        .active(enabled)
+        .halign(gtk::Align::Start)
+        .build();
+    enabled_switch.connect_active_notify({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |switch| {
+            let active = switch.is_active();
+            update_settings(&settings, &app_paths, |s| s.auto_profile_enabled = active);
+        }
+    });
+
+    let enabled_box = Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(10)
+        .build();
+    enabled_box.append(&Label::new(Some(
+        "Switch profiles automatically when a program starts",
+    )));
+    enabled_box.append(&enabled_switch);
+
+    let default_label = Label::new(Some(
+        "Once the program exits the default profile is applied, or the state \
+         from before if there is none",
+    ));
+    default_label.set_halign(gtk::Align::Start);
+    default_label.set_wrap(true);
+
+    let rules_list = ListBox::builder()
+        .selection_mode(SelectionMode::None)
+        .build();
+    populate_rules(&rules_list, settings, app_paths);
+
+    let add_button = Button::with_label("Add Rule");
+    add_button.set_halign(gtk::Align::End);
+    add_button.connect_clicked({
+        let rules_list = rules_list.clone();
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |_| {
+            let rule = ProfileRule {
+                profile: list_profiles(&app_paths)
+                    .into_iter()
+                    .next()
+                    .unwrap_or_default(),
+                ..ProfileRule::default()
+            };
+            update_settings(&settings, &app_paths, |s| s.auto_profile_rules.push(rule));
+            populate_rules(&rules_list, &settings, &app_paths);
+        }
+    });
+
+    editor.append(&enabled_box);
+    editor.append(&default_label);
+    editor.append(&Label::new(Some(
+        "Program, what to match it against, profile and priority",
+    )));
+    editor.append(&rules_list);
+    editor.append(&add_button);
+    (editor, rules_list)
+}
@@ -0,0 +1,141 @@
+use gtk::gio::{self, prelude::*};
+use gtk::glib::{self, ControlFlow};
+use gtk::{prelude::*, Box, Image, Label, LevelBar};
+use log::error;
+use std::cell::Cell;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::alerts::BatteryAlert;
+use crate::devices::DeviceHandle;
+use crate::gui::utils::{create_labeled_level_bar, send_command};
+use crate::structs::{BatteryStatus, ChargingState, Settings};
+use crate::worker::{DeviceCommand, Output, Worker};
+
+/// Adwaita battery icon for `status`
+fn battery_icon(status: &BatteryStatus) -> String {
+    // Icons come in steps of 10
+    let level = (status.level / 10).min(10) * 10;
+    match status.state {
+        ChargingState::Full => "battery-full-charged-symbolic".to_string(),
+        ChargingState::Charging => format!("battery-level-{level}-charging-symbolic"),
+        ChargingState::Discharging => format!("battery-level-{level}-symbolic"),
+        ChargingState::Unknown => "battery-missing-symbolic".to_string(),
+    }
+}
+
+fn show_status(level_bar: &LevelBar, icon: &Image, label: &Label, status: &BatteryStatus) {
+    level_bar.set_value(status.level.into());
+    icon.set_icon_name(Some(&battery_icon(status)));
+    label.set_text(&format!("{}% ({})", status.level, status.state));
+}
+
+/// Sends a desktop notification if `status` crossed a threshold
+fn notify_alerts(device: &DeviceHandle, status: &BatteryStatus, settings: &Arc<Mutex<Settings>>) {
+    let Ok(settings) = settings.lock() else {
+        error!("Failed to lock settings.");
+        return;
+    };
+    let alert = match device.battery_alerts.lock() {
+        Ok(mut alerts) => alerts.update(
+            status,
+            &settings.low_battery_thresholds,
+            settings.notify_when_charged,
+        ),
+        Err(_) => return,
+    };
+    drop(settings);
+
+    let (title, icon) = match alert {
+        Some(BatteryAlert::Low(threshold)) => (
+            format!("Controller battery below {threshold}%"),
+            "battery-caution-symbolic",
+        ),
+        Some(BatteryAlert::FullyCharged) => (
+            "Controller fully charged".to_string(),
+            "battery-full-charged-symbolic",
+        ),
+        None => return,
+    };
+
+    let notification = gio::Notification::new(&title);
+    notification.set_body(Some(&format!("{} is at {}%", device.label(), status.level)));
+    notification.set_icon(&gio::ThemedIcon::new(icon));
+
+    match gio::Application::default() {
+        // One notification per pad, a newer one replaces the last
+        Some(app) => {
+            app.send_notification(Some(&format!("battery-{}", device.label())), &notification)
+        }
+        None => error!("No application to send notifications from."),
+    }
+}
+
+/// Battery level bar, charging icon and label that keep themselves up to
+/// date
+///
+/// Polls every `Settings::battery_poll_interval` seconds for as long as the
+/// widget is on screen
+pub fn create_battery_status(
+    worker: &Worker,
+    device: &DeviceHandle,
+    initial_level: u8,
+    settings: &Arc<Mutex<Settings>>,
+) -> Box {
+    let (battery_box, level_bar) =
+        create_labeled_level_bar("Battery", initial_level.into(), 0.0, 100.0);
+
+    let icon = Image::from_icon_name("battery-missing-symbolic");
+    let label = Label::new(Some(&format!("{initial_level}%")));
+    battery_box.append(&icon);
+    battery_box.append(&label);
+
+    let poll = {
+        let worker = worker.clone();
+        let device = device.clone();
+        let level_bar = level_bar.clone();
+        let settings = Arc::clone(settings);
+        move || {
+            let level_bar = level_bar.clone();
+            let icon = icon.clone();
+            let label = label.clone();
+            let reply_device = device.clone();
+            let settings = Arc::clone(&settings);
+            send_command(&worker, &device, DeviceCommand::Battery, move |reply| {
+                if let Ok(Output::Battery(status)) = reply.result {
+                    show_status(&level_bar, &icon, &label, &status);
+                    notify_alerts(&reply_device, &status, &settings);
+                }
+            });
+        }
+    };
+
+    poll();
+
+    // Tick every second so interval changes apply without a restart
+    let last_poll = Cell::new(Instant::now());
+    let weak_box = battery_box.downgrade();
+    let settings = Arc::clone(settings);
+    glib::timeout_add_seconds_local(1, move || {
+        let Some(battery_box) = weak_box.upgrade() else {
+            return ControlFlow::Break;
+        };
+        // Pages are rebuilt when switching devices, stop polling the old one
+        if battery_box.root().is_none() {
+            return ControlFlow::Break;
+        }
+
+        let interval = settings
+            .lock()
+            .map(|s| s.battery_poll_interval)
+            .unwrap_or(30);
+        if last_poll.get().elapsed() >= Duration::from_secs(interval.max(1).into()) {
+            last_poll.set(Instant::now());
+            poll();
+        }
+
+        ControlFlow::Continue
+    });
+
+    battery_box
+}
@@ -0,0 +1,110 @@
+use gtk::{prelude::*, Box, Button, Grid, Label, Orientation, ScrolledWindow};
+use log::{error, info};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::devices::DeviceHandle;
+use crate::gui::utils::{clear_grid, send_command, set_margins};
+use crate::structs::DeviceInfo;
+use crate::worker::{DeviceCommand, Output, Worker};
+
+fn show_info(grid: &Grid, info: &DeviceInfo) {
+    clear_grid(grid);
+
+    for (row, (label, value)) in info.rows().iter().enumerate() {
+        let label = Label::new(Some(label));
+        label.set_halign(gtk::Align::Start);
+
+        let value = Label::new(Some(value));
+        value.set_halign(gtk::Align::Start);
+        value.set_selectable(true);
+
+        grid.attach(&label, 0, row as i32, 1, 1);
+        grid.attach(&value, 1, row as i32, 1, 1);
+    }
+}
+
+/// Reads `dualsensectl info` for `device` and fills `grid` with it
+fn refresh_info(
+    worker: &Worker,
+    device: &DeviceHandle,
+    grid: &Grid,
+    current: &Rc<RefCell<Option<DeviceInfo>>>,
+) {
+    let grid = grid.clone();
+    let current = Rc::clone(current);
+    send_command(
+        worker,
+        device,
+        DeviceCommand::Info,
+        move |reply| match reply.result {
+            Ok(Output::Info(info)) => {
+                show_info(&grid, &info);
+                *current.borrow_mut() = Some(info);
+            }
+            Ok(_) => {}
+            Err(err) => {
+                clear_grid(&grid);
+                grid.attach(
+                    &Label::new(Some(&format!("Couldn't read device info: {err}"))),
+                    0,
+                    0,
+                    2,
+                    1,
+                );
+            }
+        },
+    );
+}
+
+pub fn create_device_page(worker: &Worker, device: &DeviceHandle) -> ScrolledWindow {
+    let page_box = Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(10)
+        .build();
+    set_margins(&page_box, 12);
+
+    let info_grid = Grid::builder().row_spacing(6).column_spacing(20).build();
+    let current: Rc<RefCell<Option<DeviceInfo>>> = Rc::new(RefCell::new(None));
+
+    let refresh_button = Button::with_label("Refresh");
+    let copy_button = Button::with_label("Copy to Clipboard");
+
+    refresh_button.connect_clicked({
+        let worker = worker.clone();
+        let device = device.clone();
+        let info_grid = info_grid.clone();
+        let current = Rc::clone(&current);
+        move |_| refresh_info(&worker, &device, &info_grid, &current)
+    });
+
+    copy_button.connect_clicked({
+        let current = Rc::clone(&current);
+        move |button| match current.borrow().as_ref() {
+            Some(info) => {
+                button.clipboard().set_text(&info.to_report());
+                info!("Device info copied to clipboard.");
+            }
+            None => error!("No device info to copy yet."),
+        }
+    });
+
+    refresh_info(worker, device, &info_grid, &current);
+
+    let button_box = Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .halign(gtk::Align::End)
+        .build();
+    button_box.append(&refresh_button);
+    button_box.append(&copy_button);
+
+    page_box.append(&info_grid);
+    page_box.append(&button_box);
+
+    ScrolledWindow::builder()
+        .min_content_width(400)
+        .min_content_height(400)
+        .child(&page_box)
+        .build()
+}
@@ -0,0 +1,249 @@
+use gtk::cairo::Context;
+use gtk::{prelude::*, Box, DrawingArea, Grid, Label, Orientation, ScrolledWindow, ToggleButton};
+use log::info;
+use std::cell::{Cell, RefCell};
+use std::f64::consts::PI;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::devices::DeviceHandle;
+use crate::gui::utils::set_margins;
+use crate::monitor::{InputState, MonitorSession};
+
+const TOUCHPAD_WIDTH: f64 = 1920.0;
+const TOUCHPAD_HEIGHT: f64 = 1080.0;
+// Roughly what the IMU reports at rest/gentle motion, for scaling the bars
+const MOTION_RANGE: f64 = 8192.0;
+
+fn draw_stick(cr: &Context, width: f64, height: f64, (x, y): (u8, u8)) {
+    let radius = width.min(height) / 2.0 - 4.0;
+    let (cx, cy) = (width / 2.0, height / 2.0);
+
+    cr.set_source_rgb(0.5, 0.5, 0.5);
+    cr.arc(cx, cy, radius, 0.0, 2.0 * PI);
+    let _ = cr.stroke();
+
+    let dx = (f64::from(x) - 128.0) / 128.0 * radius;
+    let dy = (f64::from(y) - 128.0) / 128.0 * radius;
+    cr.set_source_rgb(0.2, 0.5, 0.9);
+    cr.arc(cx + dx, cy + dy, 6.0, 0.0, 2.0 * PI);
+    let _ = cr.fill();
+}
+
+fn draw_bar(cr: &Context, width: f64, height: f64, fraction: f64) {
+    cr.set_source_rgb(0.5, 0.5, 0.5);
+    cr.rectangle(0.5, 0.5, width - 1.0, height - 1.0);
+    let _ = cr.stroke();
+
+    cr.set_source_rgb(0.2, 0.5, 0.9);
+    let filled = fraction.clamp(0.0, 1.0) * height;
+    cr.rectangle(1.0, height - filled, width - 2.0, filled);
+    let _ = cr.fill();
+}
+
+/// Three bars centred on zero for an x/y/z reading
+fn draw_axes(cr: &Context, width: f64, height: f64, values: [i16; 3]) {
+    let bar_width = width / 3.0;
+    let mid = height / 2.0;
+
+    cr.set_source_rgb(0.5, 0.5, 0.5);
+    cr.move_to(0.0, mid);
+    cr.line_to(width, mid);
+    let _ = cr.stroke();
+
+    for (i, value) in values.iter().enumerate() {
+        let offset = (f64::from(*value) / MOTION_RANGE).clamp(-1.0, 1.0) * mid;
+        cr.set_source_rgb(0.2, 0.5, 0.9);
+        cr.rectangle(i as f64 * bar_width + 4.0, mid, bar_width - 8.0, -offset);
+        let _ = cr.fill();
+    }
+}
+
+fn draw_touchpad(cr: &Context, width: f64, height: f64, touches: &[Option<(u16, u16)>; 2]) {
+    cr.set_source_rgb(0.5, 0.5, 0.5);
+    cr.rectangle(0.5, 0.5, width - 1.0, height - 1.0);
+    let _ = cr.stroke();
+
+    cr.set_source_rgb(0.9, 0.4, 0.2);
+    for (x, y) in touches.iter().flatten() {
+        let px = f64::from(*x) / TOUCHPAD_WIDTH * width;
+        let py = f64::from(*y) / TOUCHPAD_HEIGHT * height;
+        cr.arc(px, py, 8.0, 0.0, 2.0 * PI);
+        let _ = cr.fill();
+    }
+}
+
+/// Drawing area that redraws from the shared state with `draw`
+fn state_area(
+    state: &Rc<RefCell<InputState>>,
+    width: i32,
+    height: i32,
+    draw: impl Fn(&Context, f64, f64, &InputState) + 'static,
+) -> DrawingArea {
+    let area = DrawingArea::builder()
+        .content_width(width)
+        .content_height(height)
+        .build();
+
+    let state = Rc::clone(state);
+    area.set_draw_func(move |_, cr, width, height| {
+        draw(cr, f64::from(width), f64::from(height), &state.borrow());
+    });
+
+    area
+}
+
+fn labeled(label: &str, widget: &impl IsA<gtk::Widget>) -> Box {
+    let labeled_box = Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .build();
+    labeled_box.append(&Label::new(Some(label)));
+    labeled_box.append(widget);
+    labeled_box
+}
+
+pub fn create_monitor_page(device: &DeviceHandle) -> ScrolledWindow {
+    let page_box = Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(10)
+        .build();
+    set_margins(&page_box, 12);
+
+    let state = Rc::new(RefCell::new(InputState::default()));
+
+    let left_stick = state_area(&state, 120, 120, |cr, w, h, s| {
+        draw_stick(cr, w, h, s.left_stick)
+    });
+    let right_stick = state_area(&state, 120, 120, |cr, w, h, s| {
+        draw_stick(cr, w, h, s.right_stick)
+    });
+    let left_trigger = state_area(&state, 30, 120, |cr, w, h, s| {
+        draw_bar(cr, w, h, f64::from(s.left_trigger) / 255.0)
+    });
+    let right_trigger = state_area(&state, 30, 120, |cr, w, h, s| {
+        draw_bar(cr, w, h, f64::from(s.right_trigger) / 255.0)
+    });
+    let touchpad = state_area(&state, 240, 135, |cr, w, h, s| {
+        draw_touchpad(cr, w, h, &s.touches)
+    });
+    let gyro = state_area(&state, 90, 120, |cr, w, h, s| draw_axes(cr, w, h, s.gyro));
+    let accel = state_area(&state, 90, 120, |cr, w, h, s| draw_axes(cr, w, h, s.accel));
+
+    let areas = [
+        left_stick.clone(),
+        right_stick.clone(),
+        left_trigger.clone(),
+        right_trigger.clone(),
+        touchpad.clone(),
+        gyro.clone(),
+        accel.clone(),
+    ];
+
+    let buttons_label = Label::new(Some("Buttons: none"));
+    buttons_label.set_halign(gtk::Align::Start);
+    let battery_label = Label::new(Some("Battery: unknown"));
+    battery_label.set_halign(gtk::Align::Start);
+
+    let grid = Grid::builder().row_spacing(10).column_spacing(20).build();
+    grid.attach(&labeled("L2", &left_trigger), 0, 0, 1, 1);
+    grid.attach(&labeled("Left Stick", &left_stick), 1, 0, 1, 1);
+    grid.attach(&labeled("Touchpad", &touchpad), 2, 0, 1, 1);
+    grid.attach(&labeled("Right Stick", &right_stick), 3, 0, 1, 1);
+    grid.attach(&labeled("R2", &right_trigger), 4, 0, 1, 1);
+    grid.attach(&labeled("Gyro", &gyro), 1, 1, 1, 1);
+    grid.attach(&labeled("Accel", &accel), 3, 1, 1, 1);
+
+    let start_button = ToggleButton::with_label("Start Monitor");
+    start_button.set_halign(gtk::Align::End);
+
+    // Bumped on every start/stop so an old session's loop knows to quit
+    let session = Rc::new(Cell::new(0u32));
+    // Dropping it stops the monitor
+    let running: Rc<RefCell<Option<MonitorSession>>> = Rc::new(RefCell::new(None));
+
+    // Switching devices rebuilds the pages, don't leave the old monitor running
+    page_box.connect_unrealize({
+        let session = Rc::clone(&session);
+        let running = Rc::clone(&running);
+        move |_| {
+            session.set(session.get() + 1);
+            running.borrow_mut().take();
+        }
+    });
+
+    start_button.connect_toggled({
+        let device = device.clone();
+        let state = Rc::clone(&state);
+        let buttons_label = buttons_label.clone();
+        let battery_label = battery_label.clone();
+        move |button| {
+            session.set(session.get() + 1);
+            if !button.is_active() {
+                running.borrow_mut().take();
+                button.set_label("Start Monitor");
+                return;
+            }
+
+            button.set_label("Stop Monitor");
+            info!("Monitoring {}", device.label());
+
+            let (sender, receiver) = async_channel::unbounded();
+            *running.borrow_mut() =
+                Some(MonitorSession::start(Arc::clone(&device.backend), sender));
+
+            let current = session.get();
+            let session = Rc::clone(&session);
+            let button = button.clone();
+            let state = Rc::clone(&state);
+            let areas = areas.clone();
+            let buttons_label = buttons_label.clone();
+            let battery_label = battery_label.clone();
+            gtk::glib::spawn_future_local(async move {
+                // Dropping the receiver stops the reader thread
+                while let Ok(event) = receiver.recv().await {
+                    if session.get() != current {
+                        break;
+                    }
+
+                    let mut state = state.borrow_mut();
+                    state.apply(event);
+
+                    let buttons = if state.buttons.is_empty() {
+                        "none".to_string()
+                    } else {
+                        state.buttons.iter().cloned().collect::<Vec<_>>().join(" ")
+                    };
+                    buttons_label.set_text(&format!("Buttons: {buttons}"));
+
+                    let battery = match (&state.battery, state.connected) {
+                        (_, false) => "disconnected".to_string(),
+                        (Some((level, charging)), true) => format!("{level}% ({charging})"),
+                        (None, true) => "unknown".to_string(),
+                    };
+                    battery_label.set_text(&format!("Battery: {battery}"));
+
+                    for area in &areas {
+                        area.queue_draw();
+                    }
+                }
+
+                // Ended or failed to start on its own, not by the button
+                if session.get() == current {
+                    button.set_active(false);
+                }
+            });
+        }
+    });
+
+    page_box.append(&grid);
+    page_box.append(&buttons_label);
+    page_box.append(&battery_label);
+    page_box.append(&start_button);
+
+    ScrolledWindow::builder()
+        .min_content_width(400)
+        .min_content_height(400)
+        .child(&page_box)
+        .build()
+}
@@ -0,0 +1,345 @@
+use gtk::{prelude::*, Adjustment, Entry, Grid, Label, ScrolledWindow, SpinButton, Switch};
+use log::error;
+use std::sync::{Arc, Mutex};
+
+use crate::backend::capabilities::Capabilities;
+use crate::gui::utils::set_margins;
+use crate::save::{save_settings, AppPaths};
+use crate::structs::Settings;
+use crate::worker::Worker;
+
+/// Saves `settings` after `update` has changed them
+pub fn update_settings(
+    settings: &Arc<Mutex<Settings>>,
+    app_paths: &Arc<AppPaths>,
+    update: impl FnOnce(&mut Settings),
+) {
+    match settings.lock() {
+        Ok(mut settings) => {
+            update(&mut settings);
+            if let Err(err) = save_settings(&settings, app_paths) {
+                error!("Failed to save settings: {err}");
+            }
+        }
+        Err(_) => error!("Failed to lock settings."),
+    }
+}
+
+pub fn create_preferences_page(
+    worker: &Worker,
+    settings: &Arc<Mutex<Settings>>,
+    capabilities: &Capabilities,
+    app_paths: &Arc<AppPaths>,
+) -> ScrolledWindow {
+    let current = settings.lock().map(|s| s.clone()).unwrap_or_default();
+
+    let grid = Grid::builder().row_spacing(10).column_spacing(10).build();
+    set_margins(&grid, 12);
+
+    let write_rate_adjustment =
+        Adjustment::new(f64::from(current.max_write_rate), 1.0, 60.0, 1.0, 5.0, 0.0);
+    let write_rate_spin = SpinButton::builder()
+        .adjustment(&write_rate_adjustment)
+        .tooltip_text("How often dragging a slider may update the controller")
+        .build();
+
+    write_rate_spin.connect_value_changed({
+        let worker = worker.clone();
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |spin| {
+            let rate = spin.value_as_int().max(1) as u32;
+            update_settings(&settings, &app_paths, |s| s.max_write_rate = rate);
+            worker.set_max_write_rate(rate);
+        }
+    });
+
+    let label = Label::new(Some("Max slider writes per second"));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, 0, 1, 1);
+    grid.attach(&write_rate_spin, 1, 0, 1, 1);
+
+    let binary_entry = Entry::builder()
+        .placeholder_text("dualsensectl on PATH")
+        .text(current.dualsensectl_path.as_deref().unwrap_or_default())
+        .hexpand(true)
+        .build();
+
+    binary_entry.connect_changed({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |entry| {
+            let path = entry.text().trim().to_string();
+            update_settings(&settings, &app_paths, |s| {
+                s.dualsensectl_path = (!path.is_empty()).then_some(path);
+            });
+        }
+    });
+
+    let version = if capabilities.is_empty() {
+        "Not found".to_string()
+    } else {
+        capabilities
+            .version
+            .clone()
+            .unwrap_or_else(|| "Unknown".to_string())
+    };
+
+    let binary_label = Label::new(Some("dualsensectl path (applies on restart)"));
+    binary_label.set_halign(gtk::Align::Start);
+    grid.attach(&binary_label, 0, 1, 1, 1);
+    grid.attach(&binary_entry, 1, 1, 1, 1);
+
+    let version_label = Label::new(Some("dualsensectl version"));
+    version_label.set_halign(gtk::Align::Start);
+    grid.attach(&version_label, 0, 2, 1, 1);
+    grid.attach(&Label::new(Some(&version)), 1, 2, 1, 1);
+
+    let power_off_switch = Switch::builder()
+        .active(current.power_off_on_quit)
+        .halign(gtk::Align::Start)
+        .build();
+
+    power_off_switch.connect_active_notify({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |switch| {
+            let active = switch.is_active();
+            update_settings(&settings, &app_paths, |s| s.power_off_on_quit = active);
+        }
+    });
+
+    let power_off_label = Label::new(Some("Power off controllers on quit"));
+    power_off_label.set_halign(gtk::Align::Start);
+    grid.attach(&power_off_label, 0, 3, 1, 1);
+    grid.attach(&power_off_switch, 1, 3, 1, 1);
+
+    let poll_adjustment = Adjustment::new(
+        f64::from(current.battery_poll_interval),
+        5.0,
+        600.0,
+        5.0,
+        30.0,
+        0.0,
+    );
+    let poll_spin = SpinButton::builder().adjustment(&poll_adjustment).build();
+
+    poll_spin.connect_value_changed({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |spin| {
+            let interval = spin.value_as_int().max(1) as u32;
+            update_settings(&settings, &app_paths, |s| {
+                s.battery_poll_interval = interval
+            });
+        }
+    });
+
+    let poll_label = Label::new(Some("Battery check interval (seconds)"));
+    poll_label.set_halign(gtk::Align::Start);
+    grid.attach(&poll_label, 0, 4, 1, 1);
+    grid.attach(&poll_spin, 1, 4, 1, 1);
+
+    let thresholds_entry = Entry::builder()
+        .text(
+            current
+                .low_battery_thresholds
+                .iter()
+                .map(u8::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+        .tooltip_text("Comma separated percentages, e.g. 20, 10, 5")
+        .build();
+
+    thresholds_entry.connect_changed({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |entry| {
+            let thresholds: Option<Vec<u8>> = entry
+                .text()
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(|s| s.parse().ok().filter(|t| *t <= 100))
+                .collect();
+            match thresholds {
+                Some(thresholds) => {
+                    entry.remove_css_class("error");
+                    update_settings(&settings, &app_paths, |s| {
+                        s.low_battery_thresholds = thresholds
+                    });
+                }
+                None => entry.add_css_class("error"),
+            }
+        }
+    });
+
+    let thresholds_label = Label::new(Some("Low battery warnings (%)"));
+    thresholds_label.set_halign(gtk::Align::Start);
+    grid.attach(&thresholds_label, 0, 5, 1, 1);
+    grid.attach(&thresholds_entry, 1, 5, 1, 1);
+
+    let charged_switch = Switch::builder()
+        .active(current.notify_when_charged)
+        .halign(gtk::Align::Start)
+        .build();
+
+    charged_switch.connect_active_notify({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |switch| {
+            let active = switch.is_active();
+            update_settings(&settings, &app_paths, |s| s.notify_when_charged = active);
+        }
+    });
+
+    let charged_label = Label::new(Some("Notify when fully charged"));
+    charged_label.set_halign(gtk::Align::Start);
+    grid.attach(&charged_label, 0, 6, 1, 1);
+    grid.attach(&charged_switch, 1, 6, 1, 1);
+
+    let dsx_switch = Switch::builder()
+        .active(current.dsx_enabled)
+        .halign(gtk::Align::Start)
+        .build();
+
+    dsx_switch.connect_active_notify({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |switch| {
+            let active = switch.is_active();
+            update_settings(&settings, &app_paths, |s| s.dsx_enabled = active);
+        }
+    });
+
+    let dsx_label = Label::new(Some(
+        "Accept DSX packets from game mods (applies on restart)",
+    ));
+    dsx_label.set_halign(gtk::Align::Start);
+    grid.attach(&dsx_label, 0, 7, 1, 1);
+    grid.attach(&dsx_switch, 1, 7, 1, 1);
+
+    let dsx_port_adjustment =
+        Adjustment::new(f64::from(current.dsx_port), 1024.0, 65535.0, 1.0, 10.0, 0.0);
+    let dsx_port_spin = SpinButton::builder()
+        .adjustment(&dsx_port_adjustment)
+        .build();
+
+    dsx_port_spin.connect_value_changed({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |spin| {
+            let port = spin.value_as_int().clamp(1024, 65535) as u16;
+            update_settings(&settings, &app_paths, |s| s.dsx_port = port);
+        }
+    });
+
+    let dsx_port_label = Label::new(Some("DSX UDP port"));
+    dsx_port_label.set_halign(gtk::Align::Start);
+    grid.attach(&dsx_port_label, 0, 8, 1, 1);
+    grid.attach(&dsx_port_spin, 1, 8, 1, 1);
+
+    let osc_switch = Switch::builder()
+        .active(current.osc_enabled)
+        .halign(gtk::Align::Start)
+        .build();
+
+    osc_switch.connect_active_notify({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |switch| {
+            let active = switch.is_active();
+            update_settings(&settings, &app_paths, |s| s.osc_enabled = active);
+        }
+    });
+
+    let osc_label = Label::new(Some("Accept OSC messages (applies on restart)"));
+    osc_label.set_halign(gtk::Align::Start);
+    grid.attach(&osc_label, 0, 9, 1, 1);
+    grid.attach(&osc_switch, 1, 9, 1, 1);
+
+    let osc_port_adjustment =
+        Adjustment::new(f64::from(current.osc_port), 1024.0, 65535.0, 1.0, 10.0, 0.0);
+    let osc_port_spin = SpinButton::builder()
+        .adjustment(&osc_port_adjustment)
+        .build();
+
+    osc_port_spin.connect_value_changed({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |spin| {
+            let port = spin.value_as_int().clamp(1024, 65535) as u16;
+            update_settings(&settings, &app_paths, |s| s.osc_port = port);
+        }
+    });
+
+    let osc_port_label = Label::new(Some("OSC UDP port"));
+    osc_port_label.set_halign(gtk::Align::Start);
+    grid.attach(&osc_port_label, 0, 10, 1, 1);
+    grid.attach(&osc_port_spin, 1, 10, 1, 1);
+
+    let osc_all_switch = Switch::builder()
+        .active(current.osc_listen_all)
+        .halign(gtk::Align::Start)
+        .build();
+
+    osc_all_switch.connect_active_notify({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |switch| {
+            let active = switch.is_active();
+            update_settings(&settings, &app_paths, |s| s.osc_listen_all = active);
+        }
+    });
+
+    let osc_all_label = Label::new(Some("Accept OSC from other devices on the network"));
+    osc_all_label.set_halign(gtk::Align::Start);
+    grid.attach(&osc_all_label, 0, 11, 1, 1);
+    grid.attach(&osc_all_switch, 1, 11, 1, 1);
+
+    let web_switch = Switch::builder()
+        .active(current.web_enabled)
+        .halign(gtk::Align::Start)
+        .build();
+
+    web_switch.connect_active_notify({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |switch| {
+            let active = switch.is_active();
+            update_settings(&settings, &app_paths, |s| s.web_enabled = active);
+        }
+    });
+
+    let web_label = Label::new(Some("Serve the HTTP/WebSocket API (applies on restart)"));
+    web_label.set_halign(gtk::Align::Start);
+    grid.attach(&web_label, 0, 12, 1, 1);
+    grid.attach(&web_switch, 1, 12, 1, 1);
+
+    let web_port_adjustment =
+        Adjustment::new(f64::from(current.web_port), 1024.0, 65535.0, 1.0, 10.0, 0.0);
+    let web_port_spin = SpinButton::builder()
+        .adjustment(&web_port_adjustment)
+        .build();
+
+    web_port_spin.connect_value_changed({
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        move |spin| {
+            let port = spin.value_as_int().clamp(1024, 65535) as u16;
+            update_settings(&settings, &app_paths, |s| s.web_port = port);
+        }
+    });
+
+    let web_port_label = Label::new(Some("HTTP API port"));
+    web_port_label.set_halign(gtk::Align::Start);
+    grid.attach(&web_port_label, 0, 13, 1, 1);
+    grid.attach(&web_port_spin, 1, 13, 1, 1);
+
+    ScrolledWindow::builder()
+        .min_content_width(400)
+        .min_content_height(400)
+        .child(&grid)
+        .build()
+}
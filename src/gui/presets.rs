@@ -1,11 +1,14 @@
 use gtk::{prelude::*, ScrolledWindow};
 use gtk::{Box, Label, Orientation, Separator};
 use log::{error, info};
-use std::process::Command;
-use std::sync::{Arc, Mutex};
-use std::thread;
 
-use crate::structs::{Controller, Trigger, TriggerEffect};
+use crate::backend::ControllerBackend;
+use crate::devices::DeviceHandle;
+use crate::dualsensectl::change_triggers;
+use crate::error::DualsenseError;
+use crate::gui::utils::send_command;
+use crate::structs::{Controller, Trigger};
+use crate::worker::{DeviceCommand, Worker};
 
 pub struct Preset {
     pub name: &'static str,
@@ -151,152 +154,44 @@ pub fn get_presets() -> Vec<Preset> {
     ]
 }
 
-pub fn run_command(command: &str, controller: &mut Controller) -> Result<(), String> {
-    info!("Executing command: {}", command);
-
+/// Parses a `dualsensectl trigger ...` preset command into a `Trigger`
+pub fn parse_trigger_command(command: &str) -> Result<Trigger, DualsenseError> {
     let parts: Vec<&str> = command.split_whitespace().collect();
-    if parts.is_empty() {
-        return Err("Command is empty".to_string());
+    match parts[..] {
+        ["dualsensectl", "trigger", side, mode, ref values @ ..] => {
+            Trigger::parse(side, mode, values)
+        }
+        [] => Err(DualsenseError::InvalidArgument(
+            "Command is empty".to_string(),
+        )),
+        _ => Err(DualsenseError::InvalidArgument(format!(
+            "Unsupported command: {command}"
+        ))),
     }
+}
 
-    let output = Command::new(parts[0])
-        .args(&parts[1..])
-        .output()
-        .map_err(|e| format!("Failed to execute command: {e}"))?;
+pub fn run_command(
+    backend: &dyn ControllerBackend,
+    command: &str,
+    controller: &mut Controller,
+) -> Result<(), DualsenseError> {
+    info!("Executing command: {}", command);
 
-    if output.status.success() {
-        info!("Command succeeded: {}", command);
+    let trigger = parse_trigger_command(command)?;
+    change_triggers(backend, trigger, controller)?;
 
-        if parts.len() > 2 && parts[0] == "dualsensectl" && parts[1] == "trigger" {
-            let side = parts[2].to_string();
-            match parts[3] {
-                "feedback" if parts.len() >= 6 => {
-                    controller.trigger = Trigger {
-                        side,
-                        effect: TriggerEffect::Feedback {
-                            position: parts[4].parse().unwrap_or(0),
-                            strength: parts[5].parse().unwrap_or(0),
-                        },
-                    };
-                }
-                "weapon" if parts.len() >= 7 => {
-                    controller.trigger = Trigger {
-                        side,
-                        effect: TriggerEffect::Weapon {
-                            start: parts[4].parse().unwrap_or(0),
-                            stop: parts[5].parse().unwrap_or(0),
-                            strength: parts[6].parse().unwrap_or(0),
-                        },
-                    };
-                }
-                "bow" if parts.len() >= 8 => {
-                    controller.trigger = Trigger {
-                        side,
-                        effect: TriggerEffect::Bow {
-                            start: parts[4].parse().unwrap_or(0),
-                            stop: parts[5].parse().unwrap_or(0),
-                            strength: parts[6].parse().unwrap_or(0),
-                            snapforce: parts[7].parse().unwrap_or(0),
-                        },
-                    };
-                }
-                "galloping" if parts.len() >= 8 => {
-                    controller.trigger = Trigger {
-                        side,
-                        effect: TriggerEffect::Galloping {
-                            start: parts[4].parse().unwrap_or(0),
-                            stop: parts[5].parse().unwrap_or(0),
-                            first_foot: parts[6].parse().unwrap_or(0),
-                            second_foot: parts[7].parse().unwrap_or(0),
-                            frequency: parts[8].parse().unwrap_or(0),
-                        },
-                    };
-                }
-                "machine" if parts.len() >= 9 => {
-                    controller.trigger = Trigger {
-                        side,
-                        effect: TriggerEffect::Machine {
-                            start: parts[4].parse().unwrap_or(0),
-                            stop: parts[5].parse().unwrap_or(0),
-                            strength_a: parts[6].parse().unwrap_or(0),
-                            strength_b: parts[7].parse().unwrap_or(0),
-                            frequency: parts[8].parse().unwrap_or(0),
-                            period: parts[9].parse().unwrap_or(0),
-                        },
-                    };
-                }
-                "vibration" if parts.len() >= 7 => {
-                    controller.trigger = Trigger {
-                        side,
-                        effect: TriggerEffect::Vibration {
-                            position: parts[4].parse().unwrap_or(0),
-                            amplitude: parts[5].parse().unwrap_or(0),
-                            frequency: parts[6].parse().unwrap_or(0),
-                        },
-                    };
-                }
-                "feedback-raw" if parts.len() >= 14 => {
-                    let mut strength = [0u8; 10];
-                    for i in 0..10 {
-                        strength[i] = parts[4 + i].parse().unwrap_or(0);
-                    }
-                    controller.trigger = Trigger {
-                        side,
-                        effect: TriggerEffect::FeedbackRaw { strength },
-                    };
-                }
-                "vibration-raw" if parts.len() >= 14 => {
-                    let mut amplitude = [0u8; 10];
-                    for i in 0..10 {
-                        amplitude[i] = parts[4 + i].parse().unwrap_or(0);
-                    }
-                    controller.trigger = Trigger {
-                        side,
-                        effect: TriggerEffect::VibrationRaw {
-                            amplitude,
-                            frequency: parts[14].parse().unwrap_or(0),
-                        },
-                    };
-                }
-                "off" => {
-                    controller.trigger = Trigger {
-                        side,
-                        effect: TriggerEffect::Off,
-                    };
-                }
-                "mode" => {
-                    let params = parts[4..].iter().map(|s| (*s).to_string()).collect();
-                    controller.trigger = Trigger {
-                        side,
-                        effect: TriggerEffect::Mode { params },
-                    };
-                }
-                _ => error!("Unsupported trigger command: {}", command),
-            }
-        }
-
-        Ok(())
-    } else {
-        error!(
-            "Command failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        Err(format!(
-            "Command failed with status {}: {}",
-            output.status,
-            String::from_utf8_lossy(&output.stderr)
-        ))
-    }
+    info!("Command succeeded: {}", command);
+    Ok(())
 }
 
-pub fn apply_preset(preset: &Preset, controller: &mut Controller) {
-    match run_command(preset.command, controller) {
+pub fn apply_preset(backend: &dyn ControllerBackend, preset: &Preset, controller: &mut Controller) {
+    match run_command(backend, preset.command, controller) {
         Ok(()) => info!("Preset '{}' applied successfully.", preset.name),
         Err(err) => error!("Failed to apply preset '{}': {}", preset.name, err),
     }
 }
 
-pub fn create_presets_page(controller: &Arc<Mutex<Controller>>) -> ScrolledWindow {
+pub fn create_presets_page(worker: &Worker, device: &DeviceHandle) -> ScrolledWindow {
     let presets_box = Box::builder()
         .orientation(Orientation::Vertical)
         .spacing(10)
@@ -329,22 +224,29 @@ pub fn create_presets_page(controller: &Arc<Mutex<Controller>>) -> ScrolledWindo
         let button = gtk::Button::with_label(preset.name);
         button.set_tooltip_text(Some(preset.description));
 
-        let command = preset.command.to_string();
-        let controller_clone = Arc::clone(controller);
+        let name = preset.name;
+        let command = preset.command;
+        let worker = worker.clone();
+        let device = device.clone();
 
         button.connect_clicked(move |_| {
-            let controller_clone_inner = Arc::clone(&controller_clone);
-            let command_clone = command.clone();
-
-            thread::spawn(move || {
-                if let Ok(mut ctrl) = controller_clone_inner.lock() {
-                    if let Err(err) = run_command(&command_clone, &mut ctrl) {
-                        eprintln!("Failed to execute command '{command_clone}': {err}");
-                    }
-                } else {
-                    eprintln!("Failed to lock controller for preset '{command_clone}'.");
+            let trigger = match parse_trigger_command(command) {
+                Ok(trigger) => trigger,
+                Err(err) => {
+                    error!("Failed to parse preset '{}': {}", name, err);
+                    return;
                 }
-            });
+            };
+
+            send_command(
+                &worker,
+                &device,
+                DeviceCommand::Trigger(trigger),
+                move |reply| match reply.result {
+                    Ok(_) => info!("Preset '{}' applied successfully.", name),
+                    Err(err) => error!("Failed to apply preset '{}': {}", name, err),
+                },
+            );
         });
 
         presets_box.append(&button);
@@ -352,3 +254,26 @@ pub fn create_presets_page(controller: &Arc<Mutex<Controller>>) -> ScrolledWindo
 
     scrolled_window
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_preset_parses() {
+        for preset in get_presets() {
+            assert!(
+                parse_trigger_command(preset.command).is_ok(),
+                "{}",
+                preset.command
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_commands() {
+        assert!(parse_trigger_command("").is_err());
+        assert!(parse_trigger_command("dualsensectl lightbar 255 0 0").is_err());
+        assert!(parse_trigger_command("dualsensectl trigger both").is_err());
+    }
+}
@@ -1,33 +1,351 @@
 use gtk::gio::File;
-use gtk::{prelude::*, Button, DropDown, FileDialog, Grid, ScrolledWindow};
-use serde_json;
+use gtk::{
+    prelude::*, Box, Button, Entry, FileDialog, Grid, Label, ListBox, MenuButton, Orientation,
+    Popover, ScrolledWindow, SelectionMode,
+};
+use log::{error, info};
+use std::fmt::Display;
 use std::fs;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
-use crate::save::AppPaths;
-use crate::structs::Controller;
+use crate::devices::DeviceHandle;
+use crate::gui::auto_profiles::{create_auto_profiles_editor, populate_rules};
+use crate::gui::preferences::update_settings;
+use crate::gui::utils::{confirm, send_command};
+use crate::save::{
+    controller_from_json, controller_to_json, delete_profile, duplicate_profile, list_profiles,
+    load_profile, rename_profile, save_profile, AppPaths,
+};
+use crate::structs::{Controller, Settings};
+use crate::worker::{DeviceCommand, Output, Worker};
 
-fn apply_profile_from_path(path: &std::path::Path, controller: &Arc<Mutex<Controller>>) {
+/// What the profile rows need to act on
+struct ProfilesPage {
+    worker: Worker,
+    device: DeviceHandle,
+    settings: Arc<Mutex<Settings>>,
+    app_paths: Arc<AppPaths>,
+    list: ListBox,
+    rules_list: ListBox,
+    status: Label,
+}
+
+impl ProfilesPage {
+    fn show_error(&self, what: &str, err: impl Display) {
+        error!("{what}: {err}");
+        self.status.set_text(&format!("{what}: {err}"));
+    }
+
+    fn default_profile(&self) -> Option<String> {
+        self.settings
+            .lock()
+            .ok()
+            .and_then(|s| s.default_profile.clone())
+    }
+
+    /// Rebuilds the profile list and the rules that point at profiles
+    fn refresh(self: &Rc<Self>) {
+        populate_profiles(self);
+        populate_rules(&self.rules_list, &self.settings, &self.app_paths);
+    }
+}
+
+/// Pushes `profile` to the pad and shows how it went in `status`
+fn apply_profile(
+    worker: &Worker,
+    device: &DeviceHandle,
+    name: &str,
+    profile: Controller,
+    status: &Label,
+) {
+    let name = name.to_string();
+    let status = status.clone();
+    send_command(
+        worker,
+        device,
+        DeviceCommand::Apply(profile),
+        move |reply| match reply.result {
+            Ok(Output::Applied(report)) if report.succeeded() => {
+                info!("Profile '{}' applied.", name);
+                status.set_text(&format!("Applied '{name}'"));
+            }
+            Ok(Output::Applied(report)) => {
+                error!("Failed to apply profile '{}': {}", name, report);
+                status.set_text(&format!("Couldn't apply '{name}': {report}"));
+            }
+            Ok(_) => {}
+            Err(err) => {
+                error!("Failed to apply profile '{}': {}", name, err);
+                status.set_text(&format!("Couldn't apply '{name}': {err}"));
+            }
+        },
+    );
+}
+
+fn apply_profile_from_path(
+    path: &std::path::Path,
+    worker: &Worker,
+    device: &DeviceHandle,
+    status: &Label,
+) {
     if let Ok(contents) = fs::read_to_string(path) {
-        match serde_json::from_str::<Controller>(&contents) {
+        match controller_from_json(&contents) {
             Ok(profile) => {
-                let mut controller_lock = controller.lock().unwrap();
-                *controller_lock = profile;
-                println!("Controller state updated from imported profile.");
+                let name = path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                apply_profile(worker, device, &name, profile, status);
             }
-            Err(err) => eprintln!("Failed to parse imported profile: {}", err),
+            Err(err) => error!("Failed to parse imported profile: {}", err),
         }
     } else {
-        eprintln!("Failed to read profile file.");
+        error!("Failed to read profile file.");
+    }
+}
+
+fn apply_saved_profile(page: &ProfilesPage, name: &str) {
+    match load_profile(name, &page.app_paths) {
+        Ok(profile) => apply_profile(&page.worker, &page.device, name, profile, &page.status),
+        Err(err) => page.show_error(&format!("Couldn't load '{name}'"), err),
+    }
+}
+
+/// Renames the file and every setting that refers to it
+fn rename(page: &Rc<ProfilesPage>, old: &str, new: &str) {
+    let new = new.trim();
+    if new == old {
+        return;
+    }
+    if let Err(err) = rename_profile(old, new, &page.app_paths) {
+        page.show_error(&format!("Couldn't rename '{old}'"), err);
+        return;
+    }
+
+    update_settings(&page.settings, &page.app_paths, |s| {
+        if s.default_profile.as_deref() == Some(old) {
+            s.default_profile = Some(new.to_string());
+        }
+        for rule in &mut s.auto_profile_rules {
+            if rule.profile == old {
+                rule.profile = new.to_string();
+            }
+        }
+    });
+    page.status.set_text(&format!("Renamed '{old}' to '{new}'"));
+    page.refresh();
+}
+
+fn delete(page: &Rc<ProfilesPage>, name: &str) {
+    let list = page.list.clone();
+    let page = Rc::clone(page);
+    let name = name.to_string();
+    confirm(
+        &list,
+        &format!("Delete profile '{name}'?"),
+        "The profile file is removed and can't be brought back.",
+        "Delete",
+        move || {
+            if let Err(err) = delete_profile(&name, &page.app_paths) {
+                page.show_error(&format!("Couldn't delete '{name}'"), err);
+                return;
+            }
+            update_settings(&page.settings, &page.app_paths, |s| {
+                if s.default_profile.as_deref() == Some(name.as_str()) {
+                    s.default_profile = None;
+                }
+            });
+            page.status.set_text(&format!("Deleted '{name}'"));
+            page.refresh();
+        },
+    );
+}
+
+fn duplicate(page: &Rc<ProfilesPage>, name: &str) {
+    match duplicate_profile(name, &page.app_paths) {
+        Ok(copy) => {
+            page.status
+                .set_text(&format!("Copied '{name}' to '{copy}'"));
+            page.refresh();
+        }
+        Err(err) => page.show_error(&format!("Couldn't duplicate '{name}'"), err),
+    }
+}
+
+/// Marks `name` as the profile applied at startup, `None` clears it
+fn set_default(page: &Rc<ProfilesPage>, name: Option<String>) {
+    let text = match &name {
+        Some(name) => format!("'{name}' is applied at startup"),
+        None => "No profile is applied at startup".to_string(),
+    };
+    update_settings(&page.settings, &page.app_paths, |s| {
+        s.default_profile = name
+    });
+    page.status.set_text(&text);
+    page.refresh();
+}
+
+/// Saves the pad's current settings as `name`, asking first if that would
+/// replace a profile
+fn save_current(page: &Rc<ProfilesPage>, name: &str) {
+    let name = name.trim().to_string();
+    let save = {
+        let page = Rc::clone(page);
+        let name = name.clone();
+        move || {
+            let controller = page
+                .device
+                .controller
+                .lock()
+                .map(|c| c.clone())
+                .unwrap_or_default();
+            match save_profile(&name, &controller, &page.app_paths) {
+                Ok(()) => {
+                    page.status.set_text(&format!("Saved '{name}'"));
+                    page.refresh();
+                }
+                Err(err) => page.show_error(&format!("Couldn't save '{name}'"), err),
+            }
+        }
+    };
+
+    if list_profiles(&page.app_paths).contains(&name) {
+        confirm(
+            &page.list,
+            &format!("Replace profile '{name}'?"),
+            "It will be overwritten with the current settings.",
+            "Replace",
+            save,
+        );
+    } else {
+        save();
+    }
+}
+
+fn icon_button(icon: &str, tooltip: &str) -> Button {
+    let button = Button::from_icon_name(icon);
+    button.set_tooltip_text(Some(tooltip));
+    button
+}
+
+/// Rename button with a popover holding the new name
+fn rename_button(page: &Rc<ProfilesPage>, name: &str) -> MenuButton {
+    let entry = Entry::builder().text(name).build();
+    let confirm_button = Button::with_label("Rename");
+
+    let popover_box = Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    popover_box.append(&entry);
+    popover_box.append(&confirm_button);
+    let popover = Popover::builder().child(&popover_box).build();
+
+    let do_rename = {
+        let page = Rc::clone(page);
+        let name = name.to_string();
+        let entry = entry.clone();
+        let popover = popover.clone();
+        move || {
+            popover.popdown();
+            rename(&page, &name, &entry.text());
+        }
+    };
+    entry.connect_activate({
+        let do_rename = do_rename.clone();
+        move |_| do_rename()
+    });
+    confirm_button.connect_clicked(move |_| do_rename());
+
+    MenuButton::builder()
+        .icon_name("document-edit-symbolic")
+        .tooltip_text("Rename")
+        .popover(&popover)
+        .build()
+}
+
+fn create_profile_row(page: &Rc<ProfilesPage>, name: &str, is_default: bool) -> Box {
+    let row = Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+
+    let label = Label::new(Some(name));
+    label.set_halign(gtk::Align::Start);
+    label.set_hexpand(true);
+
+    let apply_button = Button::with_label("Apply");
+    apply_button.connect_clicked({
+        let page = Rc::clone(page);
+        let name = name.to_string();
+        move |_| apply_saved_profile(&page, &name)
+    });
+
+    let default_button = if is_default {
+        icon_button("starred-symbolic", "Applied at startup, click to unset")
+    } else {
+        icon_button("non-starred-symbolic", "Apply at startup")
+    };
+    default_button.connect_clicked({
+        let page = Rc::clone(page);
+        let name = (!is_default).then(|| name.to_string());
+        move |_| set_default(&page, name.clone())
+    });
+
+    let duplicate_button = icon_button("edit-copy-symbolic", "Duplicate");
+    duplicate_button.connect_clicked({
+        let page = Rc::clone(page);
+        let name = name.to_string();
+        move |_| duplicate(&page, &name)
+    });
+
+    let delete_button = icon_button("user-trash-symbolic", "Delete");
+    delete_button.connect_clicked({
+        let page = Rc::clone(page);
+        let name = name.to_string();
+        move |_| delete(&page, &name)
+    });
+
+    row.append(&label);
+    row.append(&apply_button);
+    row.append(&default_button);
+    row.append(&rename_button(page, name));
+    row.append(&duplicate_button);
+    row.append(&delete_button);
+    row
+}
+
+fn populate_profiles(page: &Rc<ProfilesPage>) {
+    page.list.remove_all();
+
+    let profiles = list_profiles(&page.app_paths);
+    if profiles.is_empty() {
+        page.list.append(&Label::new(Some(
+            "No profiles yet, save the current settings below",
+        )));
+        return;
+    }
+
+    let default_profile = page.default_profile();
+    for name in &profiles {
+        let is_default = default_profile.as_deref() == Some(name.as_str());
+        page.list
+            .append(&create_profile_row(page, name, is_default));
     }
 }
 
 pub fn create_profiles_page(
-    controller: &Arc<Mutex<Controller>>,
+    worker: &Worker,
+    device: &DeviceHandle,
+    settings: &Arc<Mutex<Settings>>,
     app_paths: &Arc<AppPaths>,
 ) -> ScrolledWindow {
-    let controller = Arc::clone(controller);
+    let controller = Arc::clone(&device.controller);
     let app_paths = Arc::clone(app_paths);
+    let status_label = Label::new(None);
+    status_label.set_halign(gtk::Align::Start);
+    status_label.set_wrap(true);
 
     let presets_grid = Grid::builder()
         .row_spacing(10)
@@ -38,33 +356,47 @@ pub fn create_profiles_page(
         .margin_end(12)
         .build();
 
-    let export_button = Button::with_label("Export Profile");
-    let import_button = Button::with_label("Import Profile");
-    let dropdown = DropDown::builder()
-        .model(&gtk::StringList::new(&["Profile1", "Profile2", "Profile3"]))
-        .selected(0)
+    let profiles_list = ListBox::builder()
+        .selection_mode(SelectionMode::None)
         .build();
+    let (auto_profiles_editor, rules_list) = create_auto_profiles_editor(settings, &app_paths);
 
-    let dropdown_clone = dropdown.clone();
+    let page = Rc::new(ProfilesPage {
+        worker: worker.clone(),
+        device: device.clone(),
+        settings: Arc::clone(settings),
+        app_paths: Arc::clone(&app_paths),
+        list: profiles_list.clone(),
+        rules_list,
+        status: status_label.clone(),
+    });
+    populate_profiles(&page);
+
+    let name_entry = Entry::builder()
+        .placeholder_text("New profile name")
+        .hexpand(true)
+        .build();
+    let save_button = Button::with_label("Save Current as Profile");
 
-    let refresh_dropdown = {
-        let app_paths = Arc::clone(&app_paths);
+    let save_from_entry = {
+        let page = Rc::clone(&page);
+        let name_entry = name_entry.clone();
         move || {
-            let profiles_dir = &app_paths.profiles;
-            if let Ok(entries) = fs::read_dir(profiles_dir) {
-                let profiles: Vec<String> = entries
-                    .filter_map(|entry| entry.ok())
-                    .filter_map(|entry| entry.path().file_stem()?.to_str().map(|s| s.to_string()))
-                    .collect();
-
-                dropdown_clone.set_model(Some(&gtk::StringList::new(
-                    &profiles.iter().map(|s| s.as_str()).collect::<Vec<&str>>(),
-                )));
+            if name_entry.text().trim().is_empty() {
+                return;
             }
+            save_current(&page, &name_entry.text());
+            name_entry.set_text("");
         }
     };
+    name_entry.connect_activate({
+        let save_from_entry = save_from_entry.clone();
+        move |_| save_from_entry()
+    });
+    save_button.connect_clicked(move |_| save_from_entry());
 
-    refresh_dropdown();
+    let export_button = Button::with_label("Export Profile");
+    let import_button = Button::with_label("Import Profile");
 
     {
         let controller_export = Arc::clone(&controller);
@@ -83,41 +415,37 @@ pub fn create_profiles_page(
                     Ok(file) => {
                         if let Some(path) = file.path() {
                             let controller_lock = controller_for_open.lock().unwrap();
-                            let json = match serde_json::to_string_pretty(&*controller_lock) {
+                            let json = match controller_to_json(&controller_lock) {
                                 Ok(json) => json,
                                 Err(err) => {
-                                    eprintln!("Failed to serialize Controller: {}", err);
+                                    error!("Failed to serialize Controller: {}", err);
                                     return;
                                 }
                             };
 
                             match fs::write(&path, json) {
-                                Ok(_) => println!("Profile exported to {}", path.display()),
-                                Err(err) => eprintln!("Failed to export profile: {}", err),
+                                Ok(_) => info!("Profile exported to {}", path.display()),
+                                Err(err) => error!("Failed to export profile: {}", err),
                             }
                         } else {
-                            eprintln!("No valid path provided.");
+                            error!("No valid path provided.");
                         }
                     }
-                    Err(err) => eprintln!("Error exporting profile: {}", err),
+                    Err(err) => error!("Error exporting profile: {}", err),
                 },
             );
         });
     }
 
     {
-        let app_paths_import = Arc::clone(&app_paths);
-        let controller_import = Arc::clone(&controller);
-        let refresh_dropdown_import = refresh_dropdown.clone();
+        let page_import = Rc::clone(&page);
 
         import_button.connect_clicked(move |_| {
             let dialog = FileDialog::new();
             dialog.set_modal(true);
-            dialog.set_initial_folder(Some(&File::for_path(&app_paths_import.profiles)));
+            dialog.set_initial_folder(Some(&File::for_path(&page_import.app_paths.profiles)));
 
-            let controller_for_open = Arc::clone(&controller_import);
-            let app_paths_for_open = Arc::clone(&app_paths_import);
-            let refresh_dropdown_for_open = refresh_dropdown_import.clone();
+            let page_for_open = Rc::clone(&page_import);
 
             dialog.open(
                 None::<&gtk::Window>,
@@ -126,77 +454,55 @@ pub fn create_profiles_page(
                     Ok(file) => {
                         if let Some(path) = file.path() {
                             if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
-                                let target_path = app_paths_for_open.profiles.join(
+                                let page = &page_for_open;
+                                let target_path = page.app_paths.profiles.join(
                                     path.file_name()
                                         .unwrap_or_else(|| std::ffi::OsStr::new("profile.json")),
                                 );
 
                                 // Check if file is already in the profiles directory
-                                if path
-                                    .parent()
-                                    .map_or(false, |p| p == app_paths_for_open.profiles)
-                                {
-                                    apply_profile_from_path(&path, &controller_for_open);
-                                    refresh_dropdown_for_open();
+                                if path.parent().is_some_and(|p| p == page.app_paths.profiles) {
+                                    apply_profile_from_path(
+                                        &path,
+                                        &page.worker,
+                                        &page.device,
+                                        &page.status,
+                                    );
+                                    page.refresh();
                                 } else {
                                     match fs::copy(&path, &target_path) {
                                         Ok(_) => {
                                             apply_profile_from_path(
                                                 &target_path,
-                                                &controller_for_open,
+                                                &page.worker,
+                                                &page.device,
+                                                &page.status,
                                             );
-                                            refresh_dropdown_for_open();
+                                            page.refresh();
                                         }
-                                        Err(err) => eprintln!("Failed to import profile: {}", err),
+                                        Err(err) => error!("Failed to import profile: {}", err),
                                     }
                                 }
                             } else {
-                                eprintln!("Invalid file type. Please select a .json file.");
+                                error!("Invalid file type. Please select a .json file.");
                             }
                         } else {
-                            eprintln!("No valid path provided.");
+                            error!("No valid path provided.");
                         }
                     }
-                    Err(err) => eprintln!("Error importing profile: {}", err),
+                    Err(err) => error!("Error importing profile: {}", err),
                 },
             );
         });
     }
 
-    {
-        let app_paths_apply = Arc::clone(&app_paths);
-        let controller_apply = Arc::clone(&controller);
-
-        dropdown.connect_selected_notify(move |dropdown| {
-            if let Some(model) = dropdown.model() {
-                let selected_idx = dropdown.selected();
-                if let Some(selected_name) = model
-                    .downcast_ref::<gtk::StringList>()
-                    .and_then(|string_list| string_list.string(selected_idx))
-                {
-                    let profile_path = app_paths_apply
-                        .profiles
-                        .join(format!("{}.json", selected_name));
-                    if let Ok(contents) = fs::read_to_string(profile_path) {
-                        match serde_json::from_str::<Controller>(&contents) {
-                            Ok(profile) => {
-                                let mut controller_lock = controller_apply.lock().unwrap();
-                                *controller_lock = profile;
-                                println!("Profile '{}' applied.", selected_name);
-                            }
-                            Err(err) => eprintln!("Failed to load profile: {}", err),
-                        }
-                    } else {
-                        eprintln!("Failed to read profile file.");
-                    }
-                }
-            }
-        });
-    }
-
-    presets_grid.attach(&export_button, 0, 0, 1, 1);
-    presets_grid.attach(&import_button, 1, 0, 1, 1);
-    presets_grid.attach(&dropdown, 0, 1, 2, 1);
+    presets_grid.attach(&profiles_list, 0, 0, 2, 1);
+    presets_grid.attach(&name_entry, 0, 1, 1, 1);
+    presets_grid.attach(&save_button, 1, 1, 1, 1);
+    presets_grid.attach(&export_button, 0, 2, 1, 1);
+    presets_grid.attach(&import_button, 1, 2, 1, 1);
+    presets_grid.attach(&status_label, 0, 3, 2, 1);
+    presets_grid.attach(&auto_profiles_editor, 0, 4, 2, 1);
 
     ScrolledWindow::builder()
         .min_content_width(400)
@@ -1,20 +1,26 @@
 use gtk::gdk;
+use log::{error, info, warn};
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
-use std::thread;
-
-use crate::dualsensectl::{
-    change_attenuation_amount, change_lightbar_colour, change_playerleds_amount, change_triggers,
-    change_volume, toggle_lightbar, toggle_microphone, toggle_microphone_led, toggle_speaker,
-};
 
+use crate::backend::capabilities::{Capabilities, Capability};
+use crate::backend::hidraw::HidrawDevice;
+use crate::devices::{DeviceHandle, DeviceRegistry};
+use crate::gui::battery::create_battery_status;
+use crate::gui::device_info::create_device_page;
+use crate::gui::monitor::create_monitor_page;
+use crate::gui::preferences::create_preferences_page;
 use crate::gui::presets::create_presets_page;
 use crate::gui::profiles::create_profiles_page;
 use crate::gui::utils::{
-    clear_grid, create_help_popup, create_labeled_level_bar, create_validated_input_field,
-    get_field_constraints, get_input_values, set_margins, FieldConstraint,
+    clear_grid, confirm, create_help_popup, create_validated_input_field, get_field_constraints,
+    get_input_values, send_command, set_margins, FieldConstraint,
 };
-use crate::save::{load_state, save_state, AppPaths};
-use crate::structs::{Controller, Speaker, TriggerEffect};
+use crate::hotplug::{settled_serial, spawn_watcher, HotplugEvent, RECONNECT_DELAY};
+use crate::save::{load_default_profile, AppPaths};
+use crate::structs::{ConnectedDevice, Connection, Controller, Settings, Speaker, TriggerEffect};
+use crate::worker::{restore_device, DeviceCommand, Worker};
 
 use gtk::glib::Propagation;
 use gtk::prelude::*;
@@ -30,10 +36,65 @@ use gtk::{
 // Utility Functions
 //////////////////////////////////////////////////////////
 
+/// Sends `command(state)` when `switch` is flipped
+///
+/// The switch only commits its new state once the device accepted it and
+/// flips back otherwise
+fn connect_device_switch(
+    switch: &Switch,
+    worker: &Worker,
+    device: &DeviceHandle,
+    command: fn(bool) -> DeviceCommand,
+) {
+    switch.connect_state_set({
+        let worker = worker.clone();
+        let device = device.clone();
+        move |switch, state| {
+            // Flipping back after a failed write, nothing to send
+            if state == switch.state() {
+                return Propagation::Proceed;
+            }
+
+            let switch = switch.clone();
+            send_command(&worker, &device, command(state), move |reply| {
+                match reply.result {
+                    Ok(_) => switch.set_state(state),
+                    Err(_) => switch.set_active(!state),
+                }
+            });
+            Propagation::Stop
+        }
+    });
+}
+
+/// Greys out `widget` if the backend can't do `capability`
+fn disable_unless(
+    widget: &impl IsA<gtk::Widget>,
+    capabilities: &Capabilities,
+    capability: Capability,
+) {
+    if !capabilities.supports(capability) {
+        widget.set_sensitive(false);
+        widget.set_tooltip_text(Some(&format!(
+            "'{}' isn't supported by the installed dualsensectl",
+            capability.subcommand()
+        )));
+    }
+}
+
+fn speaker_index(speaker: &Speaker) -> u32 {
+    match speaker {
+        Speaker::Internal => 0,
+        Speaker::Headphone => 1,
+        Speaker::Monoheadphone => 2,
+        Speaker::Both => 3,
+    }
+}
+
 fn create_lightbar_controls(
-    controller: Arc<Mutex<Controller>>,
+    worker: &Worker,
+    device: &DeviceHandle,
     controller_state: &Controller,
-    app_paths: &Arc<AppPaths>,
 ) -> Grid {
     let grid = gtk::Grid::builder()
         .row_spacing(6)
@@ -48,25 +109,7 @@ fn create_lightbar_controls(
     lightbar_switch.set_hexpand(false);
     lightbar_switch.set_halign(gtk::Align::Center);
 
-    lightbar_switch.connect_state_set({
-        let controller_clone = Arc::clone(&controller);
-        let app_paths_clone = Arc::clone(app_paths);
-        move |_, state| {
-            let controller_clone_inner = Arc::clone(&controller_clone);
-            let app_paths_clone_inner = Arc::clone(&app_paths_clone);
-            thread::spawn(move || {
-                if let Ok(mut ctrl) = controller_clone_inner.lock() {
-                    toggle_lightbar(state, &mut ctrl);
-                    if let Err(err) = save_state(&ctrl, &app_paths_clone_inner) {
-                        eprintln!("Failed to save controller state: {err}");
-                    }
-                } else {
-                    eprintln!("Failed to lock controller for lightbar toggle.");
-                }
-            });
-            Propagation::Proceed
-        }
-    });
+    connect_device_switch(&lightbar_switch, worker, device, DeviceCommand::Lightbar);
 
     let color_dialog = ColorDialog::builder().build();
     let color_dialog_button = ColorDialogButton::builder().build();
@@ -97,8 +140,11 @@ fn create_lightbar_controls(
         .build();
 
     let apply_lightbar_changes = {
+        let worker = worker.clone();
+        let device = device.clone();
         let color_dialog_button = color_dialog_button.clone();
         let brightness_adjustment = brightness_adjustment.clone();
+        let lightbar_switch = lightbar_switch.clone();
         move || {
             let rgba = color_dialog_button.rgba();
             let red = (rgba.red() * 255.0).round() as u8;
@@ -108,14 +154,19 @@ fn create_lightbar_controls(
 
             let state = vec![red, green, blue, brightness];
 
-            let controller_clone = Arc::clone(&controller);
-            thread::spawn(move || {
-                if let Ok(mut ctrl) = controller_clone.lock() {
-                    change_lightbar_colour(state, &mut ctrl);
-                } else {
-                    eprintln!("Failed to lock controller for lightbar color change.");
-                }
-            });
+            let lightbar_switch = lightbar_switch.clone();
+            send_command(
+                &worker,
+                &device,
+                DeviceCommand::LightbarColour(state),
+                move |reply| {
+                    // Setting a colour also turns the lightbar on
+                    if reply.result.is_ok() && !lightbar_switch.state() {
+                        lightbar_switch.set_state(true);
+                        lightbar_switch.set_active(true);
+                    }
+                },
+            );
         }
     };
 
@@ -171,9 +222,10 @@ fn create_lightbar_controls(
 }
 
 fn create_microphone_controls(
-    controller: Arc<Mutex<Controller>>,
+    worker: &Worker,
+    device: &DeviceHandle,
     controller_state: &Controller,
-    app_paths: &Arc<AppPaths>,
+    capabilities: &Capabilities,
 ) -> Grid {
     let grid = gtk::Grid::builder()
         .row_spacing(6)
@@ -195,45 +247,24 @@ fn create_microphone_controls(
     microphone_led_switch.set_hexpand(false);
     microphone_led_switch.set_halign(gtk::Align::Center);
 
-    microphone_switch.connect_state_set({
-        let controller_clone = Arc::clone(&controller);
-        let app_paths_clone = Arc::clone(app_paths);
-        move |_, _| {
-            let controller_clone = Arc::clone(&controller_clone);
-            let app_paths_clone = Arc::clone(&app_paths_clone);
-            thread::spawn(move || {
-                if let Ok(mut ctrl) = controller_clone.lock() {
-                    toggle_microphone(&mut ctrl);
-                    if let Err(err) = save_state(&ctrl, &app_paths_clone) {
-                        eprintln!("Failed to save controller state: {err}");
-                    }
-                } else {
-                    eprintln!("Failed to lock controller for microphone toggle.");
-                }
-            });
-            Propagation::Proceed
-        }
-    });
-
-    microphone_led_switch.connect_state_set({
-        let controller_clone = Arc::clone(&controller);
-        let app_paths_clone = Arc::clone(app_paths);
-        move |_, _| {
-            let controller_clone = Arc::clone(&controller_clone);
-            let app_paths_clone = Arc::clone(&app_paths_clone);
-            thread::spawn(move || {
-                if let Ok(mut ctrl) = controller_clone.lock() {
-                    toggle_microphone_led(&mut ctrl);
-                    if let Err(err) = save_state(&ctrl, &app_paths_clone) {
-                        eprintln!("Failed to save controller state: {err}");
-                    }
-                } else {
-                    eprintln!("Failed to lock controller for microphone led toggle.");
-                }
-            });
-            Propagation::Proceed
-        }
-    });
+    connect_device_switch(
+        &microphone_switch,
+        worker,
+        device,
+        DeviceCommand::Microphone,
+    );
+    connect_device_switch(
+        &microphone_led_switch,
+        worker,
+        device,
+        DeviceCommand::MicrophoneLed,
+    );
+    disable_unless(&microphone_switch, capabilities, Capability::Microphone);
+    disable_unless(
+        &microphone_led_switch,
+        capabilities,
+        Capability::MicrophoneLed,
+    );
 
     grid.attach(
         &{
@@ -264,9 +295,9 @@ fn create_microphone_controls(
 }
 
 fn create_playerleds_controls(
-    controller: Arc<Mutex<Controller>>,
+    worker: &Worker,
+    device: &DeviceHandle,
     controller_state: &Controller,
-    app_paths: &Arc<AppPaths>,
 ) -> Grid {
     let grid = gtk::Grid::builder()
         .row_spacing(6)
@@ -282,24 +313,22 @@ fn create_playerleds_controls(
         .build();
 
     playerleds_dropdown.connect_selected_notify({
-        let controller_clone = Arc::clone(&controller);
-        let app_paths_clone = Arc::clone(app_paths); // Clone Arc here for the closure
-        let playerleds_dropdown = playerleds_dropdown.clone();
+        let worker = worker.clone();
+        let device = device.clone();
 
-        move |_| {
-            let playerleds = playerleds_dropdown.selected() as u8;
-            let controller_clone_inner = Arc::clone(&controller_clone);
-            let app_paths_clone_inner = Arc::clone(&app_paths_clone); // Clone Arc again for the thread
-            thread::spawn(move || {
-                if let Ok(mut ctrl) = controller_clone_inner.lock() {
-                    change_playerleds_amount(playerleds, &mut ctrl);
-                    if let Err(err) = save_state(&ctrl, &app_paths_clone_inner) {
-                        eprintln!("Failed to save controller state: {err}");
+        move |dropdown| {
+            let playerleds = dropdown.selected() as u8;
+            let dropdown = dropdown.clone();
+            send_command(
+                &worker,
+                &device,
+                DeviceCommand::PlayerLeds(playerleds),
+                move |reply| {
+                    if reply.result.is_err() {
+                        dropdown.set_selected(reply.state.playerleds.into());
                     }
-                } else {
-                    eprintln!("Failed to lock controller for player LED change.");
-                }
-            });
+                },
+            );
         }
     });
 
@@ -310,9 +339,9 @@ fn create_playerleds_controls(
 }
 
 fn create_speaker_controls(
-    controller: Arc<Mutex<Controller>>,
+    worker: &Worker,
+    device: &DeviceHandle,
     controller_state: &Controller,
-    app_paths: &Arc<AppPaths>, // Use Arc<AppPaths> directly
 ) -> Grid {
     let grid = gtk::Grid::builder()
         .row_spacing(6)
@@ -324,16 +353,15 @@ fn create_speaker_controls(
 
     let speaker_dropdown = DropDown::builder()
         .model(&speaker_item)
-        .selected(controller_state.playerleds.into())
+        .selected(speaker_index(&controller_state.speaker))
         .build();
 
     speaker_dropdown.connect_selected_notify({
-        let controller_clone = Arc::clone(&controller);
-        let app_paths_clone = Arc::clone(app_paths);
-        let speaker_dropdown = speaker_dropdown.clone();
+        let worker = worker.clone();
+        let device = device.clone();
 
-        move |_| {
-            let speaker = match speaker_dropdown.selected() {
+        move |dropdown| {
+            let speaker = match dropdown.selected() {
                 0 => Speaker::Internal,
                 1 => Speaker::Headphone,
                 2 => Speaker::Monoheadphone,
@@ -341,23 +369,22 @@ fn create_speaker_controls(
                 _ => Speaker::Internal,
             };
 
-            let controller_clone_inner = Arc::clone(&controller_clone);
-            let app_paths_clone_inner = Arc::clone(&app_paths_clone);
-            thread::spawn(move || {
-                if let Ok(mut ctrl) = controller_clone_inner.lock() {
-                    toggle_speaker(speaker, &mut ctrl);
-                    if let Err(err) = save_state(&ctrl, &app_paths_clone_inner) {
-                        eprintln!("Failed to save controller state: {err}");
+            let dropdown = dropdown.clone();
+            send_command(
+                &worker,
+                &device,
+                DeviceCommand::Speaker(speaker),
+                move |reply| {
+                    if reply.result.is_err() {
+                        dropdown.set_selected(speaker_index(&reply.state.speaker));
                     }
-                } else {
-                    eprintln!("Failed to lock controller for speaker change.");
-                }
-            });
+                },
+            );
         }
     });
 
     let volume_adjustment = Adjustment::new(
-        f64::from(controller_state.lightbar_colour[3]),
+        f64::from(controller_state.volume),
         0.0,
         255.0,
         1.0,
@@ -373,22 +400,11 @@ fn create_speaker_controls(
         .build();
 
     volume_slider.connect_value_changed({
-        let controller_clone = Arc::clone(&controller);
-        let app_paths_clone = Arc::clone(app_paths);
+        let worker = worker.clone();
+        let device = device.clone();
         move |_| {
             let volume = volume_adjustment.value().round() as u8;
-            let controller_clone_inner = Arc::clone(&controller_clone);
-            let app_paths_clone_inner = Arc::clone(&app_paths_clone);
-            thread::spawn(move || {
-                if let Ok(mut ctrl) = controller_clone_inner.lock() {
-                    change_volume(volume, &mut ctrl);
-                    if let Err(err) = save_state(&ctrl, &app_paths_clone_inner) {
-                        eprintln!("Failed to save controller state: {err}");
-                    }
-                } else {
-                    eprintln!("Failed to lock controller for volume change.");
-                }
-            });
+            send_command(&worker, &device, DeviceCommand::Volume(volume), |_| {});
         }
     });
 
@@ -399,9 +415,9 @@ fn create_speaker_controls(
 }
 
 fn create_attenuation_controls(
-    controller: Arc<Mutex<Controller>>,
+    worker: &Worker,
+    device: &DeviceHandle,
     controller_state: &Controller,
-    app_paths: &Arc<AppPaths>,
 ) -> Grid {
     let grid = gtk::Grid::builder()
         .row_spacing(6)
@@ -421,49 +437,41 @@ fn create_attenuation_controls(
         .selected(controller_state.attenuation[1].into())
         .build();
 
-    attenuation_rumble_dropdown.connect_selected_notify({
-        let controller_clone = Arc::clone(&controller);
-        let app_paths_clone = Arc::clone(app_paths); // Clone Arc<AppPaths> here
+    // Both dropdowns always send the full pair so neither side is stale
+    let apply_attenuation = {
+        let worker = worker.clone();
+        let device = device.clone();
         let attenuation_rumble_dropdown = attenuation_rumble_dropdown.clone();
-        move |_| {
-            let attenuation_rumble = attenuation_rumble_dropdown.selected() as u8;
-            let controller_clone_inner = Arc::clone(&controller_clone);
-            let app_paths_clone_inner = Arc::clone(&app_paths_clone); // Clone again for the thread
-            thread::spawn(move || {
-                if let Ok(mut ctrl) = controller_clone_inner.lock() {
-                    ctrl.attenuation[0] = attenuation_rumble;
-                    change_attenuation_amount(ctrl.attenuation.clone(), &mut ctrl);
-                    if let Err(err) = save_state(&ctrl, &app_paths_clone_inner) {
-                        eprintln!("Failed to save controller state: {err}");
-                    }
-                } else {
-                    eprintln!("Failed to lock controller for attenuation RUMBLE change.");
-                }
-            });
-        }
-    });
-
-    attenuation_trigger_dropdown.connect_selected_notify({
-        let controller_clone = Arc::clone(&controller);
-        let app_paths_clone = Arc::clone(app_paths); // Clone Arc<AppPaths> here
         let attenuation_trigger_dropdown = attenuation_trigger_dropdown.clone();
-        move |_| {
-            let attenuation_trigger = attenuation_trigger_dropdown.selected() as u8;
-            let controller_clone_inner = Arc::clone(&controller_clone);
-            let app_paths_clone_inner = Arc::clone(&app_paths_clone); // Clone again for the thread
-            thread::spawn(move || {
-                if let Ok(mut ctrl) = controller_clone_inner.lock() {
-                    ctrl.attenuation[1] = attenuation_trigger;
-                    change_attenuation_amount(ctrl.attenuation.clone(), &mut ctrl);
-                    if let Err(err) = save_state(&ctrl, &app_paths_clone_inner) {
-                        eprintln!("Failed to save controller state: {err}");
+        move || {
+            let attenuation = vec![
+                attenuation_rumble_dropdown.selected() as u8,
+                attenuation_trigger_dropdown.selected() as u8,
+            ];
+            let attenuation_rumble_dropdown = attenuation_rumble_dropdown.clone();
+            let attenuation_trigger_dropdown = attenuation_trigger_dropdown.clone();
+            send_command(
+                &worker,
+                &device,
+                DeviceCommand::Attenuation(attenuation),
+                move |reply| {
+                    if reply.result.is_err() {
+                        attenuation_rumble_dropdown.set_selected(reply.state.attenuation[0].into());
+                        attenuation_trigger_dropdown
+                            .set_selected(reply.state.attenuation[1].into());
                     }
-                } else {
-                    eprintln!("Failed to lock controller for attenuation TRIGGER change.");
-                }
-            });
+                },
+            );
         }
+    };
+
+    attenuation_rumble_dropdown.connect_selected_notify({
+        let apply_attenuation = apply_attenuation.clone();
+        move |_| apply_attenuation()
     });
+
+    attenuation_trigger_dropdown.connect_selected_notify(move |_| apply_attenuation());
+
     grid.attach(&attenuation_rumble_dropdown, 1, 0, 1, 1);
     grid.attach(&attenuation_trigger_dropdown, 2, 0, 1, 1);
 
@@ -471,9 +479,9 @@ fn create_attenuation_controls(
 }
 
 fn create_trigger_controls(
-    controller: Arc<Mutex<Controller>>,
+    worker: &Worker,
+    device: &DeviceHandle,
     controller_state: &Controller,
-    app_paths: &Arc<AppPaths>,
 ) -> Grid {
     let grid = Grid::builder()
         .column_homogeneous(true)
@@ -787,11 +795,11 @@ fn create_trigger_controls(
     });
 
     apply_button.connect_clicked({
-        let controller_clone = Arc::clone(&controller);
+        let worker = worker.clone();
+        let device = device.clone();
         let effect_dropdown = effect_dropdown.clone();
         let input_grid = input_grid.clone();
         let trigger_effects = Arc::clone(&trigger_effects);
-        let app_paths = Arc::clone(app_paths);
 
         move |_| {
             let selected = effect_dropdown.selected() as usize;
@@ -816,17 +824,16 @@ fn create_trigger_controls(
                 _ => {}
             }
 
-            if let Ok(mut ctrl) = controller_clone.lock() {
-                ctrl.trigger.effect = new_effect;
-                println!("Updated trigger: {:?}", ctrl.trigger);
-                change_triggers(&ctrl.trigger);
-
-                if let Err(err) = save_state(&ctrl, &app_paths) {
-                    eprintln!("Failed to save controller state: {err}");
-                }
-            } else {
-                eprintln!("Failed to lock controller to apply trigger effect.");
-            }
+            send_command(
+                &worker,
+                &device,
+                DeviceCommand::TriggerEffect(new_effect),
+                |reply| {
+                    if let Err(err) = reply.result {
+                        error!("Failed to apply trigger effect: {}", err);
+                    }
+                },
+            );
         }
     });
 
@@ -842,20 +849,28 @@ fn create_trigger_controls(
 // Main UI Function
 //////////////////////////////////////////////////////////
 
-pub fn build_ui(
-    app: &Application,
-    controller: Arc<Mutex<Controller>>,
-    app_paths: Arc<AppPaths>,
-) -> ApplicationWindow {
-    let controller_state = load_state(&app_paths);
-
-    let stack = gtk::Stack::builder()
-        .transition_type(gtk::StackTransitionType::SlideLeftRight)
-        .transition_duration(300)
-        .build();
-
-    let stack_switcher = gtk::StackSwitcher::builder().stack(&stack).build();
-    set_margins(&stack_switcher, 12);
+/// Replaces every page in `stack` with pages for `device`
+///
+/// Only call it when the device changes, building the pages restarts the
+/// battery poll and reads the device info again
+fn populate_device_pages(
+    stack: &gtk::Stack,
+    worker: &Worker,
+    device: &DeviceHandle,
+    settings: &Arc<Mutex<Settings>>,
+    app_paths: &Arc<AppPaths>,
+) {
+    let visible_page = stack.visible_child_name();
+    while let Some(child) = stack.first_child() {
+        stack.remove(&child);
+    }
+
+    let controller_state = device
+        .controller
+        .lock()
+        .map(|c| c.clone())
+        .unwrap_or_default();
+    let capabilities = device.backend.capabilities();
 
     let main_controls_box = Box::builder()
         .orientation(Orientation::Vertical)
@@ -863,26 +878,35 @@ pub fn build_ui(
         .build();
     set_margins(&main_controls_box, 12);
 
-    let battery_box = create_labeled_level_bar(
-        "Battery",
-        controller_state.battery_percentage.into(),
-        0.0,
-        100.0,
-    )
-    .0;
-
-    let lightbar_controls_grid =
-        create_lightbar_controls(Arc::clone(&controller), &controller_state, &app_paths);
-    let playerleds_controls_grid =
-        create_playerleds_controls(Arc::clone(&controller), &controller_state, &app_paths);
+    let battery_box = create_battery_status(
+        worker,
+        device,
+        controller_state.battery_percentage,
+        settings,
+    );
+
+    let lightbar_controls_grid = create_lightbar_controls(worker, device, &controller_state);
+    let playerleds_controls_grid = create_playerleds_controls(worker, device, &controller_state);
     let microphone_controls_grid =
-        create_microphone_controls(Arc::clone(&controller), &controller_state, &app_paths);
-    let speaker_controls_grid =
-        create_speaker_controls(Arc::clone(&controller), &controller_state, &app_paths);
-    let attenuation_controls_grid =
-        create_attenuation_controls(Arc::clone(&controller), &controller_state, &app_paths);
-    let trigger_controls_grid =
-        create_trigger_controls(Arc::clone(&controller), &controller_state, &app_paths);
+        create_microphone_controls(worker, device, &controller_state, &capabilities);
+    let speaker_controls_grid = create_speaker_controls(worker, device, &controller_state);
+    let attenuation_controls_grid = create_attenuation_controls(worker, device, &controller_state);
+    let trigger_controls_grid = create_trigger_controls(worker, device, &controller_state);
+
+    disable_unless(&battery_box, &capabilities, Capability::Battery);
+    disable_unless(&lightbar_controls_grid, &capabilities, Capability::Lightbar);
+    disable_unless(
+        &playerleds_controls_grid,
+        &capabilities,
+        Capability::PlayerLeds,
+    );
+    disable_unless(&speaker_controls_grid, &capabilities, Capability::Speaker);
+    disable_unless(
+        &attenuation_controls_grid,
+        &capabilities,
+        Capability::Attenuation,
+    );
+    disable_unless(&trigger_controls_grid, &capabilities, Capability::Trigger);
 
     let settings_grid = gtk::Grid::builder()
         .row_spacing(10)
@@ -904,21 +928,288 @@ pub fn build_ui(
     settings_grid.attach(&Label::new(Some("Triggers")), 0, 12, 2, 1);
     settings_grid.attach(&trigger_controls_grid, 0, 13, 2, 1);
 
+    let power_off_button = Button::builder()
+        .label("Power Off")
+        .halign(gtk::Align::End)
+        .build();
+    power_off_button.connect_clicked({
+        let worker = worker.clone();
+        let device = device.clone();
+        move |button| {
+            let worker = worker.clone();
+            let device = device.clone();
+            confirm(
+                button,
+                &format!("Power off {}?", device.label()),
+                "It will disconnect and has to be turned back on with the PS button.",
+                "Power Off",
+                move || {
+                    send_command(&worker, &device, DeviceCommand::PowerOff, |_| {});
+                },
+            );
+        }
+    });
+    disable_unless(&power_off_button, &capabilities, Capability::PowerOff);
+
+    if capabilities.is_empty() {
+        main_controls_box.append(&Label::new(Some(
+            "dualsensectl wasn't found. Install it or set its path under Preferences.",
+        )));
+    }
     main_controls_box.append(&settings_grid);
+    main_controls_box.append(&power_off_button);
 
     stack.add_titled(&main_controls_box, Some("main"), "Settings");
 
-    let presets_page = create_presets_page(&Arc::clone(&controller));
+    let presets_page = create_presets_page(worker, device);
+    disable_unless(&presets_page, &capabilities, Capability::Trigger);
     stack.add_titled(&presets_page, Some("presets"), "Presets");
 
-    let profiles_page = create_profiles_page(&Arc::clone(&controller), &Arc::clone(&app_paths));
+    let profiles_page = create_profiles_page(worker, device, settings, app_paths);
     stack.add_titled(&profiles_page, Some("profiles"), "Profiles");
 
+    let device_page = create_device_page(worker, device);
+    disable_unless(&device_page, &capabilities, Capability::Info);
+    stack.add_titled(&device_page, Some("device"), "Device");
+
+    let monitor_page = create_monitor_page(device);
+    disable_unless(&monitor_page, &capabilities, Capability::Monitor);
+    stack.add_titled(&monitor_page, Some("monitor"), "Monitor");
+
+    let preferences_page = create_preferences_page(worker, settings, &capabilities, app_paths);
+    stack.add_titled(&preferences_page, Some("preferences"), "Preferences");
+
+    if let Some(name) = visible_page {
+        stack.set_visible_child_name(&name);
+    }
+}
+
+/// Queues a power-off for every connected pad
+fn power_off_all(registry: &DeviceRegistry, worker: &Worker) {
+    for device in registry.connected_handles() {
+        info!("Powering off {}", device.label());
+        send_command(worker, &device, DeviceCommand::PowerOff, |_| {});
+    }
+}
+
+/// "Connected over USB" etc. for whichever pad is selected
+fn connection_status(device: Option<&ConnectedDevice>) -> String {
+    match device.map(|d| d.connection) {
+        Some(Connection::Usb) => "Connected over USB".to_string(),
+        Some(Connection::Bluetooth) => "Connected over Bluetooth".to_string(),
+        None => "No controller detected".to_string(),
+    }
+}
+
+/// Pushes `device`'s saved state to the pad, or `profile` instead if given
+fn restore(worker: &Worker, device: &DeviceHandle, profile: Option<Controller>) {
+    restore_device(device, profile, |command, done| {
+        send_command(worker, device, command, done)
+    });
+}
+
+/// Writes the saved state back to a pad that just (re)connected
+///
+/// Waits a moment first, the node shows up before the pad is ready for
+/// output reports
+fn restore_after_reconnect(registry: &Arc<DeviceRegistry>, worker: &Worker, device: HidrawDevice) {
+    let registry = Arc::clone(registry);
+    let worker = worker.clone();
+    gtk::glib::timeout_add_local_once(RECONNECT_DELAY, move || match settled_serial(&device) {
+        Some(serial) => restore(&worker, &registry.handle(Some(&serial)), None),
+        None => warn!(
+            "{} has no serial, not restoring its state",
+            device.node.display()
+        ),
+    });
+}
+
+/// Dropdown listing connected pads, falls back to a single "Default device"
+/// entry when none are reported
+///
+/// Refreshes itself and restores saved state when `hotplug` reports a pad
+fn create_device_picker(
+    registry: &Arc<DeviceRegistry>,
+    worker: &Worker,
+    stack: &gtk::Stack,
+    settings: &Arc<Mutex<Settings>>,
+    app_paths: &Arc<AppPaths>,
+    hotplug: Option<async_channel::Receiver<HotplugEvent>>,
+) -> Box {
+    let picker_box = Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+
+    let devices: Rc<RefCell<Vec<ConnectedDevice>>> = Rc::new(RefCell::new(Vec::new()));
+    let device_dropdown = DropDown::builder().hexpand(true).build();
+    let refresh_button = Button::with_label("Refresh");
+    let power_off_all_button = Button::with_label("Power Off All");
+    let status_label = Label::new(None);
+    status_label.add_css_class("dim-label");
+
+    // Serial the pages were built for, `None` until the first build
+    let shown: Rc<RefCell<Option<Option<String>>>> = Rc::new(RefCell::new(None));
+
+    let show_selected = {
+        let registry = Arc::clone(registry);
+        let devices = Rc::clone(&devices);
+        let worker = worker.clone();
+        let stack = stack.clone();
+        let settings = Arc::clone(settings);
+        let app_paths = Arc::clone(app_paths);
+        let status_label = status_label.clone();
+        move |selected: u32| {
+            let selected_device = devices.borrow().get(selected as usize).cloned();
+            status_label.set_text(&connection_status(selected_device.as_ref()));
+            let serial = selected_device.map(|d| d.serial);
+            // The registry keeps one handle per serial, the pages are bound
+            // to it already
+            if shown.borrow().as_ref() == Some(&serial) {
+                return;
+            }
+            *shown.borrow_mut() = Some(serial.clone());
+            let device = registry.handle(serial.as_deref());
+            info!("Selected device: {}", device.label());
+            populate_device_pages(&stack, &worker, &device, &settings, &app_paths);
+        }
+    };
+
+    let selected_handler = Rc::new(device_dropdown.connect_selected_notify({
+        let show_selected = show_selected.clone();
+        move |dropdown| show_selected(dropdown.selected())
+    }));
+
+    let refresh_devices = {
+        let registry = Arc::clone(registry);
+        let devices = Rc::clone(&devices);
+        let device_dropdown = device_dropdown.clone();
+        move || {
+            // Stay on the same pad if it's still there
+            let previous = devices
+                .borrow()
+                .get(device_dropdown.selected() as usize)
+                .map(|d| d.serial.clone());
+            let found = registry.devices();
+            let selected = previous
+                .and_then(|serial| found.iter().position(|d| d.serial == serial))
+                .unwrap_or(0) as u32;
+            let labels: Vec<String> = if found.is_empty() {
+                vec!["Default device".to_string()]
+            } else {
+                found
+                    .iter()
+                    .map(|d| format!("{} ({:?})", d.serial, d.connection))
+                    .collect()
+            };
+            *devices.borrow_mut() = found;
+            // Replacing the model notifies on its own, once is enough
+            device_dropdown.block_signal(&selected_handler);
+            device_dropdown.set_model(Some(&StringList::new(
+                &labels.iter().map(String::as_str).collect::<Vec<&str>>(),
+            )));
+            device_dropdown.set_selected(selected);
+            device_dropdown.unblock_signal(&selected_handler);
+            show_selected(selected);
+        }
+    };
+
+    refresh_button.connect_clicked({
+        let refresh_devices = refresh_devices.clone();
+        move |_| refresh_devices()
+    });
+
+    power_off_all_button.connect_clicked({
+        let registry = Arc::clone(registry);
+        let worker = worker.clone();
+        move |button| {
+            let registry = Arc::clone(&registry);
+            let worker = worker.clone();
+            confirm(
+                button,
+                "Power off all controllers?",
+                "Every connected controller will disconnect.",
+                "Power Off All",
+                move || power_off_all(&registry, &worker),
+            );
+        }
+    });
+
+    if let Some(hotplug) = hotplug {
+        let registry = Arc::clone(registry);
+        let worker = worker.clone();
+        let refresh_devices = refresh_devices.clone();
+        gtk::glib::spawn_future_local(async move {
+            while let Ok(event) = hotplug.recv().await {
+                if let HotplugEvent::Added(device) = &event {
+                    restore_after_reconnect(&registry, &worker, device.clone());
+                }
+                refresh_devices();
+            }
+        });
+    }
+
+    refresh_devices();
+
+    picker_box.append(&Label::new(Some("Controller")));
+    picker_box.append(&device_dropdown);
+    picker_box.append(&status_label);
+    picker_box.append(&refresh_button);
+    picker_box.append(&power_off_all_button);
+
+    picker_box
+}
+
+pub fn build_ui(
+    app: &Application,
+    registry: Arc<DeviceRegistry>,
+    worker: Worker,
+    settings: Arc<Mutex<Settings>>,
+    app_paths: Arc<AppPaths>,
+) -> ApplicationWindow {
+    let stack = gtk::Stack::builder()
+        .transition_type(gtk::StackTransitionType::SlideLeftRight)
+        .transition_duration(300)
+        .build();
+
+    let stack_switcher = gtk::StackSwitcher::builder().stack(&stack).build();
+    set_margins(&stack_switcher, 12);
+
+    let (hotplug_sender, hotplug) = async_channel::unbounded();
+    let hotplug = match spawn_watcher(hotplug_sender) {
+        Ok(_) => Some(hotplug),
+        Err(err) => {
+            error!("Hotplug detection unavailable: {}", err);
+            None
+        }
+    };
+
+    // Pads come up with whatever they had last, push the saved state or
+    // the default profile
+    let default_profile = settings
+        .lock()
+        .ok()
+        .and_then(|s| load_default_profile(&s, &app_paths));
+    if let Some((name, _)) = &default_profile {
+        info!("Applying default profile '{}'", name);
+    }
+    for device in registry.devices() {
+        restore(
+            &worker,
+            &registry.handle(Some(&device.serial)),
+            default_profile.as_ref().map(|(_, profile)| profile.clone()),
+        );
+    }
+
+    let device_picker =
+        create_device_picker(&registry, &worker, &stack, &settings, &app_paths, hotplug);
+
     let main_box = Box::builder()
         .orientation(Orientation::Vertical)
         .spacing(10)
         .build();
     set_margins(&main_box, 12);
+    main_box.append(&device_picker);
     main_box.append(&stack_switcher);
     main_box.append(&stack);
 
@@ -931,17 +1222,18 @@ pub fn build_ui(
         .build();
 
     window.connect_close_request({
-        let controller_clone = Arc::clone(&controller);
-        let app_paths_clone = Arc::clone(&app_paths);
-        move |win| {
-            if let Ok(controller) = controller_clone.lock() {
-                if let Err(err) = save_state(&controller, &app_paths_clone) {
-                    eprintln!("Failed to save controller state: {err}");
+        let registry = Arc::clone(&registry);
+        let settings = Arc::clone(&settings);
+        let worker = worker.clone();
+        move |_| {
+            registry.save_all();
+            if settings.lock().is_ok_and(|s| s.power_off_on_quit) {
+                // Block so the commands run before the app exits
+                for device in registry.connected_handles() {
+                    info!("Powering off {} on quit", device.label());
+                    worker.execute(&device, DeviceCommand::PowerOff);
                 }
-            } else {
-                eprintln!("Failed to lock controller for saving state.");
             }
-            win.close();
             Propagation::Proceed
         }
     });
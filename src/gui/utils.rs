@@ -5,6 +5,9 @@ use gtk::LevelBar;
 use gtk::{prelude::*, Label};
 use gtk::{Box, DropDown, Entry, Grid, InputPurpose, Orientation, Popover, Switch, Widget};
 
+use crate::devices::DeviceHandle;
+use crate::worker::{DeviceCommand, Reply, Worker};
+
 /// Trigger field min, max, and tooltip
 #[derive(Clone)]
 pub struct FieldConstraint {
@@ -232,7 +235,7 @@ pub fn validate_input(entry: &Entry, constraint: &FieldConstraint, popover: &Pop
     let is_valid = text.split(',').all(|v| {
         v.trim()
             .parse::<u8>()
-            .map_or(false, |n| n >= constraint.min && n <= constraint.max)
+            .is_ok_and(|n| n >= constraint.min && n <= constraint.max)
     });
 
     if !is_valid {
@@ -526,3 +529,48 @@ pub fn set_margins<W: gtk::prelude::WidgetExt>(widget: &W, margin: i32) {
     widget.set_margin_start(margin);
     widget.set_margin_end(margin);
 }
+
+/// Queues `command` on the worker and calls `on_reply` on the GTK main loop
+/// once it has run
+pub fn send_command<F>(worker: &Worker, device: &DeviceHandle, command: DeviceCommand, on_reply: F)
+where
+    F: FnOnce(Reply) + 'static,
+{
+    let receiver = worker.send(device, command);
+    gtk::glib::spawn_future_local(async move {
+        if let Ok(reply) = receiver.recv().await {
+            on_reply(reply);
+        }
+    });
+}
+
+/// Asks "are you sure?" over `widget`'s window and runs `on_accept` on yes
+pub fn confirm<F>(
+    widget: &impl IsA<Widget>,
+    message: &str,
+    detail: &str,
+    accept: &str,
+    on_accept: F,
+) where
+    F: FnOnce() + 'static,
+{
+    let dialog = gtk::AlertDialog::builder()
+        .modal(true)
+        .message(message)
+        .detail(detail)
+        .buttons(["Cancel", accept])
+        .cancel_button(0)
+        .default_button(0)
+        .build();
+
+    let window = widget.root().and_downcast::<gtk::Window>();
+    dialog.choose(
+        window.as_ref(),
+        None::<&gtk::gio::Cancellable>,
+        move |result| {
+            if let Ok(1) = result {
+                on_accept();
+            }
+        },
+    );
+}
@@ -0,0 +1,344 @@
+use clap::{Parser, Subcommand};
+use log::{error, info};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use std::env;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{self, ExitStatus};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::daemon::service_file;
+use crate::devices::{DeviceHandle, DeviceRegistry};
+use crate::dualsensectl::{
+    apply_controller, change_lightbar_colour, change_triggers, change_volume, report_battery,
+    toggle_lightbar,
+};
+use crate::error::DualsenseError;
+use crate::save::{list_profiles, load_profile, AppPaths};
+use crate::structs::{Controller, Trigger};
+
+/// Signals that would otherwise kill us before the state is restored
+const FORWARDED_SIGNALS: [libc::c_int; 3] = [SIGINT, SIGTERM, SIGHUP];
+/// How often to check whether the wrapped program has exited
+const WAIT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Command line arguments, no subcommand opens the GUI
+#[derive(Parser, Debug)]
+#[command(version, about = "Configure DualSense controllers")]
+pub struct Args {
+    /// Serial of the controller to use, defaults to the first connected one
+    #[arg(short, long, global = true)]
+    pub device: Option<String>,
+    /// Keep controllers at their saved state in the background, no window
+    #[arg(long)]
+    pub daemon: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Push a saved profile to the controller
+    ApplyProfile { name: String },
+    /// List saved profiles
+    ListProfiles,
+    /// Change one setting
+    Set {
+        #[command(subcommand)]
+        setting: SetCommand,
+    },
+    /// Print saved information
+    Get {
+        #[command(subcommand)]
+        what: GetCommand,
+    },
+    /// Print battery level and charging state
+    Battery,
+    /// Print a systemd user unit that runs this binary with `--daemon`
+    ServiceFile,
+    /// Apply a profile, run a program and restore the previous state once
+    /// it exits, e.g. `run --profile Racing -- %command%` in Steam
+    Run {
+        #[arg(long)]
+        profile: String,
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SetCommand {
+    /// `on`, `off` or `R G B [BRIGHTNESS]`
+    Lightbar {
+        #[arg(num_args = 1..=4, required = true)]
+        values: Vec<String>,
+    },
+    /// Speaker volume, 0-255
+    Volume { volume: u8 },
+    /// `SIDE MODE [PARAMS...]`, same as `dualsensectl trigger`
+    Trigger {
+        #[arg(num_args = 2.., required = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum GetCommand {
+    /// The controller's saved state
+    State {
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// `--device`, or the first connected pad
+fn device(registry: &DeviceRegistry, serial: Option<&str>) -> DeviceHandle {
+    match serial {
+        Some(serial) => registry.handle(Some(serial)),
+        None => registry.connected_handles().remove(0),
+    }
+}
+
+enum Lightbar {
+    On,
+    Off,
+    Colour(Vec<u8>),
+}
+
+fn parse_lightbar(values: &[String]) -> Result<Lightbar, DualsenseError> {
+    match values {
+        [state] if state == "on" => Ok(Lightbar::On),
+        [state] if state == "off" => Ok(Lightbar::Off),
+        [_, _, _] | [_, _, _, _] => {
+            let mut colour = values
+                .iter()
+                .map(|v| v.parse::<u8>())
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|err| DualsenseError::InvalidArgument(format!("Lightbar: {err}")))?;
+            // Full brightness unless given
+            if colour.len() == 3 {
+                colour.push(255);
+            }
+            Ok(Lightbar::Colour(colour))
+        }
+        _ => Err(DualsenseError::InvalidArgument(
+            "Lightbar takes on, off or R G B [BRIGHTNESS]".to_string(),
+        )),
+    }
+}
+
+fn set(
+    setting: SetCommand,
+    device: &DeviceHandle,
+    controller: &mut Controller,
+) -> Result<(), DualsenseError> {
+    let backend = &*device.backend;
+    match setting {
+        SetCommand::Lightbar { values } => match parse_lightbar(&values)? {
+            Lightbar::On => toggle_lightbar(backend, true, controller),
+            Lightbar::Off => toggle_lightbar(backend, false, controller),
+            Lightbar::Colour(colour) => change_lightbar_colour(backend, colour, controller),
+        },
+        SetCommand::Volume { volume } => change_volume(backend, volume, controller),
+        SetCommand::Trigger { args } => {
+            let [side, mode, values @ ..] = &args[..] else {
+                return Err(DualsenseError::InvalidArgument(
+                    "trigger needs SIDE MODE [PARAMS...]".to_string(),
+                ));
+            };
+            change_triggers(backend, Trigger::parse(side, mode, values)?, controller)
+        }
+    }
+}
+
+fn on_off(state: bool) -> &'static str {
+    if state {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn print_state(controller: &Controller) {
+    println!(
+        "lightbar: {} {:?}",
+        on_off(controller.lightbar_enabled),
+        controller.lightbar_colour
+    );
+    println!("player-leds: {}", controller.playerleds);
+    println!("microphone: {}", on_off(controller.microphone));
+    println!("microphone-led: {}", on_off(controller.microphone_led));
+    println!("speaker: {:?}", controller.speaker);
+    println!("volume: {}", controller.volume);
+    println!("attenuation: {:?}", controller.attenuation);
+    println!(
+        "trigger: {} {:?}",
+        controller.trigger.side, controller.trigger.effect
+    );
+    println!("battery: {}%", controller.battery_percentage);
+}
+
+/// Runs `change` on the pad's state and saves it if it worked
+fn change_state(
+    device: &DeviceHandle,
+    app_paths: &Arc<AppPaths>,
+    change: impl FnOnce(&mut Controller) -> Result<(), DualsenseError>,
+) -> Result<(), DualsenseError> {
+    let mut controller = device.controller.lock().unwrap_or_else(|e| e.into_inner());
+    change(&mut controller)?;
+    device.save(&controller, app_paths)?;
+    Ok(())
+}
+
+/// Pushes `target` to the pad and saves it, prints how each field went
+fn apply(
+    device: &DeviceHandle,
+    target: &Controller,
+    app_paths: &Arc<AppPaths>,
+) -> Result<(), DualsenseError> {
+    change_state(device, app_paths, |controller| {
+        let report = apply_controller(&*device.backend, target, controller);
+        println!("{report}");
+        match report.failure() {
+            Some((field, err)) => Err(DualsenseError::CommandFailed(format!("{field:?}: {err}"))),
+            None => Ok(()),
+        }
+    })
+}
+
+fn read_profile(name: &str, app_paths: &Arc<AppPaths>) -> Result<Controller, DualsenseError> {
+    load_profile(name, app_paths)
+        .map_err(|err| DualsenseError::InvalidArgument(format!("Profile '{name}': {err}")))
+}
+
+/// Runs `program` and waits for it, passing on any signal we get so we're
+/// still around to clean up afterwards
+fn run_program(program: &[String]) -> Result<ExitStatus, DualsenseError> {
+    let (name, args) = program
+        .split_first()
+        .ok_or_else(|| DualsenseError::InvalidArgument("No command to run".to_string()))?;
+
+    let received = Arc::new(AtomicUsize::new(0));
+    for signal in FORWARDED_SIGNALS {
+        signal_hook::flag::register_usize(signal, Arc::clone(&received), signal as usize)?;
+    }
+
+    let mut child = process::Command::new(name).args(args).spawn()?;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        let signal = received.swap(0, Ordering::SeqCst);
+        if signal != 0 {
+            info!("Forwarding signal {} to {}", signal, name);
+            // SAFETY: plain kill(2) on our own child's pid
+            unsafe {
+                libc::kill(child.id() as libc::pid_t, signal as libc::c_int);
+            }
+        }
+
+        thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+/// Applies `profile`, runs `program` and puts the previous state back
+/// however it exits
+///
+/// Returns the program's exit code, even if restoring fails
+fn run_with_profile(
+    device: &DeviceHandle,
+    profile: &str,
+    program: &[String],
+    app_paths: &Arc<AppPaths>,
+) -> Result<i32, DualsenseError> {
+    let profile = read_profile(profile, app_paths)?;
+    let snapshot = device
+        .controller
+        .lock()
+        .map(|c| c.clone())
+        .unwrap_or_default();
+
+    // A missing pad shouldn't stop the game from starting
+    if let Err(err) = apply(device, &profile, app_paths) {
+        error!("Failed to apply profile, running without it: {}", err);
+    }
+
+    let status = run_program(program);
+
+    info!("Restoring previous controller state.");
+    // The program's exit code matters more to whoever launched us
+    if let Err(err) = apply(device, &snapshot, app_paths) {
+        error!("Failed to restore previous controller state: {}", err);
+    }
+
+    let status = status?;
+    // Killed by a signal has no code, report it like a shell would
+    Ok(status
+        .code()
+        .or_else(|| status.signal().map(|s| 128 + s))
+        .unwrap_or(1))
+}
+
+/// Runs `command` without a display, changes are saved like in the GUI
+///
+/// Returns the exit code for the process
+pub fn run(
+    command: Command,
+    serial: Option<&str>,
+    registry: &DeviceRegistry,
+    app_paths: &Arc<AppPaths>,
+) -> Result<i32, DualsenseError> {
+    match command {
+        Command::ListProfiles => {
+            for profile in list_profiles(app_paths) {
+                println!("{profile}");
+            }
+            Ok(0)
+        }
+        Command::ServiceFile => {
+            print!("{}", service_file(&env::current_exe()?));
+            Ok(0)
+        }
+        Command::Get {
+            what: GetCommand::State { json },
+        } => {
+            let controller = device(registry, serial)
+                .controller
+                .lock()
+                .map(|c| c.clone())
+                .unwrap_or_default();
+            if json {
+                let json = serde_json::to_string_pretty(&controller)
+                    .map_err(|err| DualsenseError::CommandFailed(err.to_string()))?;
+                println!("{json}");
+            } else {
+                print_state(&controller);
+            }
+            Ok(0)
+        }
+        Command::Battery => {
+            let device = device(registry, serial);
+            let mut controller = device.controller.lock().unwrap_or_else(|e| e.into_inner());
+            let status = report_battery(&*device.backend, &mut controller)?;
+            println!("{}% ({})", status.level, status.state);
+            Ok(0)
+        }
+        Command::ApplyProfile { name } => {
+            let profile = read_profile(&name, app_paths)?;
+            apply(&device(registry, serial), &profile, app_paths).map(|()| 0)
+        }
+        Command::Set { setting } => {
+            let device = device(registry, serial);
+            change_state(&device, app_paths, |controller| {
+                set(setting, &device, controller)
+            })
+            .map(|()| 0)
+        }
+        Command::Run { profile, command } => {
+            run_with_profile(&device(registry, serial), &profile, &command, app_paths)
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use inotify::{EventMask, Inotify, WatchMask};
+use log::{error, info};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use crate::backend::hidraw::{find_devices, read_device, HidrawDevice};
+
+/// How long a new node needs before the pad accepts output reports
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// A DualSense showing up or going away
+#[derive(Debug, Clone)]
+pub enum HotplugEvent {
+    Added(HidrawDevice),
+    Removed(HidrawDevice),
+}
+
+/// Serial of a pad that was just added, looked up again if it had none
+///
+/// The driver can fill in `HID_UNIQ` after the node shows up, so call this
+/// once `RECONNECT_DELAY` has passed
+pub fn settled_serial(device: &HidrawDevice) -> Option<String> {
+    device
+        .serial
+        .clone()
+        .or_else(|| read_device(device.node.file_name()?)?.serial)
+}
+
+/// Watches for DualSense hidraw nodes and sends an event for each one that
+/// comes or goes
+///
+/// sysfs doesn't raise inotify events, so this watches `/dev` for the
+/// nodes and looks the VID/PID up under `/sys/class/hidraw` once one
+/// appears. Stops once the receiving side is dropped
+pub fn spawn_watcher(
+    sender: async_channel::Sender<HotplugEvent>,
+) -> io::Result<thread::JoinHandle<()>> {
+    let mut inotify = Inotify::init()?;
+    inotify
+        .watches()
+        .add("/dev", WatchMask::CREATE | WatchMask::DELETE)?;
+
+    // Removed nodes can't be looked up anymore, remember what they were
+    let mut known: HashMap<OsString, HidrawDevice> = find_devices()
+        .into_iter()
+        .filter_map(|device| Some((device.node.file_name()?.to_os_string(), device)))
+        .collect();
+
+    thread::Builder::new()
+        .name("hotplug".to_string())
+        .spawn(move || {
+            let mut buffer = [0; 4096];
+            loop {
+                let events = match inotify.read_events_blocking(&mut buffer) {
+                    Ok(events) => events,
+                    Err(err) => {
+                        error!("Failed to read hotplug events: {}", err);
+                        break;
+                    }
+                };
+
+                for event in events {
+                    let Some(name) = event.name else {
+                        continue;
+                    };
+                    if !name.to_string_lossy().starts_with("hidraw") {
+                        continue;
+                    }
+
+                    let hotplug = if event.mask.contains(EventMask::CREATE) {
+                        read_device(name)
+                            .inspect(|device| {
+                                known.insert(name.to_os_string(), device.clone());
+                            })
+                            .map(HotplugEvent::Added)
+                    } else {
+                        known.remove(name).map(HotplugEvent::Removed)
+                    };
+
+                    if let Some(hotplug) = hotplug {
+                        info!("Hotplug: {:?}", hotplug);
+                        if sender.send_blocking(hotplug).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            info!("Hotplug watcher stopped.");
+        })
+}
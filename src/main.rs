@@ -1,28 +1,129 @@
 #![allow(dead_code)]
 
+mod alerts;
+mod autoswitch;
+mod backend;
+mod daemon;
+mod dbus;
+mod devices;
+mod dsx;
 mod dualsensectl;
+mod error;
 mod gui;
+mod headless;
+mod hotplug;
+mod monitor;
+mod osc;
+mod rpc;
 mod save;
 mod structs;
+mod web;
+mod worker;
 
+use backend::ControllerBackend;
+use clap::Parser;
+use devices::DeviceRegistry;
 use env_logger::Builder;
 use gtk::glib;
 use gtk::prelude::*;
 use gtk::Application;
 use gui::ui::build_ui;
-use log::Level;
-use save::{AppPaths, load_state, truncate_log};
+use headless::Args;
+use log::{error, info, Level};
+use save::{load_settings, truncate_log, AppPaths};
 use std::env;
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use structs::Settings;
+use worker::Worker;
 
 const APP_ID: &str = "org.gtk_rs.Dualsensectl";
+const BACKEND_ENV: &str = "DUALSENSECTL_GUI_BACKEND";
+
+/// Services that stop when dropped, held until we exit
+struct Services {
+    dbus: Option<zbus::blocking::Connection>,
+    rpc: Option<rpc::RpcServer>,
+}
+
+/// Starts the D-Bus and RPC services, the DSX, OSC and HTTP servers that are
+/// enabled and automatic profile switching
+///
+/// Only the GUI and the daemon host these, a one-shot command exits before
+/// they'd be of any use
+fn start_services(
+    registry: &Arc<DeviceRegistry>,
+    worker: &Worker,
+    settings: &Arc<Mutex<Settings>>,
+    app_paths: &Arc<AppPaths>,
+) -> Services {
+    let dbus = match dbus::serve(Arc::clone(registry), worker.clone(), Arc::clone(app_paths)) {
+        Ok(connection) => Some(connection),
+        Err(err) => {
+            error!("D-Bus service unavailable: {}", err);
+            None
+        }
+    };
+
+    let rpc = match rpc::serve(Arc::clone(registry), worker.clone(), Arc::clone(app_paths)) {
+        Ok(server) => Some(server),
+        Err(err) => {
+            error!("RPC socket unavailable: {}", err);
+            None
+        }
+    };
+
+    let dsx = settings
+        .lock()
+        .map(|s| s.dsx_enabled.then_some(s.dsx_port))
+        .unwrap_or_default();
+    if let Some(port) = dsx {
+        if let Err(err) = dsx::serve(Arc::clone(registry), worker.clone(), port) {
+            error!("DSX server unavailable on port {}: {}", port, err);
+        }
+    }
+
+    let osc = settings
+        .lock()
+        .map(|s| s.osc_enabled.then_some((s.osc_port, s.osc_listen_all)))
+        .unwrap_or_default();
+    if let Some((port, listen_all)) = osc {
+        if let Err(err) = osc::serve(Arc::clone(registry), worker.clone(), port, listen_all) {
+            error!("OSC server unavailable on port {}: {}", port, err);
+        }
+    }
+
+    let web = settings
+        .lock()
+        .map(|s| s.web_enabled.then_some(s.web_port))
+        .unwrap_or_default();
+    if let Some(port) = web {
+        if let Err(err) = web::serve(
+            Arc::clone(registry),
+            worker.clone(),
+            Arc::clone(app_paths),
+            port,
+        ) {
+            error!("HTTP API unavailable on port {}: {}", port, err);
+        }
+    }
+
+    if let Err(err) = autoswitch::spawn(
+        Arc::clone(registry),
+        worker.clone(),
+        Arc::clone(settings),
+        Arc::clone(app_paths),
+    ) {
+        error!("Automatic profile switching unavailable: {}", err);
+    }
+
+    Services { dbus, rpc }
+}
 
 fn main() -> glib::ExitCode {
+    let args = Args::parse();
     let app_paths = Arc::new(AppPaths::new());
-    let controller = Arc::new(Mutex::new(load_state(&app_paths)));
 
     truncate_log(&app_paths.log_file);
     let log_file = OpenOptions::new()
@@ -57,12 +158,67 @@ fn main() -> glib::ExitCode {
         })
         .init();
 
+    let settings = load_settings(&app_paths);
+    let backend: Arc<dyn ControllerBackend> = backend::from_name(
+        &env::var(BACKEND_ENV).unwrap_or_else(|_| "cli".to_string()),
+        settings.dualsensectl_path.as_deref(),
+    );
+    let registry = Arc::new(DeviceRegistry::new(
+        Arc::clone(&backend),
+        Arc::clone(&app_paths),
+    ));
+
+    if let Some(command) = args.command {
+        return match headless::run(command, args.device.as_deref(), &registry, &app_paths) {
+            Ok(code) => glib::ExitCode::from(code),
+            Err(err) => {
+                eprintln!("Error: {err}");
+                glib::ExitCode::FAILURE
+            }
+        };
+    }
+
+    let worker = Worker::spawn(Arc::clone(&app_paths), settings.max_write_rate);
+    let settings = Arc::new(Mutex::new(settings));
+
+    // Probing runs the backend, a one-shot command does without
+    let capabilities = backend.capabilities();
+    info!(
+        "Using {} backend, version {}",
+        backend.name(),
+        capabilities.version.as_deref().unwrap_or("unknown")
+    );
+    let _services = start_services(&registry, &worker, &settings, &app_paths);
+
+    if args.daemon {
+        return match daemon::run(&registry, &worker, &settings, &app_paths) {
+            Ok(()) => glib::ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("Error: {err}");
+                glib::ExitCode::FAILURE
+            }
+        };
+    }
+
     let app = Application::builder().application_id(APP_ID).build();
 
     app.connect_activate(move |app| {
-        let window = build_ui(app, Arc::clone(&controller), Arc::clone(&app_paths));
+        // Launching again activates the running instance, show its window
+        // rather than building a second one with its own hotplug watcher
+        if let Some(window) = app.active_window() {
+            window.present();
+            return;
+        }
+        let window = build_ui(
+            app,
+            Arc::clone(&registry),
+            worker.clone(),
+            Arc::clone(&settings),
+            Arc::clone(&app_paths),
+        );
         window.present();
     });
 
-    app.run()
+    // Our arguments were handled by clap, GTK would reject them
+    app.run_with_args(&env::args().take(1).collect::<Vec<_>>())
 }
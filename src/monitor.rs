@@ -0,0 +1,395 @@
+use log::{debug, error, info};
+use std::collections::BTreeSet;
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::backend::ControllerBackend;
+
+/// One thing the pad reported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    /// Every button currently held, by `dualsensectl` name
+    Buttons(Vec<String>),
+    LeftStick {
+        x: u8,
+        y: u8,
+    },
+    RightStick {
+        x: u8,
+        y: u8,
+    },
+    LeftTrigger(u8),
+    RightTrigger(u8),
+    /// Touchpad contact `id` (0 or 1), `x` 0-1919, `y` 0-1079
+    Touch {
+        id: u8,
+        active: bool,
+        x: u16,
+        y: u16,
+    },
+    Gyro {
+        x: i16,
+        y: i16,
+        z: i16,
+    },
+    Accel {
+        x: i16,
+        y: i16,
+        z: i16,
+    },
+    Battery {
+        level: u8,
+        state: String,
+    },
+    Added(String),
+    Removed(String),
+}
+
+/// `None` if any of them doesn't parse, so a bad value can't shift the rest
+fn numbers<T: std::str::FromStr>(value: &str) -> Option<Vec<T>> {
+    value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().ok())
+        .collect()
+}
+
+/// Parses one line of `dualsensectl monitor`
+///
+/// ```text
+/// buttons: cross r1
+/// left_stick: 128 127
+/// l2: 255
+/// touch0: 1 960 540
+/// gyro: -3 12 0
+/// battery: 80 discharging
+/// add 84:30:95:aa:bb:cc
+/// ```
+///
+/// Returns `None` for anything it doesn't recognise
+pub fn parse_line(line: &str) -> Option<InputEvent> {
+    let line = line.trim();
+
+    if let Some((event, serial)) = line.split_once(' ') {
+        match event {
+            "add" => return Some(InputEvent::Added(serial.trim().to_string())),
+            "remove" => return Some(InputEvent::Removed(serial.trim().to_string())),
+            _ => {}
+        }
+    }
+
+    let (key, value) = line.split_once(':')?;
+    let value = value.trim();
+
+    match key.trim() {
+        "buttons" => Some(InputEvent::Buttons(
+            value.split_whitespace().map(str::to_string).collect(),
+        )),
+        "left_stick" => match numbers::<u8>(value)?[..] {
+            [x, y] => Some(InputEvent::LeftStick { x, y }),
+            _ => None,
+        },
+        "right_stick" => match numbers::<u8>(value)?[..] {
+            [x, y] => Some(InputEvent::RightStick { x, y }),
+            _ => None,
+        },
+        "l2" => value.parse().ok().map(InputEvent::LeftTrigger),
+        "r2" => value.parse().ok().map(InputEvent::RightTrigger),
+        key @ ("touch0" | "touch1") => match numbers::<u16>(value)?[..] {
+            [active, x, y] => Some(InputEvent::Touch {
+                id: u8::from(key == "touch1"),
+                active: active != 0,
+                x,
+                y,
+            }),
+            _ => None,
+        },
+        "gyro" => match numbers::<i16>(value)?[..] {
+            [x, y, z] => Some(InputEvent::Gyro { x, y, z }),
+            _ => None,
+        },
+        "accel" => match numbers::<i16>(value)?[..] {
+            [x, y, z] => Some(InputEvent::Accel { x, y, z }),
+            _ => None,
+        },
+        "battery" => {
+            let mut parts = value.split_whitespace();
+            let level = parts.next()?.trim_end_matches('%').parse().ok()?;
+            let state = parts.next().unwrap_or("unknown").to_string();
+            Some(InputEvent::Battery { level, state })
+        }
+        _ => None,
+    }
+}
+
+/// Latest value of everything the monitor has reported
+#[derive(Debug, Clone)]
+pub struct InputState {
+    pub buttons: BTreeSet<String>,
+    pub left_stick: (u8, u8),
+    pub right_stick: (u8, u8),
+    pub left_trigger: u8,
+    pub right_trigger: u8,
+    pub touches: [Option<(u16, u16)>; 2],
+    pub gyro: [i16; 3],
+    pub accel: [i16; 3],
+    pub battery: Option<(u8, String)>,
+    pub connected: bool,
+}
+
+impl Default for InputState {
+    fn default() -> Self {
+        Self {
+            buttons: BTreeSet::new(),
+            left_stick: (128, 128),
+            right_stick: (128, 128),
+            left_trigger: 0,
+            right_trigger: 0,
+            touches: [None, None],
+            gyro: [0; 3],
+            accel: [0; 3],
+            battery: None,
+            connected: true,
+        }
+    }
+}
+
+impl InputState {
+    pub fn apply(&mut self, event: InputEvent) {
+        match event {
+            InputEvent::Buttons(buttons) => self.buttons = buttons.into_iter().collect(),
+            InputEvent::LeftStick { x, y } => self.left_stick = (x, y),
+            InputEvent::RightStick { x, y } => self.right_stick = (x, y),
+            InputEvent::LeftTrigger(value) => self.left_trigger = value,
+            InputEvent::RightTrigger(value) => self.right_trigger = value,
+            InputEvent::Touch { id, active, x, y } => {
+                if let Some(touch) = self.touches.get_mut(usize::from(id)) {
+                    *touch = active.then_some((x, y));
+                }
+            }
+            InputEvent::Gyro { x, y, z } => self.gyro = [x, y, z],
+            InputEvent::Accel { x, y, z } => self.accel = [x, y, z],
+            InputEvent::Battery { level, state } => self.battery = Some((level, state)),
+            InputEvent::Added(_) => self.connected = true,
+            InputEvent::Removed(_) => self.connected = false,
+        }
+    }
+}
+
+/// Sends every parsed event until `output` ends or the receiving side is
+/// dropped
+fn read_events(output: Box<dyn BufRead + Send>, sender: &async_channel::Sender<InputEvent>) {
+    for line in output.lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        match parse_line(&line) {
+            Some(event) => {
+                if sender.send_blocking(event).is_err() {
+                    break;
+                }
+            }
+            None => debug!("Ignoring monitor line: {}", line),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SessionState {
+    stopped: bool,
+    /// `MonitorStream::stop` once the monitor is running
+    stop: Option<Box<dyn FnOnce() + Send>>,
+}
+
+/// Owns a running monitor and the thread reading it, dropping it ends both
+pub struct MonitorSession {
+    state: Arc<Mutex<SessionState>>,
+}
+
+impl MonitorSession {
+    /// Starts the monitor and sends its events, off the calling thread so
+    /// a slow start can't block the GUI
+    ///
+    /// `sender` is dropped once the monitor ends or fails to start
+    pub fn start(
+        backend: Arc<dyn ControllerBackend>,
+        sender: async_channel::Sender<InputEvent>,
+    ) -> Self {
+        let state = Arc::new(Mutex::new(SessionState::default()));
+
+        let shared = Arc::clone(&state);
+        let spawned = thread::Builder::new()
+            .name("monitor".to_string())
+            .spawn(move || {
+                let stream = match backend.monitor() {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        error!("Failed to start monitor: {}", err);
+                        return;
+                    }
+                };
+
+                {
+                    let mut shared = shared.lock().unwrap_or_else(|e| e.into_inner());
+                    // Stopped while it was starting
+                    if shared.stopped {
+                        (stream.stop)();
+                        return;
+                    }
+                    shared.stop = Some(stream.stop);
+                }
+                read_events(stream.output, &sender);
+                info!("Monitor reader stopped.");
+            });
+        if let Err(err) = spawned {
+            error!("Failed to start monitor thread: {}", err);
+        }
+
+        Self { state }
+    }
+}
+
+impl Drop for MonitorSession {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.stopped = true;
+        if let Some(stop) = state.stop.take() {
+            stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    const FIXTURE: &str = include_str!("../resources/monitor-fixture.txt");
+
+    /// State after the first `lines` lines of the fixture
+    fn state_after(lines: usize) -> InputState {
+        let mut state = InputState::default();
+        for line in FIXTURE.lines().take(lines) {
+            state.apply(parse_line(line).expect("fixture lines all parse"));
+        }
+        state
+    }
+
+    fn buttons(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn every_fixture_line_parses() {
+        for line in FIXTURE.lines() {
+            assert!(parse_line(line).is_some(), "{line}");
+        }
+    }
+
+    #[test]
+    fn connect_and_idle() {
+        let state = state_after(7);
+        assert!(state.connected);
+        assert_eq!(state.battery, Some((80, "discharging".to_string())));
+        assert!(state.buttons.is_empty());
+        assert_eq!(state.left_stick, (128, 128));
+        assert_eq!(state.right_stick, (128, 128));
+        assert_eq!((state.left_trigger, state.right_trigger), (0, 0));
+    }
+
+    #[test]
+    fn buttons_sticks_and_triggers() {
+        let state = state_after(13);
+        assert_eq!(state.buttons, buttons(&["cross"]));
+        assert_eq!(state.left_stick, (60, 110));
+        assert_eq!(state.left_trigger, 120);
+        assert_eq!(state.gyro, [-3, 12, 0]);
+        assert_eq!(state.accel, [10, 8150, -300]);
+        assert_eq!(state.touches, [Some((960, 540)), None]);
+
+        let state = state_after(19);
+        assert_eq!(state.buttons, buttons(&["cross", "r1"]));
+        assert_eq!(state.right_stick, (200, 128));
+        assert_eq!(state.right_trigger, 255);
+        assert_eq!(state.touches, [Some((1200, 600)), Some((300, 200))]);
+        assert_eq!(state.gyro, [40, -25, 3]);
+        // Only gyro came again, accel keeps its last value
+        assert_eq!(state.accel, [10, 8150, -300]);
+    }
+
+    #[test]
+    fn release_and_disconnect() {
+        let state = state_after(27);
+        assert!(state.buttons.is_empty());
+        assert_eq!(state.left_stick, (128, 128));
+        assert_eq!(state.right_stick, (128, 128));
+        assert_eq!((state.left_trigger, state.right_trigger), (0, 0));
+        assert_eq!(state.touches, [None, None]);
+        assert_eq!(state.battery, Some((85, "charging".to_string())));
+        assert!(state.connected);
+
+        assert!(!state_after(28).connected);
+    }
+
+    #[test]
+    fn battery_percent_sign() {
+        assert_eq!(
+            parse_line("battery: 40% discharging"),
+            Some(InputEvent::Battery {
+                level: 40,
+                state: "discharging".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_lines() {
+        assert_eq!(parse_line(""), None);
+        assert_eq!(parse_line("garbage"), None);
+        assert_eq!(parse_line("unknown: 1 2"), None);
+        assert_eq!(parse_line("left_stick: 128"), None);
+        assert_eq!(parse_line("left_stick: 300 128"), None);
+        // A bad value in the middle mustn't shift the others into place
+        assert_eq!(parse_line("gyro: 1 x 2 3"), None);
+        assert_eq!(parse_line("touch0: 1 960"), None);
+        assert_eq!(parse_line("l2: -1"), None);
+        assert_eq!(parse_line("battery: full"), None);
+    }
+
+    /// Waits up to a few seconds for the next event, `None` once the
+    /// session has closed the channel
+    fn next_event(receiver: &async_channel::Receiver<InputEvent>) -> Option<InputEvent> {
+        let (result, done) = std::sync::mpsc::channel();
+        let receiver = receiver.clone();
+        thread::spawn(move || {
+            let _ = result.send(receiver.recv_blocking().ok());
+        });
+        done.recv_timeout(std::time::Duration::from_secs(5))
+            .expect("monitor session hung")
+    }
+
+    #[test]
+    fn session_reads_until_dropped() {
+        let backend = MockBackend::new()
+            .with_device("84:30:95:aa:bb:cc")
+            .with_monitor_fixture("l2: 10\nnot a report\nr2: 20\n");
+        let (sender, receiver) = async_channel::unbounded();
+        let session = MonitorSession::start(backend.for_device("84:30:95:aa:bb:cc"), sender);
+
+        assert_eq!(next_event(&receiver), Some(InputEvent::LeftTrigger(10)));
+        assert_eq!(next_event(&receiver), Some(InputEvent::RightTrigger(20)));
+
+        // A live monitor keeps running after its last report
+        drop(session);
+        assert_eq!(next_event(&receiver), None);
+        assert_eq!(backend.calls(), ["-d 84:30:95:aa:bb:cc monitor"]);
+    }
+
+    #[test]
+    fn session_that_fails_closes_the_channel() {
+        let (sender, receiver) = async_channel::unbounded();
+        let _session = MonitorSession::start(Arc::new(MockBackend::new().failing()), sender);
+        assert_eq!(next_event(&receiver), None);
+    }
+}
@@ -0,0 +1,521 @@
+use log::{debug, error, info};
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+
+use crate::devices::{DeviceCache, DeviceRegistry};
+use crate::error::DualsenseError;
+use crate::structs::Trigger;
+use crate::worker::{DeviceCommand, Worker};
+
+/// Port TouchOSC and most OSC tools send to by default
+pub const DEFAULT_PORT: u16 = 9000;
+
+/// Every address we handle starts with this
+const ADDRESS_PREFIX: &str = "/dualsense";
+
+const BUNDLE_TAG: &[u8] = b"#bundle\0";
+
+/// One OSC argument, by type tag
+#[derive(Debug, Clone, PartialEq)]
+pub enum Argument {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Blob(Vec<u8>),
+    Long(i64),
+    Double(f64),
+    Bool(bool),
+    Nil,
+    Impulse,
+}
+
+impl Argument {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Argument::Int(i) => Some(f64::from(*i)),
+            Argument::Float(f) => Some(f64::from(*f)),
+            Argument::Long(l) => Some(*l as f64),
+            Argument::Double(d) => Some(*d),
+            Argument::Bool(b) => Some(f64::from(u8::from(*b))),
+            Argument::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Rounded and clamped to a byte
+    fn as_byte(&self) -> Option<u8> {
+        self.as_f64().map(|v| v.round().clamp(0.0, 255.0) as u8)
+    }
+
+    /// A colour channel, floats are taken as 0.0-1.0 like TouchOSC faders
+    /// send them
+    fn as_channel(&self) -> Option<u8> {
+        match self {
+            Argument::Float(_) | Argument::Double(_) => self
+                .as_f64()
+                .map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8),
+            _ => self.as_byte(),
+        }
+    }
+
+    /// Toggle buttons send 0/1, `T`/`F` or nothing at all
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            Argument::Impulse | Argument::Nil => Some(true),
+            Argument::String(s) => match s.trim() {
+                "on" | "true" => Some(true),
+                "off" | "false" => Some(false),
+                _ => None,
+            },
+            _ => self.as_f64().map(|v| v >= 0.5),
+        }
+    }
+}
+
+/// An OSC message, bundles are flattened into these
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub address: String,
+    pub args: Vec<Argument>,
+}
+
+fn malformed(what: &str) -> DualsenseError {
+    DualsenseError::InvalidArgument(format!("OSC packet: {what}"))
+}
+
+/// Reads through a packet, everything in OSC is aligned to 4 bytes
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DualsenseError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| malformed("truncated"))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DualsenseError> {
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.take(N)?);
+        Ok(bytes)
+    }
+
+    fn skip_padding(&mut self) {
+        self.position = (self.position + 3) & !3;
+    }
+
+    /// Null terminated, padded to a multiple of 4
+    fn string(&mut self) -> Result<String, DualsenseError> {
+        let rest = &self.data[self.position.min(self.data.len())..];
+        let len = rest
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| malformed("unterminated string"))?;
+        let string = std::str::from_utf8(&rest[..len])
+            .map_err(|_| malformed("string isn't UTF-8"))?
+            .to_string();
+        self.position += len + 1;
+        self.skip_padding();
+        Ok(string)
+    }
+
+    /// Size prefixed, padded to a multiple of 4
+    fn blob(&mut self) -> Result<Vec<u8>, DualsenseError> {
+        let len = u32::from_be_bytes(self.array()?) as usize;
+        let blob = self.take(len)?.to_vec();
+        self.skip_padding();
+        Ok(blob)
+    }
+
+    fn argument(&mut self, tag: char) -> Result<Option<Argument>, DualsenseError> {
+        Ok(Some(match tag {
+            'i' => Argument::Int(i32::from_be_bytes(self.array()?)),
+            'f' => Argument::Float(f32::from_be_bytes(self.array()?)),
+            's' | 'S' => Argument::String(self.string()?),
+            'b' => Argument::Blob(self.blob()?),
+            'h' => Argument::Long(i64::from_be_bytes(self.array()?)),
+            'd' => Argument::Double(f64::from_be_bytes(self.array()?)),
+            'T' => Argument::Bool(true),
+            'F' => Argument::Bool(false),
+            'N' => Argument::Nil,
+            'I' => Argument::Impulse,
+            // Time tags, colours and MIDI messages are all 4 or 8 bytes we
+            // have no use for
+            't' => {
+                self.take(8)?;
+                return Ok(None);
+            }
+            'c' | 'r' | 'm' => {
+                self.take(4)?;
+                return Ok(None);
+            }
+            // Arrays are read as the arguments inside them
+            '[' | ']' => return Ok(None),
+            tag => return Err(malformed(&format!("unknown type tag '{tag}'"))),
+        }))
+    }
+}
+
+fn decode_message(data: &[u8]) -> Result<Message, DualsenseError> {
+    let mut reader = Reader::new(data);
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        return Err(malformed(&format!("bad address {address:?}")));
+    }
+
+    // Very old senders leave out the type tags
+    if reader.is_empty() {
+        return Ok(Message {
+            address,
+            args: Vec::new(),
+        });
+    }
+
+    let tags = reader.string()?;
+    let tags = tags
+        .strip_prefix(',')
+        .ok_or_else(|| malformed("missing type tags"))?;
+
+    let mut args = Vec::new();
+    for tag in tags.chars() {
+        if let Some(arg) = reader.argument(tag)? {
+            args.push(arg);
+        }
+    }
+
+    Ok(Message { address, args })
+}
+
+fn decode_into(data: &[u8], messages: &mut Vec<Message>) -> Result<(), DualsenseError> {
+    if !data.starts_with(BUNDLE_TAG) {
+        messages.push(decode_message(data)?);
+        return Ok(());
+    }
+
+    let mut reader = Reader::new(data);
+    reader.take(BUNDLE_TAG.len())?;
+    // Time tag, everything is applied as soon as it arrives
+    reader.take(8)?;
+
+    while !reader.is_empty() {
+        let len = u32::from_be_bytes(reader.array()?) as usize;
+        decode_into(reader.take(len)?, messages)?;
+    }
+    Ok(())
+}
+
+/// Decodes an OSC packet, flattening bundles into their messages
+pub fn decode_packet(data: &[u8]) -> Result<Vec<Message>, DualsenseError> {
+    let mut messages = Vec::new();
+    decode_into(data, &mut messages)?;
+    Ok(messages)
+}
+
+/// Turns one message into a command
+///
+/// ```text
+/// /dualsense/lightbar r g b [brightness]
+/// /dualsense/lightbar/on 1
+/// /dualsense/trigger/left/feedback position strength
+/// /dualsense/trigger/right/off
+/// /dualsense/playerleds n
+/// /dualsense/microphone/led 1
+/// /dualsense/volume n
+/// ```
+///
+/// `None` for addresses outside `/dualsense`
+pub fn message_command(message: &Message) -> Result<Option<DeviceCommand>, DualsenseError> {
+    let Some(path) = message.address.strip_prefix(ADDRESS_PREFIX) else {
+        return Ok(None);
+    };
+    let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+    let args = &message.args;
+
+    let missing = || {
+        DualsenseError::InvalidArgument(format!(
+            "OSC {}: missing or bad arguments {:?}",
+            message.address, args
+        ))
+    };
+    let byte = |index: usize| args.get(index).and_then(Argument::as_byte);
+    let flag = || args.first().map_or(Some(true), Argument::as_bool);
+
+    let command = match parts[..] {
+        ["lightbar"] => {
+            let channels = args
+                .iter()
+                .map(Argument::as_channel)
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(missing)?;
+            match channels[..] {
+                [r, g, b] => DeviceCommand::LightbarColour(vec![r, g, b, 255]),
+                [r, g, b, brightness, ..] => {
+                    DeviceCommand::LightbarColour(vec![r, g, b, brightness])
+                }
+                _ => return Err(missing()),
+            }
+        }
+        ["lightbar", "on"] => DeviceCommand::Lightbar(flag().ok_or_else(missing)?),
+        ["trigger", side, mode] => {
+            let values = args
+                .iter()
+                .map(|arg| arg.as_byte().map(|v| v.to_string()))
+                .collect::<Option<Vec<String>>>()
+                .ok_or_else(missing)?;
+            DeviceCommand::Trigger(Trigger::parse(side, mode, &values)?)
+        }
+        ["playerleds"] => DeviceCommand::PlayerLeds(byte(0).ok_or_else(missing)?.min(5)),
+        ["microphone"] => DeviceCommand::Microphone(flag().ok_or_else(missing)?),
+        ["microphone", "led"] => DeviceCommand::MicrophoneLed(flag().ok_or_else(missing)?),
+        ["volume"] => DeviceCommand::Volume(byte(0).ok_or_else(missing)?),
+        ["restore"] => DeviceCommand::Restore,
+        _ => {
+            return Err(DualsenseError::Unsupported(format!(
+                "OSC address {}",
+                message.address
+            )))
+        }
+    };
+
+    Ok(Some(command))
+}
+
+/// Queues a command for every message in `packet` on every connected pad
+///
+/// Like the DSX server it doesn't wait, the worker only keeps the newest
+/// pending trigger and colour values. Commands are transient so a fader
+/// doesn't rewrite the saved state, `/dualsense/restore` goes back to it
+fn handle_packet(devices: &mut DeviceCache, worker: &Worker, packet: &[u8]) {
+    let messages = match decode_packet(packet) {
+        Ok(messages) => messages,
+        Err(err) => {
+            error!("{}", err);
+            return;
+        }
+    };
+
+    for message in &messages {
+        match message_command(message) {
+            Ok(Some(command)) => {
+                for device in devices.handles() {
+                    worker.send_transient(device, command.clone());
+                }
+            }
+            Ok(None) => debug!("Ignoring OSC message {}", message.address),
+            Err(err) => error!("Skipping OSC message: {}", err),
+        }
+    }
+}
+
+/// Listens for OSC messages on `port`
+///
+/// Only on localhost unless `listen_all` is set, e.g. for TouchOSC on a
+/// phone
+pub fn serve(
+    registry: Arc<DeviceRegistry>,
+    worker: Worker,
+    port: u16,
+    listen_all: bool,
+) -> io::Result<()> {
+    let address = if listen_all {
+        Ipv4Addr::UNSPECIFIED
+    } else {
+        Ipv4Addr::LOCALHOST
+    };
+    let socket = UdpSocket::bind((address, port))?;
+
+    thread::Builder::new()
+        .name("osc".to_string())
+        .spawn(move || {
+            let mut devices = DeviceCache::new(registry);
+            let mut buffer = [0u8; 8192];
+            loop {
+                match socket.recv_from(&mut buffer) {
+                    Ok((len, _)) => handle_packet(&mut devices, &worker, &buffer[..len]),
+                    Err(err) => error!("Failed to receive OSC packet: {}", err),
+                }
+            }
+        })?;
+
+    info!("OSC server listening on {}:{}", address, port);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{mock_call, MockDevices};
+    use crate::structs::TriggerEffect;
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    fn message(address: &str, args: Vec<Argument>) -> Message {
+        Message {
+            address: address.to_string(),
+            args,
+        }
+    }
+
+    #[test]
+    fn lightbar_floats_are_faders() {
+        let command = message_command(&message(
+            "/dualsense/lightbar",
+            vec![
+                Argument::Float(1.0),
+                Argument::Float(0.5),
+                Argument::Float(0.0),
+            ],
+        ));
+        assert!(matches!(
+            command,
+            Ok(Some(DeviceCommand::LightbarColour(colour))) if colour == [255, 128, 0, 255]
+        ));
+    }
+
+    #[test]
+    fn bad_argument_fails_the_message() {
+        // Skipping the blob would shift blue into red's place
+        let lightbar = message(
+            "/dualsense/lightbar",
+            vec![
+                Argument::Blob(vec![1]),
+                Argument::Int(10),
+                Argument::Int(20),
+                Argument::Int(30),
+            ],
+        );
+        assert!(message_command(&lightbar).is_err());
+
+        let trigger = message(
+            "/dualsense/trigger/left/feedback",
+            vec![
+                Argument::String("x".to_string()),
+                Argument::Int(3),
+                Argument::Int(6),
+            ],
+        );
+        assert!(message_command(&trigger).is_err());
+    }
+
+    #[test]
+    fn trigger_from_address() {
+        let command = message_command(&message(
+            "/dualsense/trigger/right/feedback",
+            vec![Argument::Int(3), Argument::Float(6.0)],
+        ));
+        let Ok(Some(DeviceCommand::Trigger(trigger))) = command else {
+            panic!("not a trigger: {command:?}");
+        };
+        assert_eq!(trigger.side, "right");
+        assert!(matches!(
+            trigger.effect,
+            TriggerEffect::Feedback {
+                position: 3,
+                strength: 6
+            }
+        ));
+    }
+
+    #[test]
+    fn other_addresses() {
+        assert!(matches!(
+            message_command(&message("/other/volume", vec![Argument::Int(3)])),
+            Ok(None)
+        ));
+        assert!(message_command(&message("/dualsense/nothing", vec![])).is_err());
+    }
+
+    #[test]
+    fn decodes_bundles() {
+        let mut inner = b"/dualsense/volume\0\0\0,i\0\0".to_vec();
+        inner.extend_from_slice(&100i32.to_be_bytes());
+        let mut packet = BUNDLE_TAG.to_vec();
+        packet.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        packet.extend_from_slice(&(inner.len() as u32).to_be_bytes());
+        packet.extend_from_slice(&inner);
+
+        assert_eq!(
+            decode_packet(&packet).unwrap(),
+            vec![message("/dualsense/volume", vec![Argument::Int(100)])]
+        );
+        assert!(decode_packet(&packet[..packet.len() - 2]).is_err());
+    }
+
+    /// An OSC message with int arguments
+    fn encode(address: &str, args: &[i32]) -> Vec<u8> {
+        fn padded(bytes: &[u8]) -> Vec<u8> {
+            let mut bytes = bytes.to_vec();
+            bytes.push(0);
+            bytes.resize(bytes.len().next_multiple_of(4), 0);
+            bytes
+        }
+
+        let mut packet = padded(address.as_bytes());
+        packet.extend(padded(format!(",{}", "i".repeat(args.len())).as_bytes()));
+        for arg in args {
+            packet.extend_from_slice(&arg.to_be_bytes());
+        }
+        packet
+    }
+
+    /// Backend calls once `count` new ones have arrived, nothing answers
+    /// OSC so there's no reply to wait for
+    fn wait_for_calls(devices: &MockDevices, count: usize) -> Vec<String> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut calls = Vec::new();
+        while calls.len() < count && Instant::now() < deadline {
+            calls.extend(devices.new_calls());
+            thread::sleep(Duration::from_millis(10));
+        }
+        calls
+    }
+
+    #[test]
+    fn changes_are_transient_until_restore() {
+        let devices = MockDevices::new("osc-restore");
+        let port = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|socket| socket.local_addr())
+            .unwrap()
+            .port();
+        serve(
+            Arc::clone(&devices.registry),
+            devices.worker.clone(),
+            port,
+            false,
+        )
+        .unwrap();
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        client.connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        let user = devices.state();
+
+        client.send(&encode("/dualsense/volume", &[100])).unwrap();
+        assert_eq!(wait_for_calls(&devices, 1), [mock_call("volume 100")]);
+        assert_eq!(devices.state().volume, user.volume);
+        assert_eq!(fs::read_dir(&devices.app_paths.config).unwrap().count(), 0);
+
+        client.send(&encode("/dualsense/restore", &[])).unwrap();
+        let calls = wait_for_calls(&devices, 8);
+        assert!(
+            calls.contains(&mock_call(&format!("volume {}", user.volume))),
+            "{calls:?}"
+        );
+        assert_eq!(fs::read_dir(&devices.app_paths.config).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,503 @@
+use dirs_next as dirs;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::devices::{DeviceHandle, DeviceRegistry};
+use crate::error::DualsenseError;
+use crate::save::{load_profile, AppPaths};
+use crate::structs::{Controller, Trigger};
+use crate::worker::{DeviceCommand, DeviceEvent, Output, Worker};
+
+const SOCKET_NAME: &str = "dualsensectl-gui.sock";
+
+/// One line from a client
+///
+/// ```text
+/// {"id":1,"method":"set_trigger","params":{"side":"right","effect":{"Feedback":{"position":3,"strength":6}}}}
+/// ```
+#[derive(Deserialize, Debug)]
+struct Request {
+    /// Echoed back in the response so clients can match them up
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default = "no_params")]
+    params: Value,
+}
+
+fn no_params() -> Value {
+    json!({})
+}
+
+/// `device` is a serial, leave it out for the first connected pad
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct DeviceParams {
+    device: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LightbarParams {
+    #[serde(default)]
+    device: Option<String>,
+    #[serde(default)]
+    enabled: Option<bool>,
+    /// R, G, B, brightness, turns the lightbar on
+    #[serde(default)]
+    colour: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize)]
+struct TriggerParams {
+    #[serde(default)]
+    device: Option<String>,
+    #[serde(flatten)]
+    trigger: Trigger,
+}
+
+#[derive(Deserialize)]
+struct ApplyParams {
+    #[serde(default)]
+    device: Option<String>,
+    state: Controller,
+}
+
+#[derive(Deserialize)]
+struct ProfileParams {
+    #[serde(default)]
+    device: Option<String>,
+    name: String,
+}
+
+/// Pushed to subscribed clients, one per line
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    StateChanged {
+        device: Option<&'a str>,
+        state: &'a Controller,
+    },
+    Battery {
+        device: Option<&'a str>,
+        level: u8,
+        state: String,
+    },
+}
+
+/// `$XDG_RUNTIME_DIR/dualsensectl-gui.sock`
+pub fn socket_path() -> Option<PathBuf> {
+    dirs::runtime_dir().map(|dir| dir.join(SOCKET_NAME))
+}
+
+/// Listening socket, removed again when dropped
+pub struct RpcServer {
+    path: PathBuf,
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Runs requests by method name, shared with the HTTP API
+pub struct Handler {
+    registry: Arc<DeviceRegistry>,
+    worker: Worker,
+    app_paths: Arc<AppPaths>,
+}
+
+impl Handler {
+    pub fn new(registry: Arc<DeviceRegistry>, worker: Worker, app_paths: Arc<AppPaths>) -> Self {
+        Self {
+            registry,
+            worker,
+            app_paths,
+        }
+    }
+
+    pub fn worker(&self) -> &Worker {
+        &self.worker
+    }
+
+    fn device(&self, serial: Option<&str>) -> DeviceHandle {
+        match serial {
+            Some(serial) => self.registry.handle(Some(serial)),
+            None => self.registry.connected_handles().remove(0),
+        }
+    }
+
+    fn run(&self, serial: Option<&str>, command: DeviceCommand) -> Result<Output, DualsenseError> {
+        self.worker.execute(&self.device(serial), command).result
+    }
+
+    fn state(&self, serial: Option<&str>) -> Result<Value, DualsenseError> {
+        let state = self
+            .device(serial)
+            .controller
+            .lock()
+            .map(|c| c.clone())
+            .unwrap_or_default();
+        to_value(&state)
+    }
+
+    pub fn handle(&self, method: &str, params: Value) -> Result<Value, DualsenseError> {
+        match method {
+            "set_lightbar" => {
+                let params: LightbarParams = from_value(params)?;
+                let device = params.device.as_deref();
+                match (params.colour, params.enabled) {
+                    (Some(colour), _) => self.run(device, DeviceCommand::LightbarColour(colour))?,
+                    (None, Some(enabled)) => self.run(device, DeviceCommand::Lightbar(enabled))?,
+                    (None, None) => {
+                        return Err(DualsenseError::InvalidArgument(
+                            "set_lightbar needs colour or enabled".to_string(),
+                        ))
+                    }
+                };
+                self.state(device)
+            }
+            "set_trigger" => {
+                let params: TriggerParams = from_value(params)?;
+                self.run(
+                    params.device.as_deref(),
+                    DeviceCommand::Trigger(params.trigger),
+                )?;
+                self.state(params.device.as_deref())
+            }
+            "apply" => {
+                let params: ApplyParams = from_value(params)?;
+                applied(self.run(params.device.as_deref(), DeviceCommand::Apply(params.state))?)
+            }
+            "apply_profile" => {
+                let params: ProfileParams = from_value(params)?;
+                let profile = load_profile(&params.name, &self.app_paths).map_err(|err| {
+                    DualsenseError::InvalidArgument(format!("Profile '{}': {err}", params.name))
+                })?;
+                applied(self.run(params.device.as_deref(), DeviceCommand::Apply(profile))?)
+            }
+            "get_state" => {
+                let params: DeviceParams = from_value(params)?;
+                self.state(params.device.as_deref())
+            }
+            "get_battery" => {
+                let params: DeviceParams = from_value(params)?;
+                match self.run(params.device.as_deref(), DeviceCommand::Battery)? {
+                    Output::Battery(status) => Ok(json!({
+                        "level": status.level,
+                        "state": status.state.to_string(),
+                    })),
+                    _ => Ok(Value::Null),
+                }
+            }
+            method => Err(DualsenseError::InvalidArgument(format!(
+                "Unknown method: {method}"
+            ))),
+        }
+    }
+}
+
+fn from_value<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, DualsenseError> {
+    serde_json::from_value(params).map_err(|err| DualsenseError::InvalidArgument(err.to_string()))
+}
+
+fn to_value<T: Serialize>(value: &T) -> Result<Value, DualsenseError> {
+    serde_json::to_value(value).map_err(|err| DualsenseError::CommandFailed(err.to_string()))
+}
+
+/// Result of `apply`/`apply_profile`, an error if anything was rolled back
+fn applied(output: Output) -> Result<Value, DualsenseError> {
+    match output {
+        Output::Applied(report) if !report.succeeded() => {
+            Err(DualsenseError::CommandFailed(report.to_string()))
+        }
+        Output::Applied(report) => Ok(Value::String(report.to_string())),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// What subscribers are sent for a worker event
+pub fn event_value(event: &DeviceEvent) -> Option<Value> {
+    let event = match event {
+        DeviceEvent::StateChanged { serial, state } => Event::StateChanged {
+            device: serial.as_deref(),
+            state,
+        },
+        DeviceEvent::Battery { serial, status } => Event::Battery {
+            device: serial.as_deref(),
+            level: status.level,
+            state: status.state.to_string(),
+        },
+    };
+    serde_json::to_value(&event).ok()
+}
+
+fn write_line(stream: &Mutex<UnixStream>, value: &Value) -> io::Result<()> {
+    let mut stream = stream.lock().unwrap_or_else(|e| e.into_inner());
+    writeln!(stream, "{value}")
+}
+
+/// Forwards worker events to a subscribed client until it goes away
+fn spawn_subscription(worker: &Worker, stream: Arc<Mutex<UnixStream>>) {
+    let events = worker.subscribe();
+    thread::spawn(move || {
+        while let Ok(event) = events.recv_blocking() {
+            let Some(event) = event_value(&event) else {
+                continue;
+            };
+            if write_line(&stream, &event).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn serve_client(handler: &Handler, stream: UnixStream) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let stream = Arc::new(Mutex::new(stream));
+    let mut subscribed = false;
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) if request.method == "subscribe" => {
+                if !subscribed {
+                    spawn_subscription(handler.worker(), Arc::clone(&stream));
+                    subscribed = true;
+                }
+                json!({ "id": request.id, "result": "subscribed" })
+            }
+            Ok(request) => match handler.handle(&request.method, request.params) {
+                Ok(result) => json!({ "id": request.id, "result": result }),
+                Err(err) => json!({ "id": request.id, "error": err.to_string() }),
+            },
+            Err(err) => json!({ "id": null, "error": format!("Invalid request: {err}") }),
+        };
+
+        write_line(&stream, &response)?;
+    }
+
+    Ok(())
+}
+
+/// Listens on `$XDG_RUNTIME_DIR/dualsensectl-gui.sock` for newline
+/// delimited JSON requests
+///
+/// Methods: `set_lightbar`, `set_trigger`, `apply`, `apply_profile`,
+/// `get_state`, `get_battery` and `subscribe`, which streams state and
+/// battery events on the same connection
+pub fn serve(
+    registry: Arc<DeviceRegistry>,
+    worker: Worker,
+    app_paths: Arc<AppPaths>,
+) -> io::Result<RpcServer> {
+    let path = socket_path()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "$XDG_RUNTIME_DIR not set"))?;
+    serve_at(path, registry, worker, app_paths)
+}
+
+fn serve_at(
+    path: PathBuf,
+    registry: Arc<DeviceRegistry>,
+    worker: Worker,
+    app_paths: Arc<AppPaths>,
+) -> io::Result<RpcServer> {
+    // Left behind by a crash, a live one would still accept connections
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is already in use", path.display()),
+            ));
+        }
+        fs::remove_file(&path)?;
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+    let handler = Arc::new(Handler::new(registry, worker, app_paths));
+
+    thread::Builder::new()
+        .name("rpc".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        error!("Failed to accept RPC client: {}", err);
+                        continue;
+                    }
+                };
+                let handler = Arc::clone(&handler);
+                thread::spawn(move || {
+                    if let Err(err) = serve_client(&handler, stream) {
+                        error!("RPC client error: {}", err);
+                    }
+                });
+            }
+        })?;
+
+    info!("RPC socket listening at {}", path.display());
+    Ok(RpcServer { path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{mock_call, MockDevices, MOCK_SERIAL};
+    use std::time::Duration;
+
+    /// `serve_at` on a socket in the test's temporary dir
+    struct Server {
+        devices: MockDevices,
+        path: PathBuf,
+        _server: RpcServer,
+    }
+
+    impl Server {
+        fn start(name: &str) -> Self {
+            let devices = MockDevices::new(name);
+            let path = devices.app_paths.config.join(SOCKET_NAME);
+            let server = serve_at(
+                path.clone(),
+                Arc::clone(&devices.registry),
+                devices.worker.clone(),
+                Arc::clone(&devices.app_paths),
+            )
+            .unwrap();
+            Self {
+                devices,
+                path,
+                _server: server,
+            }
+        }
+
+        fn connect(&self) -> Client {
+            let stream = UnixStream::connect(&self.path).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            Client {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                stream,
+            }
+        }
+    }
+
+    struct Client {
+        stream: UnixStream,
+        reader: BufReader<UnixStream>,
+    }
+
+    impl Client {
+        fn send(&mut self, line: &str) -> Value {
+            writeln!(self.stream, "{line}").unwrap();
+            self.read()
+        }
+
+        /// The next line the server sends
+        fn read(&mut self) -> Value {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).expect("no RPC reply");
+            serde_json::from_str(&line).unwrap()
+        }
+    }
+
+    #[test]
+    fn set_trigger_and_get_state() {
+        let server = Server::start("rpc-trigger");
+        let mut client = server.connect();
+
+        let response = client.send(
+            r#"{"id":1,"method":"set_trigger","params":{"side":"right","effect":{"Feedback":{"position":3,"strength":6}}}}"#,
+        );
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["trigger"]["side"], "right");
+        assert_eq!(
+            server.devices.new_calls(),
+            [mock_call("trigger right feedback 3 6")]
+        );
+
+        let response = client.send(&format!(
+            r#"{{"id":"two","method":"get_state","params":{{"device":"{MOCK_SERIAL}"}}}}"#
+        ));
+        assert_eq!(response["id"], "two");
+        assert_eq!(
+            response["result"]["trigger"]["effect"]["Feedback"]["strength"],
+            6
+        );
+    }
+
+    #[test]
+    fn errors_keep_the_connection_open() {
+        let server = Server::start("rpc-errors");
+        let mut client = server.connect();
+
+        let response = client.send(r#"{"id":1,"method":"explode"}"#);
+        assert_eq!(response["id"], 1);
+        assert!(response["error"]
+            .as_str()
+            .unwrap()
+            .contains("Unknown method"));
+
+        let response = client.send(r#"{"id":2,"method":"set_lightbar","params":{}}"#);
+        assert!(response["error"].as_str().unwrap().contains("colour"));
+
+        let response = client.send("not json");
+        assert_eq!(response["id"], Value::Null);
+        assert!(response["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid request"));
+
+        let response = client.send(r#"{"id":3,"method":"get_battery"}"#);
+        assert_eq!(response["result"]["level"], 100);
+        // Only the battery read reached the pad
+        assert_eq!(server.devices.new_calls(), [mock_call("battery")]);
+    }
+
+    #[test]
+    fn subscribers_get_events() {
+        let server = Server::start("rpc-subscribe");
+        let mut subscriber = server.connect();
+        let mut client = server.connect();
+
+        let response = subscriber.send(r#"{"id":1,"method":"subscribe"}"#);
+        assert_eq!(response["result"], "subscribed");
+
+        client.send(r#"{"id":1,"method":"set_lightbar","params":{"colour":[10,20,30,255]}}"#);
+        let event = subscriber.read();
+        assert_eq!(event["event"], "state_changed");
+        assert_eq!(event["device"], MOCK_SERIAL);
+        assert_eq!(event["state"]["lightbar_colour"], json!([10, 20, 30, 255]));
+
+        client.send(r#"{"id":2,"method":"get_battery"}"#);
+        let event = subscriber.read();
+        assert_eq!(event["event"], "battery");
+        assert_eq!(event["level"], 100);
+    }
+
+    #[test]
+    fn socket_is_private_and_removed_on_drop() {
+        let server = Server::start("rpc-socket");
+        let mode = fs::metadata(&server.path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let path = server.path.clone();
+        drop(server);
+        assert!(!path.exists());
+    }
+}
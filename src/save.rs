@@ -1,13 +1,122 @@
-use crate::structs::Controller;
+use crate::structs::{Controller, Settings};
 use dirs_next as dirs;
+use log::{error, warn};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, Read, Seek, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 const MAX_LOG_SIZE: usize = 1024 * 1024; // 1 MB
 const STATE_FILE_NAME: &str = "state.json";
+const SETTINGS_FILE_NAME: &str = "settings.json";
+
+/// Layout version of state and profile files
+///
+/// Bump it and add a step to `MIGRATIONS` whenever `Controller` changes
+/// in a way older files can't be read as
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n - 1]` takes a file from version `n` to `n + 1`
+///
+/// Files from before `schema_version` was written count as version 1, they
+/// have the same fields
+const MIGRATIONS: [fn(&mut Map<String, Value>); 0] = [];
+
+#[derive(Serialize)]
+struct Versioned<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    controller: &'a Controller,
+}
+
+/// State or profile file contents, tagged with `SCHEMA_VERSION`
+pub fn controller_to_json(controller: &Controller) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&Versioned {
+        schema_version: SCHEMA_VERSION,
+        controller,
+    })
+}
+
+/// `schema_version` of a state or profile object, 1 if it has none
+fn schema_version(state: &Map<String, Value>) -> u64 {
+    state
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .map_or(1, |v| v.max(1))
+}
+
+/// Reads a state or profile file of any version, migrating older layouts
+///
+/// Missing fields take their defaults, a newer file loads what we
+/// understand of it
+pub fn controller_from_json(json: &str) -> serde_json::Result<Controller> {
+    let mut value: Value = serde_json::from_str(json)?;
+
+    if let Value::Object(state) = &mut value {
+        let version = schema_version(state) as usize;
+        state.remove("schema_version");
+        for migrate in MIGRATIONS.iter().skip(version - 1) {
+            migrate(state);
+        }
+    }
+
+    serde_json::from_value(value)
+}
+
+/// Moves a file we couldn't read to `<file>.<timestamp>.bak`, so the next
+/// save doesn't overwrite what the user had
+fn back_up_unreadable(path: &Path) {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(
+        ".{}.bak",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    ));
+
+    match fs::rename(path, &backup) {
+        Ok(()) => warn!(
+            "Moved unreadable {} to {}",
+            path.display(),
+            Path::new(&backup).display()
+        ),
+        Err(err) => error!("Failed to back up {}: {err}", path.display()),
+    }
+}
+
+/// Copies a file written by a newer version to `<file>.schema-<n>.bak`
+/// before loading it, the next save would drop the fields we don't know
+///
+/// Only the first copy is kept, so the backup is the untouched original
+fn back_up_newer(path: &Path, json: &str) {
+    let Ok(Value::Object(state)) = serde_json::from_str(json) else {
+        return;
+    };
+    let version = schema_version(&state);
+    if version <= u64::from(SCHEMA_VERSION) {
+        return;
+    }
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".schema-{version}.bak"));
+    let backup = PathBuf::from(backup);
+    if backup.exists() {
+        return;
+    }
+    match fs::copy(path, &backup) {
+        Ok(_) => warn!(
+            "{} has schema version {version}, newer than {SCHEMA_VERSION}, loading what we \
+             understand and keeping the original as {}",
+            path.display(),
+            backup.display()
+        ),
+        Err(err) => warn!(
+            "{} has schema version {version}, newer than {SCHEMA_VERSION}, and couldn't be \
+             backed up: {err}",
+            path.display()
+        ),
+    }
+}
 
 // TODO: Refactor out or further integrate
 #[derive(Serialize, Deserialize, Debug)]
@@ -41,7 +150,7 @@ impl AppPaths {
                 let fallback = dirs::home_dir()
                     .expect("Failed to find home directory.")
                     .join(".local/share");
-                println!(
+                eprintln!(
                     "$XDG_DATA_HOME not set, falling back to {}",
                     fallback.display()
                 );
@@ -51,7 +160,7 @@ impl AppPaths {
 
         let logs_path = data_local_path.join("logs");
         if !logs_path.exists() {
-            println!(
+            eprintln!(
                 "Attempting to create logs directory at: {}",
                 logs_path.display()
             );
@@ -60,7 +169,7 @@ impl AppPaths {
 
         let profiles_path = data_local_path.join("profiles");
         if !profiles_path.exists() {
-            println!(
+            eprintln!(
                 "Attempting to create profiles directory at: {}",
                 profiles_path.display()
             );
@@ -72,7 +181,7 @@ impl AppPaths {
             .join("dualsensectl-gui");
 
         if !config_path.exists() {
-            println!(
+            eprintln!(
                 "Attempting to create config directory at: {}",
                 config_path.display()
             );
@@ -81,9 +190,9 @@ impl AppPaths {
 
         let log_file_path = logs_path.join("dualsensectl.log");
 
-        println!("Config path is: {}", config_path.display());
-        println!("Log file path is: {}", log_file_path.display());
-        println!("Profiles path is: {}", profiles_path.display());
+        eprintln!("Config path is: {}", config_path.display());
+        eprintln!("Log file path is: {}", log_file_path.display());
+        eprintln!("Profiles path is: {}", profiles_path.display());
 
         AppPaths {
             config: config_path,
@@ -94,27 +203,86 @@ impl AppPaths {
     }
 }
 
+#[cfg(test)]
+impl AppPaths {
+    /// Empty directories under the system temp dir, one set per `name`
+    pub fn temporary(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "dualsensectl-gui-test-{}-{name}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+
+        let paths = AppPaths {
+            config: root.join("config"),
+            logs: root.join("logs"),
+            profiles: root.join("profiles"),
+            log_file: root.join("logs/dualsensectl.log"),
+        };
+        for dir in [&paths.config, &paths.logs, &paths.profiles] {
+            fs::create_dir_all(dir).expect("Failed to create test directory");
+        }
+        paths
+    }
+}
+
+/// State file for a device, `state.json` for the default one
+fn state_file(app_paths: &Arc<AppPaths>, device: Option<&str>) -> PathBuf {
+    match device {
+        Some(serial) => {
+            let serial: String = serial.chars().filter(char::is_ascii_alphanumeric).collect();
+            app_paths.config.join(format!("state-{serial}.json"))
+        }
+        None => app_paths.config.join(STATE_FILE_NAME),
+    }
+}
+
 pub fn save_state(controller: &Controller, app_paths: &Arc<AppPaths>) -> io::Result<()> {
-    let state_file = app_paths.config.join(STATE_FILE_NAME);
+    save_device_state(controller, None, app_paths)
+}
+
+pub fn load_state(app_paths: &Arc<AppPaths>) -> Controller {
+    load_device_state(None, app_paths)
+}
+
+pub fn save_device_state(
+    controller: &Controller,
+    device: Option<&str>,
+    app_paths: &Arc<AppPaths>,
+) -> io::Result<()> {
+    let state_file = state_file(app_paths, device);
 
     eprintln!("Saving controller state: {controller:?}");
-    let json = serde_json::to_string_pretty(controller)?;
+    let json = controller_to_json(controller)?;
     let mut file = fs::File::create(state_file)?;
     file.write_all(json.as_bytes())?;
     eprintln!("Controller state saved.");
     Ok(())
 }
 
-pub fn load_state(app_paths: &Arc<AppPaths>) -> Controller {
-    let state_file = app_paths.config.join(STATE_FILE_NAME);
+/// Loads a device's state, a pad we haven't seen before starts from the
+/// default `state.json`
+pub fn load_device_state(device: Option<&str>, app_paths: &Arc<AppPaths>) -> Controller {
+    let state_file = state_file(app_paths, device);
 
     if let Ok(json) = fs::read_to_string(&state_file) {
-        if let Ok(state) = serde_json::from_str::<Controller>(&json) {
-            eprintln!("Loaded state: {state:?}");
-            return state;
-        } else {
-            eprintln!("Failed to deserialize {}", state_file.display());
+        back_up_newer(&state_file, &json);
+        match controller_from_json(&json) {
+            Ok(state) => {
+                eprintln!("Loaded state: {state:?}");
+                return state;
+            }
+            Err(err) => {
+                eprintln!("Failed to deserialize {}: {err}", state_file.display());
+                back_up_unreadable(&state_file);
+            }
         }
+    } else if device.is_some() {
+        eprintln!(
+            "{} not found, using default state file",
+            state_file.display()
+        );
+        return load_device_state(None, app_paths);
     } else {
         eprintln!("{} not found, using default state", state_file.display());
     }
@@ -122,13 +290,45 @@ pub fn load_state(app_paths: &Arc<AppPaths>) -> Controller {
     Controller::default()
 }
 
+pub fn save_settings(settings: &Settings, app_paths: &Arc<AppPaths>) -> io::Result<()> {
+    let settings_file = app_paths.config.join(SETTINGS_FILE_NAME);
+
+    let json = serde_json::to_string_pretty(settings)?;
+    fs::write(settings_file, json)?;
+    eprintln!("Settings saved: {settings:?}");
+    Ok(())
+}
+
+pub fn load_settings(app_paths: &Arc<AppPaths>) -> Settings {
+    let settings_file = app_paths.config.join(SETTINGS_FILE_NAME);
+
+    match fs::read_to_string(&settings_file) {
+        Ok(json) => match serde_json::from_str::<Settings>(&json) {
+            Ok(settings) => {
+                eprintln!("Loaded settings: {settings:?}");
+                settings
+            }
+            Err(err) => {
+                eprintln!("Failed to deserialize {}: {err}", settings_file.display());
+                back_up_unreadable(&settings_file);
+                Settings::default()
+            }
+        },
+        Err(_) => {
+            eprintln!(
+                "{} not found, using default settings",
+                settings_file.display()
+            );
+            Settings::default()
+        }
+    }
+}
+
 pub fn truncate_log(log_path: &std::path::Path) {
     if let Ok(mut file) = File::open(log_path) {
         let metadata = file.metadata().expect("Failed to get file metadata");
         if metadata.len() as usize > MAX_LOG_SIZE {
-            println!(
-                "Truncating log file as it exceeds the max size of {MAX_LOG_SIZE} bytes."
-            );
+            eprintln!("Truncating log file as it exceeds the max size of {MAX_LOG_SIZE} bytes.");
 
             let mut buffer = Vec::with_capacity(MAX_LOG_SIZE / 2);
             file.seek(std::io::SeekFrom::End(-(MAX_LOG_SIZE as i64) / 2))
@@ -147,3 +347,171 @@ pub fn truncate_log(log_path: &std::path::Path) {
         }
     }
 }
+
+/// Names of the saved profiles, sorted
+pub fn list_profiles(app_paths: &Arc<AppPaths>) -> Vec<String> {
+    let mut profiles: Vec<String> = fs::read_dir(&app_paths.profiles)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .filter_map(|path| path.file_stem()?.to_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    profiles.sort();
+    profiles
+}
+
+/// `<profiles>/<name>.json`, refusing names that would end up elsewhere
+fn profile_path(name: &str, app_paths: &Arc<AppPaths>) -> io::Result<PathBuf> {
+    if name.trim().is_empty() || name.starts_with('.') || name.contains(['/', '\\', '\0']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid profile name '{name}'"),
+        ));
+    }
+    Ok(app_paths.profiles.join(format!("{name}.json")))
+}
+
+pub fn load_profile(name: &str, app_paths: &Arc<AppPaths>) -> io::Result<Controller> {
+    let path = profile_path(name, app_paths)?;
+    let json = fs::read_to_string(&path)?;
+    back_up_newer(&path, &json);
+    Ok(controller_from_json(&json)?)
+}
+
+/// The profile marked as default, `None` if there isn't one or it won't load
+pub fn load_default_profile(
+    settings: &Settings,
+    app_paths: &Arc<AppPaths>,
+) -> Option<(String, Controller)> {
+    let name = settings.default_profile.as_ref()?;
+    match load_profile(name, app_paths) {
+        Ok(profile) => Some((name.clone(), profile)),
+        Err(err) => {
+            eprintln!("Failed to load default profile '{name}': {err}");
+            None
+        }
+    }
+}
+
+/// Writes `controller` as profile `name`, replacing one of the same name
+pub fn save_profile(
+    name: &str,
+    controller: &Controller,
+    app_paths: &Arc<AppPaths>,
+) -> io::Result<()> {
+    let path = profile_path(name, app_paths)?;
+    fs::create_dir_all(&app_paths.profiles)?;
+    fs::write(&path, controller_to_json(controller)?)?;
+    eprintln!("Profile saved to {}", path.display());
+    Ok(())
+}
+
+/// Fails rather than replace an existing profile called `new`
+pub fn rename_profile(old: &str, new: &str, app_paths: &Arc<AppPaths>) -> io::Result<()> {
+    let from = profile_path(old, app_paths)?;
+    let to = profile_path(new, app_paths)?;
+    if to.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("A profile called '{new}' already exists"),
+        ));
+    }
+    fs::rename(from, to)
+}
+
+pub fn delete_profile(name: &str, app_paths: &Arc<AppPaths>) -> io::Result<()> {
+    fs::remove_file(profile_path(name, app_paths)?)
+}
+
+/// Copies profile `name` to "`name` copy", numbered if that's taken, and
+/// returns the new name
+pub fn duplicate_profile(name: &str, app_paths: &Arc<AppPaths>) -> io::Result<String> {
+    let from = profile_path(name, app_paths)?;
+    let copy = (1..)
+        .map(|n| match n {
+            1 => format!("{name} copy"),
+            n => format!("{name} copy {n}"),
+        })
+        .find(|copy| !app_paths.profiles.join(format!("{copy}.json")).exists())
+        .expect("some copy number is free");
+    fs::copy(from, profile_path(&copy, app_paths)?)?;
+    Ok(copy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{Speaker, TriggerEffect};
+
+    /// `state.json` as written before `schema_version` existed
+    const STATE_V1: &str = include_str!("../resources/state-v1-fixture.json");
+
+    #[test]
+    fn pre_versioned_state_loads() {
+        let state = controller_from_json(STATE_V1).unwrap();
+        assert_eq!(state.lightbar_colour, [255, 0, 128, 200]);
+        assert!(state.lightbar_enabled);
+        assert_eq!(state.battery_percentage, 80);
+        assert_eq!(state.playerleds, 3);
+        assert!(!state.microphone);
+        assert!(state.microphone_led);
+        assert!(matches!(state.speaker, Speaker::Headphone));
+        assert_eq!(state.volume, 60);
+        assert_eq!(state.attenuation, [2, 5]);
+        assert_eq!(state.trigger.side, "right");
+        assert!(matches!(
+            state.trigger.effect,
+            TriggerEffect::Weapon {
+                start: 2,
+                stop: 6,
+                strength: 8
+            }
+        ));
+
+        // Saved again it's the same state, tagged with the current version
+        let json = controller_to_json(&state).unwrap();
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+        let mut original: Value = serde_json::from_str(STATE_V1).unwrap();
+        original["schema_version"] = SCHEMA_VERSION.into();
+        assert_eq!(value, original);
+    }
+
+    #[test]
+    fn newer_state_is_backed_up_once() {
+        let app_paths = Arc::new(AppPaths::temporary("save-newer"));
+        let state_file = app_paths.config.join(STATE_FILE_NAME);
+        let newer = r#"{"schema_version": 99, "volume": 30, "haptics": {"mode": "new"}}"#;
+        fs::write(&state_file, newer).unwrap();
+
+        let state = load_state(&app_paths);
+        assert_eq!(state.volume, 30);
+        let backup = app_paths.config.join("state.json.schema-99.bak");
+        assert_eq!(fs::read_to_string(&backup).unwrap(), newer);
+
+        // Loading again leaves the first backup alone
+        fs::write(&state_file, r#"{"schema_version": 99, "volume": 31}"#).unwrap();
+        assert_eq!(load_state(&app_paths).volume, 31);
+        assert_eq!(fs::read_to_string(&backup).unwrap(), newer);
+
+        save_state(&state, &app_paths).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), newer);
+    }
+
+    #[test]
+    fn current_and_older_files_are_not_backed_up() {
+        let app_paths = Arc::new(AppPaths::temporary("save-current"));
+        fs::write(app_paths.profiles.join("old.json"), STATE_V1).unwrap();
+        save_profile("new", &Controller::default(), &app_paths).unwrap();
+
+        assert_eq!(load_profile("old", &app_paths).unwrap().volume, 60);
+        assert_eq!(load_profile("new", &app_paths).unwrap().volume, 0);
+        assert_eq!(list_profiles(&app_paths), ["new", "old"]);
+        assert_eq!(fs::read_dir(&app_paths.profiles).unwrap().count(), 2);
+    }
+}
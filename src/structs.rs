@@ -1,8 +1,17 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+
+use crate::error::DualsenseError;
 
 /// Controller state
-#[derive(Serialize, Deserialize, Debug)]
+///
+/// Fields missing from a saved file take their defaults, the colour and
+/// attenuation are padded to their full length
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct Controller {
+    /// Red, green, blue and brightness
+    #[serde(deserialize_with = "lightbar_colour")]
     pub lightbar_colour: Vec<u8>,
     pub lightbar_enabled: bool,
     pub battery_percentage: u8,
@@ -11,10 +20,34 @@ pub struct Controller {
     pub microphone_led: bool,
     pub speaker: Speaker,
     pub volume: u8,
+    /// Rumble and trigger
+    #[serde(deserialize_with = "attenuation")]
     pub attenuation: Vec<u8>,
     pub trigger: Trigger,
 }
 
+/// `values` cut or padded with `fill` to `len`, so indexing a short list
+/// from a file or a client can't panic
+fn padded<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    len: usize,
+    fill: u8,
+) -> Result<Vec<u8>, D::Error> {
+    let mut values = Vec::<u8>::deserialize(deserializer)?;
+    values.resize(len, fill);
+    Ok(values)
+}
+
+/// Missing channels and brightness are full
+fn lightbar_colour<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    padded(deserializer, 4, 255)
+}
+
+/// Missing levels are off
+fn attenuation<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    padded(deserializer, 2, 0)
+}
+
 /// Sensible defaults for a controller
 ///
 /// Used when we find no saved profile
@@ -35,10 +68,192 @@ impl Default for Controller {
     }
 }
 
+/// App preferences, not tied to a controller
+///
+/// Missing fields fall back to their defaults so older files still load
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// Most writes per second for a single slider-style setting
+    pub max_write_rate: u32,
+    /// `dualsensectl` to run instead of the one on `PATH`
+    pub dualsensectl_path: Option<String>,
+    /// Turn every connected pad off when the window closes
+    pub power_off_on_quit: bool,
+    /// Seconds between battery reads
+    pub battery_poll_interval: u32,
+    /// Battery percentages that trigger a low battery notification
+    pub low_battery_thresholds: Vec<u8>,
+    /// Notify once a charging pad is full
+    pub notify_when_charged: bool,
+    /// Listen for DSX packets from game mods
+    pub dsx_enabled: bool,
+    /// UDP port for DSX packets, on localhost only
+    pub dsx_port: u16,
+    /// Listen for OSC messages from TouchOSC, Pure Data and the like
+    pub osc_enabled: bool,
+    /// UDP port for OSC messages
+    pub osc_port: u16,
+    /// Accept OSC from other machines rather than only localhost
+    pub osc_listen_all: bool,
+    /// Serve the HTTP/WebSocket API
+    pub web_enabled: bool,
+    /// Port for the HTTP API, on localhost only
+    pub web_port: u16,
+    /// Apply profiles when a program from `auto_profile_rules` is running
+    pub auto_profile_enabled: bool,
+    pub auto_profile_rules: Vec<ProfileRule>,
+    /// Profile applied at startup and once no rule matches any more
+    ///
+    /// `None` keeps the saved state, or puts back the one from before an
+    /// automatic switch
+    pub default_profile: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            max_write_rate: 10,
+            dualsensectl_path: None,
+            power_off_on_quit: false,
+            battery_poll_interval: 30,
+            low_battery_thresholds: vec![20, 10, 5],
+            notify_when_charged: true,
+            dsx_enabled: false,
+            dsx_port: crate::dsx::DEFAULT_PORT,
+            osc_enabled: false,
+            osc_port: crate::osc::DEFAULT_PORT,
+            osc_listen_all: false,
+            web_enabled: false,
+            web_port: crate::web::DEFAULT_PORT,
+            auto_profile_enabled: false,
+            auto_profile_rules: Vec::new(),
+            default_profile: None,
+        }
+    }
+}
+
+/// What a `ProfileRule` pattern is compared against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum MatchOn {
+    /// The executable name, e.g. `eldenring.exe`
+    #[default]
+    Executable,
+    /// Anywhere in the full command line
+    CommandLine,
+}
+
+/// Applies `profile` while a matching program runs
+///
+/// When several match, the highest `priority` wins
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ProfileRule {
+    pub pattern: String,
+    pub match_on: MatchOn,
+    pub profile: String,
+    pub priority: i32,
+}
+
+/// How a pad is connected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Connection {
+    Usb,
+    Bluetooth,
+}
+
+/// A pad as reported by device enumeration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectedDevice {
+    pub serial: String,
+    pub connection: Connection,
+}
+
+/// Whether the pad is charging, as reported alongside the battery level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum ChargingState {
+    Charging,
+    Discharging,
+    Full,
+    #[default]
+    Unknown,
+}
+
+impl fmt::Display for ChargingState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            ChargingState::Charging => "charging",
+            ChargingState::Discharging => "discharging",
+            ChargingState::Full => "full",
+            ChargingState::Unknown => "unknown",
+        };
+        write!(f, "{state}")
+    }
+}
+
+/// Battery level in percent and charging state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct BatteryStatus {
+    pub level: u8,
+    pub state: ChargingState,
+}
+
+/// Firmware and hardware details of a pad
+///
+/// Everything is optional since `dualsensectl info` output varies between
+/// versions, lines we don't recognise end up in `other`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub serial: Option<String>,
+    pub connection: Option<Connection>,
+    pub hardware_version: Option<String>,
+    pub firmware_version: Option<String>,
+    /// (label, date) pairs, e.g. firmware and update build dates
+    pub build_dates: Vec<(String, String)>,
+    pub other: Vec<(String, String)>,
+}
+
+impl DeviceInfo {
+    /// Every known field as (label, value), in display order
+    pub fn rows(&self) -> Vec<(String, String)> {
+        let unknown = || "Unknown".to_string();
+        let mut rows = vec![
+            (
+                "Serial".to_string(),
+                self.serial.clone().unwrap_or_else(unknown),
+            ),
+            (
+                "Connection".to_string(),
+                self.connection.map_or_else(unknown, |c| format!("{c:?}")),
+            ),
+            (
+                "Hardware".to_string(),
+                self.hardware_version.clone().unwrap_or_else(unknown),
+            ),
+            (
+                "Firmware".to_string(),
+                self.firmware_version.clone().unwrap_or_else(unknown),
+            ),
+        ];
+        rows.extend(self.build_dates.iter().cloned());
+        rows.extend(self.other.iter().cloned());
+        rows
+    }
+
+    /// Plain text for pasting into bug reports
+    pub fn to_report(&self) -> String {
+        self.rows()
+            .iter()
+            .map(|(label, value)| format!("{label}: {value}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
 /// Speaker mode enum
 ///
 /// Default Internal
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub enum Speaker {
     #[default]
     Internal,
@@ -50,7 +265,7 @@ pub enum Speaker {
 /// Trigger modes with varying fields
 ///
 /// Default Off
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub enum TriggerEffect {
     #[default]
     Off,
@@ -102,7 +317,8 @@ pub enum TriggerEffect {
 }
 
 /// Trigger struct with mode string and params
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Trigger {
     pub side: String,
     pub effect: TriggerEffect,
@@ -188,7 +404,7 @@ impl Trigger {
                     .map(std::string::ToString::to_string)
                     .collect::<Vec<_>>()
                     .join(" ");
-                format!("trigger {} feedback-raw [{}]", self.side, strengths)
+                format!("trigger {} feedback-raw {}", self.side, strengths)
             }
             TriggerEffect::VibrationRaw {
                 amplitude,
@@ -200,7 +416,7 @@ impl Trigger {
                     .collect::<Vec<_>>()
                     .join(" ");
                 format!(
-                    "trigger {} vibration-raw [{}] {}",
+                    "trigger {} vibration-raw {} {}",
                     self.side, amplitudes, frequency
                 )
             }
@@ -210,4 +426,234 @@ impl Trigger {
             }
         }
     }
+
+    /// The reverse of `to_command`, from the side, mode and values of
+    /// `dualsensectl trigger SIDE MODE [VALUES...]`
+    ///
+    /// Values past what the mode needs are ignored, a value that isn't a
+    /// number fails rather than becoming 0
+    pub fn parse<S: AsRef<str>>(
+        side: &str,
+        mode: &str,
+        values: &[S],
+    ) -> Result<Trigger, DualsenseError> {
+        let values: Vec<&str> = values.iter().map(AsRef::as_ref).collect();
+
+        let effect = match mode {
+            "off" => TriggerEffect::Off,
+            "feedback" => {
+                let [position, strength] = trigger_values(mode, &values)?;
+                TriggerEffect::Feedback { position, strength }
+            }
+            "weapon" => {
+                let [start, stop, strength] = trigger_values(mode, &values)?;
+                TriggerEffect::Weapon {
+                    start,
+                    stop,
+                    strength,
+                }
+            }
+            "bow" => {
+                let [start, stop, strength, snapforce] = trigger_values(mode, &values)?;
+                TriggerEffect::Bow {
+                    start,
+                    stop,
+                    strength,
+                    snapforce,
+                }
+            }
+            "galloping" => {
+                let [start, stop, first_foot, second_foot, frequency] =
+                    trigger_values(mode, &values)?;
+                TriggerEffect::Galloping {
+                    start,
+                    stop,
+                    first_foot,
+                    second_foot,
+                    frequency,
+                }
+            }
+            "machine" => {
+                let [start, stop, strength_a, strength_b, frequency, period] =
+                    trigger_values(mode, &values)?;
+                TriggerEffect::Machine {
+                    start,
+                    stop,
+                    strength_a,
+                    strength_b,
+                    frequency,
+                    period,
+                }
+            }
+            "vibration" => {
+                let [position, amplitude, frequency] = trigger_values(mode, &values)?;
+                TriggerEffect::Vibration {
+                    position,
+                    amplitude,
+                    frequency,
+                }
+            }
+            "feedback-raw" => TriggerEffect::FeedbackRaw {
+                strength: trigger_values(mode, &values)?,
+            },
+            "vibration-raw" => {
+                let [amplitude @ .., frequency] = trigger_values::<11>(mode, &values)?;
+                TriggerEffect::VibrationRaw {
+                    amplitude,
+                    frequency,
+                }
+            }
+            // Raw bytes, decimal or 0x hex, checked by the backend
+            "mode" => TriggerEffect::Mode {
+                params: values.iter().map(|value| value.to_string()).collect(),
+            },
+            mode => {
+                return Err(DualsenseError::InvalidArgument(format!(
+                    "Unsupported trigger mode: {mode}"
+                )))
+            }
+        };
+
+        Ok(Trigger {
+            side: side.to_string(),
+            effect,
+        })
+    }
+}
+
+/// The first `N` of `values` as bytes
+fn trigger_values<const N: usize>(mode: &str, values: &[&str]) -> Result<[u8; N], DualsenseError> {
+    if values.len() < N {
+        return Err(DualsenseError::InvalidArgument(format!(
+            "Trigger mode {mode} needs {N} values, got {}",
+            values.len()
+        )));
+    }
+
+    let mut bytes = [0u8; N];
+    for (byte, value) in bytes.iter_mut().zip(values) {
+        *byte = value.trim().parse().map_err(|_| {
+            DualsenseError::InvalidArgument(format!(
+                "Trigger mode {mode}: {value:?} isn't a number from 0 to 255"
+            ))
+        })?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_lists_are_padded() {
+        let controller: Controller =
+            serde_json::from_str(r#"{"lightbar_colour": [10, 20], "attenuation": []}"#).unwrap();
+        assert_eq!(controller.lightbar_colour, [10, 20, 255, 255]);
+        assert_eq!(controller.attenuation, [0, 0]);
+
+        let controller: Controller = serde_json::from_str(
+            r#"{"lightbar_colour": [1, 2, 3, 4, 5], "attenuation": [6, 7, 8]}"#,
+        )
+        .unwrap();
+        assert_eq!(controller.lightbar_colour, [1, 2, 3, 4]);
+        assert_eq!(controller.attenuation, [6, 7]);
+
+        let controller: Controller = serde_json::from_str("{}").unwrap();
+        assert_eq!(controller.lightbar_colour, [255, 255, 255, 255]);
+        assert_eq!(controller.attenuation, [0, 0]);
+    }
+
+    /// `to_command` and back
+    fn round_trip(trigger: &Trigger) -> Trigger {
+        let command = trigger.to_command();
+        let parts: Vec<&str> = command.split_whitespace().collect();
+        Trigger::parse(parts[1], parts[2], &parts[3..]).unwrap()
+    }
+
+    #[test]
+    fn parse_round_trips_every_mode() {
+        let effects = [
+            TriggerEffect::Off,
+            TriggerEffect::Feedback {
+                position: 3,
+                strength: 6,
+            },
+            TriggerEffect::Weapon {
+                start: 2,
+                stop: 7,
+                strength: 5,
+            },
+            TriggerEffect::Bow {
+                start: 1,
+                stop: 4,
+                strength: 6,
+                snapforce: 3,
+            },
+            TriggerEffect::Galloping {
+                start: 0,
+                stop: 9,
+                first_foot: 2,
+                second_foot: 5,
+                frequency: 10,
+            },
+            TriggerEffect::Machine {
+                start: 1,
+                stop: 9,
+                strength_a: 3,
+                strength_b: 5,
+                frequency: 8,
+                period: 12,
+            },
+            TriggerEffect::Vibration {
+                position: 2,
+                amplitude: 5,
+                frequency: 30,
+            },
+            TriggerEffect::FeedbackRaw {
+                strength: [0, 1, 2, 3, 4, 5, 6, 7, 8, 8],
+            },
+            TriggerEffect::VibrationRaw {
+                amplitude: [8, 0, 8, 0, 8, 0, 8, 0, 8, 0],
+                frequency: 40,
+            },
+            TriggerEffect::Mode {
+                params: vec!["0x21".to_string(), "255".to_string()],
+            },
+        ];
+
+        for effect in effects {
+            let trigger = Trigger {
+                side: "left".to_string(),
+                effect,
+            };
+            let parsed = round_trip(&trigger);
+            assert_eq!(parsed.side, "left");
+            assert_eq!(
+                format!("{:?}", parsed.effect),
+                format!("{:?}", trigger.effect)
+            );
+        }
+    }
+
+    #[test]
+    fn parse_rejects_bad_values() {
+        assert!(Trigger::parse("left", "feedback", &["3"]).is_err());
+        assert!(Trigger::parse("left", "feedback", &["3", "x"]).is_err());
+        assert!(Trigger::parse("left", "feedback", &["3", "256"]).is_err());
+        assert!(Trigger::parse("left", "feedback", &["-1", "6"]).is_err());
+        assert!(Trigger::parse("left", "sideways", &["1"]).is_err());
+    }
+
+    #[test]
+    fn parse_ignores_extra_values() {
+        let trigger = Trigger::parse("right", "feedback", &["3", "6", "9"]).unwrap();
+        assert!(matches!(
+            trigger.effect,
+            TriggerEffect::Feedback {
+                position: 3,
+                strength: 6
+            }
+        ));
+    }
 }
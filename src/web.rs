@@ -0,0 +1,446 @@
+use log::{debug, error, info};
+use serde_json::{json, Value};
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::devices::DeviceRegistry;
+use crate::error::DualsenseError;
+use crate::rpc::{event_value, Handler};
+use crate::save::AppPaths;
+use crate::worker::Worker;
+
+/// Port the HTTP API listens on unless changed in Preferences
+pub const DEFAULT_PORT: u16 = 8787;
+
+/// Decodes `%XX` escapes, browsers escape the colons in serials
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Path segments and the `device` query parameter of a request URL
+fn parse_url(url: &str) -> (Vec<String>, Option<String>) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(percent_decode)
+        .collect();
+    let device = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "device")
+        .map(|(_, value)| percent_decode(value));
+    (segments, device)
+}
+
+fn status_code(err: &DualsenseError) -> u16 {
+    match err {
+        DualsenseError::InvalidArgument(_) => 400,
+        DualsenseError::NoDevice => 404,
+        DualsenseError::PermissionDenied => 403,
+        DualsenseError::Unsupported(_) => 501,
+        DualsenseError::Timeout => 504,
+        DualsenseError::BinaryMissing | DualsenseError::CommandFailed(_) => 500,
+    }
+}
+
+fn json_response(status: u16, body: &Value) -> Response<io::Cursor<Vec<u8>>> {
+    let content_type =
+        Header::from_bytes("Content-Type", "application/json").expect("static header is valid");
+    Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+fn read_body(request: &mut Request) -> Result<Value, DualsenseError> {
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body)?;
+    serde_json::from_str(&body)
+        .map_err(|err| DualsenseError::InvalidArgument(format!("Request body: {err}")))
+}
+
+/// Adds the `device` from the query string to `params`
+fn with_device(mut params: Value, device: Option<String>) -> Value {
+    if let (Value::Object(map), Some(device)) = (&mut params, device) {
+        map.insert("device".to_string(), Value::String(device));
+    }
+    params
+}
+
+/// Maps a REST call onto the matching RPC method
+///
+/// ```text
+/// GET  /state                  current state as a Controller
+/// PUT  /state                  apply a Controller
+/// GET  /battery
+/// PUT  /lightbar               {"colour":[r,g,b,brightness]} or {"enabled":false}
+/// PUT  /trigger/{side}         a TriggerEffect, e.g. {"Feedback":{"position":3,"strength":6}}
+/// POST /profiles/{name}/apply
+/// ```
+fn route(handler: &Handler, request: &mut Request) -> Result<Value, DualsenseError> {
+    let (segments, device) = parse_url(request.url());
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let method = request.method().clone();
+
+    match (method, &segments[..]) {
+        (Method::Get, ["state"]) => handler.handle("get_state", with_device(json!({}), device)),
+        (Method::Put, ["state"]) => {
+            let state = read_body(request)?;
+            handler.handle("apply", with_device(json!({ "state": state }), device))
+        }
+        (Method::Get, ["battery"]) => handler.handle("get_battery", with_device(json!({}), device)),
+        (Method::Put, ["lightbar"]) => {
+            let params = read_body(request)?;
+            handler.handle("set_lightbar", with_device(params, device))
+        }
+        (Method::Put, ["trigger", side]) => {
+            if !matches!(*side, "left" | "right" | "both") {
+                return Err(DualsenseError::InvalidArgument(format!(
+                    "Trigger side must be left, right or both, not {side}"
+                )));
+            }
+            let effect = read_body(request)?;
+            let params = json!({ "side": side, "effect": effect });
+            handler.handle("set_trigger", with_device(params, device))
+        }
+        (Method::Post, ["profiles", name, "apply"]) => {
+            let params = json!({ "name": name });
+            handler.handle("apply_profile", with_device(params, device))
+        }
+        (method, _) => Err(DualsenseError::InvalidArgument(format!(
+            "No route for {} {}",
+            method,
+            request.url()
+        ))),
+    }
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// `host` without its port is 127.0.0.1 or localhost
+fn is_local_host(host: &str) -> bool {
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    };
+    name == "127.0.0.1" || name.eq_ignore_ascii_case("localhost")
+}
+
+/// Why a request has to be turned away, if it does
+///
+/// Any web page can send requests to localhost, so:
+/// - `Host` must be us, or a DNS rebinding page could read the replies
+/// - a browser's `Origin` must be a local page
+/// - changes must be JSON, which a page can't send cross-origin without
+///   a CORS preflight we never answer
+fn rejection(request: &Request, port: u16) -> Option<(u16, &'static str)> {
+    let host_ok = header(request, "Host").is_some_and(|host| {
+        host.eq_ignore_ascii_case(&format!("127.0.0.1:{port}"))
+            || host.eq_ignore_ascii_case(&format!("localhost:{port}"))
+    });
+    if !host_ok {
+        return Some((403, "Host must be 127.0.0.1 or localhost"));
+    }
+
+    let origin_ok = header(request, "Origin").is_none_or(|origin| {
+        origin
+            .strip_prefix("http://")
+            .or_else(|| origin.strip_prefix("https://"))
+            .is_some_and(is_local_host)
+    });
+    if !origin_ok {
+        return Some((403, "Only pages on localhost may use the API"));
+    }
+
+    let is_json = header(request, "Content-Type").is_some_and(|content_type| {
+        content_type
+            .split(';')
+            .next()
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+    });
+    if *request.method() != Method::Get && !is_json {
+        return Some((415, "Content-Type must be application/json"));
+    }
+
+    None
+}
+
+fn is_websocket(request: &Request) -> bool {
+    request.headers().iter().any(|header| {
+        header.field.equiv("Upgrade") && header.value.as_str().eq_ignore_ascii_case("websocket")
+    })
+}
+
+/// Upgrades `/events` and pushes every state and battery change to it
+///
+/// The socket is write only, anything the client sends is ignored
+fn serve_websocket(worker: &Worker, request: Request) {
+    let key = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Sec-WebSocket-Key"))
+        .map(|header| derive_accept_key(header.value.as_bytes()));
+    let Some(accept) = key else {
+        let _ = request.respond(json_response(
+            400,
+            &json!({ "error": "Missing Sec-WebSocket-Key" }),
+        ));
+        return;
+    };
+
+    let accept = Header::from_bytes("Sec-WebSocket-Accept", accept).expect("accept key is ASCII");
+    let stream = request.upgrade("websocket", Response::empty(101).with_header(accept));
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+    // Subscribed before the thread starts so nothing is missed in between
+    let events = worker.subscribe();
+    thread::spawn(move || {
+        while let Ok(event) = events.recv_blocking() {
+            let Some(event) = event_value(&event) else {
+                continue;
+            };
+            if let Err(err) = socket.send(Message::Text(event.to_string())) {
+                debug!("WebSocket client went away: {}", err);
+                break;
+            }
+        }
+    });
+}
+
+fn serve_request(handler: &Handler, port: u16, mut request: Request) {
+    // Checked for the WebSocket too, a page could otherwise read every event
+    if let Some((status, message)) = rejection(&request, port) {
+        debug!(
+            "Rejecting {} {}: {}",
+            request.method(),
+            request.url(),
+            message
+        );
+        let _ = request.respond(json_response(status, &json!({ "error": message })));
+        return;
+    }
+
+    let (segments, _) = parse_url(request.url());
+    if segments == ["events"] && is_websocket(&request) {
+        serve_websocket(handler.worker(), request);
+        return;
+    }
+
+    let response = match route(handler, &mut request) {
+        Ok(result) => json_response(200, &json!({ "result": result })),
+        Err(err) => json_response(status_code(&err), &json!({ "error": err.to_string() })),
+    };
+    if let Err(err) = request.respond(response) {
+        debug!("Failed to answer HTTP request: {}", err);
+    }
+}
+
+/// Serves the REST API and the `/events` WebSocket on `127.0.0.1:port`
+///
+/// Requests from web pages that aren't on localhost are refused, see
+/// `rejection`
+pub fn serve(
+    registry: Arc<DeviceRegistry>,
+    worker: Worker,
+    app_paths: Arc<AppPaths>,
+    port: u16,
+) -> io::Result<()> {
+    let server = Server::http((Ipv4Addr::LOCALHOST, port)).map_err(io::Error::other)?;
+    let handler = Arc::new(Handler::new(registry, worker, app_paths));
+
+    thread::Builder::new()
+        .name("web".to_string())
+        .spawn(move || {
+            for request in server.incoming_requests() {
+                let handler = Arc::clone(&handler);
+                // Requests wait on the pad, don't hold up the others
+                if let Err(err) =
+                    thread::Builder::new().spawn(move || serve_request(&handler, port, request))
+                {
+                    error!("Failed to start HTTP request thread: {}", err);
+                }
+            }
+        })?;
+
+    info!("HTTP API listening on http://127.0.0.1:{}", port);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{mock_call, MockDevices};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    struct Api {
+        devices: MockDevices,
+        port: u16,
+    }
+
+    impl Api {
+        fn start(name: &str) -> Self {
+            let devices = MockDevices::new(name);
+            let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+                .and_then(|listener| listener.local_addr())
+                .unwrap()
+                .port();
+            serve(
+                Arc::clone(&devices.registry),
+                devices.worker.clone(),
+                Arc::clone(&devices.app_paths),
+                port,
+            )
+            .unwrap();
+            Self { devices, port }
+        }
+
+        /// Sends a raw request with `headers` and returns the status code
+        fn status(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &str) -> u16 {
+            let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, self.port)).unwrap();
+            let mut request = format!("{method} {path} HTTP/1.1\r\nConnection: close\r\n");
+            for (name, value) in headers {
+                request.push_str(&format!("{name}: {value}\r\n"));
+            }
+            request.push_str(&format!("Content-Length: {}\r\n\r\n{body}", body.len()));
+            stream.write_all(request.as_bytes()).unwrap();
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+                .split_whitespace()
+                .nth(1)
+                .and_then(|status| status.parse().ok())
+                .unwrap_or_else(|| panic!("bad response: {response:?}"))
+        }
+
+        fn host(&self) -> String {
+            format!("127.0.0.1:{}", self.port)
+        }
+    }
+
+    #[test]
+    fn host_must_be_local() {
+        let api = Api::start("web-host");
+        let host = api.host();
+        let localhost = format!("localhost:{}", api.port);
+        assert_eq!(api.status("GET", "/state", &[("Host", &host)], ""), 200);
+        assert_eq!(
+            api.status("GET", "/state", &[("Host", &localhost)], ""),
+            200
+        );
+
+        // DNS rebinding, the browser still sends the attacker's name
+        let rebound = format!("evil.example:{}", api.port);
+        assert_eq!(api.status("GET", "/state", &[("Host", &rebound)], ""), 403);
+        assert_eq!(api.status("GET", "/state", &[], ""), 403);
+    }
+
+    #[test]
+    fn origin_must_be_local() {
+        let api = Api::start("web-origin");
+        let host = api.host();
+        let get = |origin| api.status("GET", "/state", &[("Host", &host), ("Origin", origin)], "");
+        assert_eq!(get("http://localhost:3000"), 200);
+        assert_eq!(get("http://127.0.0.1"), 200);
+        assert_eq!(get("https://evil.example"), 403);
+        assert_eq!(get("http://localhost.evil.example"), 403);
+        assert_eq!(get("null"), 403);
+
+        let websocket = api.status(
+            "GET",
+            "/events",
+            &[
+                ("Host", &host),
+                ("Origin", "https://evil.example"),
+                ("Upgrade", "websocket"),
+                ("Connection", "Upgrade"),
+                ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+                ("Sec-WebSocket-Version", "13"),
+            ],
+            "",
+        );
+        assert_eq!(websocket, 403);
+    }
+
+    #[test]
+    fn changes_must_be_json() {
+        let api = Api::start("web-json");
+        let host = api.host();
+        let body = r#"{"colour":[10,20,30,255]}"#;
+
+        // What a cross-origin form or simple fetch would send
+        let form = [("Host", host.as_str()), ("Content-Type", "text/plain")];
+        assert_eq!(api.status("PUT", "/lightbar", &form, body), 415);
+        assert_eq!(
+            api.status("PUT", "/lightbar", &[("Host", &host)], body),
+            415
+        );
+        assert!(api.devices.new_calls().is_empty());
+
+        let json = [
+            ("Host", host.as_str()),
+            ("Content-Type", "application/json; charset=utf-8"),
+        ];
+        assert_eq!(api.status("PUT", "/lightbar", &json, body), 200);
+        assert_eq!(
+            api.devices.new_calls(),
+            [mock_call("lightbar 10 20 30 255")]
+        );
+    }
+
+    #[test]
+    fn trigger_side_is_checked() {
+        let api = Api::start("web-trigger");
+        let host = api.host();
+        let json = [
+            ("Host", host.as_str()),
+            ("Content-Type", "application/json"),
+        ];
+        let body = r#"{"Feedback":{"position":3,"strength":6}}"#;
+
+        assert_eq!(api.status("PUT", "/trigger/middle", &json, body), 400);
+        assert!(api.devices.new_calls().is_empty());
+
+        assert_eq!(api.status("PUT", "/trigger/left", &json, body), 200);
+        assert_eq!(
+            api.devices.new_calls(),
+            [mock_call("trigger left feedback 3 6")]
+        );
+    }
+}
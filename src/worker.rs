@@ -0,0 +1,622 @@
+use log::{debug, error, info};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::devices::DeviceHandle;
+use crate::dualsensectl::{
+    apply_controller, change_attenuation_amount, change_lightbar_colour, change_playerleds_amount,
+    change_triggers, change_volume, device_info, power_off, report_battery, toggle_lightbar,
+    toggle_microphone, toggle_microphone_led, toggle_speaker, ApplyReport,
+};
+use crate::error::DualsenseError;
+use crate::save::AppPaths;
+use crate::structs::{BatteryStatus, Controller, DeviceInfo, Speaker, Trigger, TriggerEffect};
+
+/// One hardware operation, mirrors the functions in `dualsensectl.rs`
+#[derive(Debug, Clone)]
+pub enum DeviceCommand {
+    Lightbar(bool),
+    LightbarColour(Vec<u8>),
+    PlayerLeds(u8),
+    Microphone(bool),
+    MicrophoneLed(bool),
+    Speaker(Speaker),
+    Volume(u8),
+    Attenuation(Vec<u8>),
+    Trigger(Trigger),
+    /// Changes the effect, keeping whichever side is currently set
+    TriggerEffect(TriggerEffect),
+    Battery,
+    PowerOff,
+    Info,
+    /// Pushes every setting of a controller state, rolling back on failure
+    Apply(Controller),
+    /// Applies the device's current state again, e.g. after a reconnect
+    Restore,
+}
+
+impl DeviceCommand {
+    /// Whether a successful run changes the saved state
+    ///
+    /// Reads and restores don't
+    fn changes_state(&self) -> bool {
+        !matches!(
+            self,
+            DeviceCommand::Info | DeviceCommand::Battery | DeviceCommand::Restore
+        )
+    }
+
+    /// Commands that fire on every slider tick share a key so only the
+    /// newest pending value per device gets written
+    fn coalesce_key(&self) -> Option<&'static str> {
+        match self {
+            DeviceCommand::LightbarColour(_) => Some("lightbar-colour"),
+            DeviceCommand::Volume(_) => Some("volume"),
+            // Sides are separate so one can't drop the other's update, "both"
+            // isn't coalesced so it stays ordered against either side
+            DeviceCommand::Trigger(trigger) => match trigger.side.as_str() {
+                "left" => Some("trigger-left"),
+                "right" => Some("trigger-right"),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Anything a command reads back besides the controller state
+#[derive(Debug, Clone)]
+pub enum Output {
+    Done,
+    Battery(BatteryStatus),
+    Info(DeviceInfo),
+    Applied(ApplyReport),
+}
+
+/// What the worker sends back: the outcome and the device state after it
+///
+/// `state` is always the committed state, so on failure it's what the UI
+/// should fall back to
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub result: Result<Output, DualsenseError>,
+    pub state: Controller,
+}
+
+/// Something that changed on a device, for anyone watching from outside
+/// the GUI
+#[derive(Debug, Clone)]
+pub enum DeviceEvent {
+    StateChanged {
+        serial: Option<String>,
+        state: Controller,
+    },
+    Battery {
+        serial: Option<String>,
+        status: BatteryStatus,
+    },
+}
+
+struct Job {
+    device: DeviceHandle,
+    command: DeviceCommand,
+    reply: async_channel::Sender<Reply>,
+    /// Leaves the device's state and saved file alone
+    transient: bool,
+    /// Callers of the pending commands this one replaced, they get its reply
+    superseded: Vec<async_channel::Sender<Reply>>,
+}
+
+enum Message {
+    Run(Job),
+    SetMaxWriteRate(u32),
+    Subscribe(async_channel::Sender<DeviceEvent>),
+}
+
+/// Serial, setting and whether it's transient, so a game's override can't
+/// swallow a pending change of the user's or the other way round
+type CoalesceKey = (Option<String>, &'static str, bool);
+
+/// Commands waiting to run on the worker thread
+///
+/// Coalescable commands wait until their key's last write is at least
+/// `min_interval` old, a newer value for the same key replaces the pending
+/// one and its caller gets the newer command's reply
+struct Queue {
+    app_paths: Arc<AppPaths>,
+    min_interval: Duration,
+    pending: HashMap<CoalesceKey, Job>,
+    last_write: HashMap<CoalesceKey, Instant>,
+    subscribers: Vec<async_channel::Sender<DeviceEvent>>,
+}
+
+impl Queue {
+    fn new(app_paths: Arc<AppPaths>, max_write_rate: u32) -> Self {
+        Self {
+            app_paths,
+            min_interval: min_interval(max_write_rate),
+            pending: HashMap::new(),
+            last_write: HashMap::new(),
+            subscribers: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, mut job: Job) {
+        match job.command.coalesce_key() {
+            Some(key) => {
+                let key = (job.device.serial.clone(), key, job.transient);
+                if let Some(old) = self.pending.remove(&key) {
+                    debug!("Dropping superseded command: {:?}", old.command);
+                    job.superseded.extend(old.superseded);
+                    job.superseded.push(old.reply);
+                }
+                self.pending.insert(key, job);
+            }
+            None => {
+                // Keep pending slider values ahead of anything sent after them
+                self.flush_device(&job.device.serial);
+                self.run(job);
+            }
+        }
+    }
+
+    /// When the next pending command may run
+    fn next_due(&self) -> Option<Instant> {
+        let now = Instant::now();
+        self.pending
+            .keys()
+            .map(|key| self.due(key).unwrap_or(now))
+            .min()
+    }
+
+    /// `None` if `key` has never been written and can go straight away
+    fn due(&self, key: &CoalesceKey) -> Option<Instant> {
+        self.last_write
+            .get(key)
+            .map(|last| *last + self.min_interval)
+    }
+
+    fn run_due(&mut self) {
+        let now = Instant::now();
+        let due: Vec<CoalesceKey> = self
+            .pending
+            .keys()
+            .filter(|key| self.due(key).is_none_or(|due| due <= now))
+            .cloned()
+            .collect();
+
+        for key in due {
+            self.run_pending(key);
+        }
+    }
+
+    fn flush_device(&mut self, serial: &Option<String>) {
+        let keys: Vec<CoalesceKey> = self
+            .pending
+            .keys()
+            .filter(|(s, _, _)| s == serial)
+            .cloned()
+            .collect();
+
+        for key in keys {
+            self.run_pending(key);
+        }
+    }
+
+    fn flush_all(&mut self) {
+        let keys: Vec<CoalesceKey> = self.pending.keys().cloned().collect();
+        for key in keys {
+            self.run_pending(key);
+        }
+    }
+
+    fn run_pending(&mut self, key: CoalesceKey) {
+        if let Some(job) = self.pending.remove(&key) {
+            self.run(job);
+            self.last_write.insert(key, Instant::now());
+        }
+    }
+
+    fn run(&mut self, job: Job) {
+        let changes_state = job.command.changes_state() && !job.transient;
+        let reply = execute(&job.device, job.command, job.transient, &self.app_paths);
+
+        let serial = job.device.serial.clone();
+        match &reply.result {
+            Ok(Output::Battery(status)) => self.publish(DeviceEvent::Battery {
+                serial,
+                status: *status,
+            }),
+            Ok(_) if changes_state => self.publish(DeviceEvent::StateChanged {
+                serial,
+                state: reply.state.clone(),
+            }),
+            _ => {}
+        }
+
+        // The callers may have gone away, that's fine
+        for superseded in &job.superseded {
+            let _ = superseded.send_blocking(reply.clone());
+        }
+        let _ = job.reply.send_blocking(reply);
+    }
+
+    /// Sends `event` to every subscriber, forgetting the ones that left
+    fn publish(&mut self, event: DeviceEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.try_send(event.clone()).is_ok());
+    }
+}
+
+fn min_interval(max_write_rate: u32) -> Duration {
+    Duration::from_secs(1) / max_write_rate.max(1)
+}
+
+/// Handle to the thread that owns all device I/O
+///
+/// Commands run one at a time in the order they were sent, so quick clicks
+/// can't reorder hardware writes
+#[derive(Clone)]
+pub struct Worker {
+    sender: mpsc::Sender<Message>,
+}
+
+impl Worker {
+    /// Starts the worker, `max_write_rate` limits slider-style commands per
+    /// device and setting
+    pub fn spawn(app_paths: Arc<AppPaths>, max_write_rate: u32) -> Self {
+        let (sender, receiver) = mpsc::channel::<Message>();
+
+        thread::Builder::new()
+            .name("device-io".to_string())
+            .spawn(move || {
+                let mut queue = Queue::new(app_paths, max_write_rate);
+                loop {
+                    let message = match queue.next_due() {
+                        Some(due) => {
+                            receiver.recv_timeout(due.saturating_duration_since(Instant::now()))
+                        }
+                        None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                    };
+
+                    match message {
+                        Ok(Message::Run(job)) => queue.push(job),
+                        Ok(Message::Subscribe(subscriber)) => queue.subscribers.push(subscriber),
+                        Ok(Message::SetMaxWriteRate(rate)) => {
+                            info!("Max write rate set to {} per second", rate);
+                            queue.min_interval = min_interval(rate);
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => {
+                            queue.flush_all();
+                            break;
+                        }
+                    }
+
+                    queue.run_due();
+                }
+                info!("Device worker stopped.");
+            })
+            .expect("Failed to spawn device worker thread");
+
+        Self { sender }
+    }
+
+    pub fn set_max_write_rate(&self, max_write_rate: u32) {
+        if self
+            .sender
+            .send(Message::SetMaxWriteRate(max_write_rate))
+            .is_err()
+        {
+            error!("Device worker is gone, can't change write rate.");
+        }
+    }
+
+    /// Every state change and battery reading from now on, whoever sent
+    /// the command
+    pub fn subscribe(&self) -> async_channel::Receiver<DeviceEvent> {
+        let (sender, receiver) = async_channel::unbounded();
+        if self.sender.send(Message::Subscribe(sender)).is_err() {
+            error!("Device worker is gone, can't subscribe.");
+        }
+        receiver
+    }
+
+    /// Queues `command` for `device`, the reply arrives on the returned
+    /// channel once it has run
+    ///
+    /// If a newer value for the same setting superseded it, the reply is
+    /// the newer command's
+    pub fn send(
+        &self,
+        device: &DeviceHandle,
+        command: DeviceCommand,
+    ) -> async_channel::Receiver<Reply> {
+        self.queue(device, command, false)
+    }
+
+    /// Like `send` but only drives the pad, the device's state and saved
+    /// file stay as they were
+    ///
+    /// For overrides like a game's DSX effects, `Restore` goes back to the
+    /// user's own settings afterwards
+    pub fn send_transient(
+        &self,
+        device: &DeviceHandle,
+        command: DeviceCommand,
+    ) -> async_channel::Receiver<Reply> {
+        self.queue(device, command, true)
+    }
+
+    fn queue(
+        &self,
+        device: &DeviceHandle,
+        command: DeviceCommand,
+        transient: bool,
+    ) -> async_channel::Receiver<Reply> {
+        let (reply, receiver) = async_channel::bounded(1);
+        let job = Job {
+            device: device.clone(),
+            command,
+            reply,
+            transient,
+            superseded: Vec::new(),
+        };
+
+        if self.sender.send(Message::Run(job)).is_err() {
+            error!("Device worker is gone, dropping command.");
+        }
+
+        receiver
+    }
+
+    /// Like `send` but blocks until the command has run
+    pub fn execute(&self, device: &DeviceHandle, command: DeviceCommand) -> Reply {
+        self.send(device, command)
+            .recv_blocking()
+            .unwrap_or_else(|_| Reply {
+                result: Err(DualsenseError::CommandFailed(
+                    "The device worker is gone".to_string(),
+                )),
+                state: device
+                    .controller
+                    .lock()
+                    .map(|c| c.clone())
+                    .unwrap_or_default(),
+            })
+    }
+}
+
+/// Pushes `device`'s saved state to the pad, or `profile` instead if given
+///
+/// `send` runs the command and hands its reply to the callback, so the
+/// daemon can block on it and the GUI can wait on its main loop
+pub fn restore_device<F>(device: &DeviceHandle, profile: Option<Controller>, send: F)
+where
+    F: FnOnce(DeviceCommand, fn(Reply)),
+{
+    info!("Restoring state of {}", device.label());
+    let command = match profile {
+        Some(profile) => DeviceCommand::Apply(profile),
+        None => DeviceCommand::Restore,
+    };
+    send(command, |reply| match reply.result {
+        Ok(Output::Applied(report)) if !report.succeeded() => {
+            error!("Failed to restore controller state: {}", report);
+        }
+        Err(err) => error!("Failed to restore controller state: {}", err),
+        Ok(_) => {}
+    });
+}
+
+fn lock_controller(device: &DeviceHandle) -> MutexGuard<'_, Controller> {
+    device.controller.lock().unwrap_or_else(|poisoned| {
+        error!("Controller lock was poisoned, recovering.");
+        poisoned.into_inner()
+    })
+}
+
+/// Runs one command against the device and saves its state on success
+///
+/// The backend works on a copy so the GUI isn't locked out for the length
+/// of a slow write, a `transient` command's copy is never committed
+fn execute(
+    device: &DeviceHandle,
+    command: DeviceCommand,
+    transient: bool,
+    app_paths: &Arc<AppPaths>,
+) -> Reply {
+    let mut scratch = lock_controller(device).clone();
+    let ctrl = &mut scratch;
+    let backend = &*device.backend;
+
+    let save = command.changes_state() && !transient;
+
+    let done = |()| Output::Done;
+    let result = match command {
+        DeviceCommand::Info => device_info(backend, device.serial.as_deref()).map(Output::Info),
+        DeviceCommand::Battery => report_battery(backend, ctrl).map(Output::Battery),
+        DeviceCommand::Lightbar(state) => toggle_lightbar(backend, state, ctrl).map(done),
+        DeviceCommand::LightbarColour(colour) => {
+            change_lightbar_colour(backend, colour, ctrl).map(done)
+        }
+        DeviceCommand::PlayerLeds(amount) => {
+            change_playerleds_amount(backend, amount, ctrl).map(done)
+        }
+        DeviceCommand::Microphone(state) => toggle_microphone(backend, state, ctrl).map(done),
+        DeviceCommand::MicrophoneLed(state) => {
+            toggle_microphone_led(backend, state, ctrl).map(done)
+        }
+        DeviceCommand::Speaker(speaker) => toggle_speaker(backend, speaker, ctrl).map(done),
+        DeviceCommand::Volume(volume) => change_volume(backend, volume, ctrl).map(done),
+        DeviceCommand::Attenuation(attenuation) => {
+            change_attenuation_amount(backend, attenuation, ctrl).map(done)
+        }
+        DeviceCommand::Trigger(trigger) => change_triggers(backend, trigger, ctrl).map(done),
+        DeviceCommand::TriggerEffect(effect) => {
+            let trigger = Trigger {
+                side: ctrl.trigger.side.clone(),
+                effect,
+            };
+            change_triggers(backend, trigger, ctrl).map(done)
+        }
+        DeviceCommand::PowerOff => power_off(backend).map(done),
+        DeviceCommand::Apply(target) => {
+            Ok(Output::Applied(apply_controller(backend, &target, ctrl)))
+        }
+        DeviceCommand::Restore => {
+            let target = ctrl.clone();
+            Ok(Output::Applied(apply_controller(backend, &target, ctrl)))
+        }
+    };
+
+    let mut committed = lock_controller(device);
+    if !transient && result.is_ok() {
+        *committed = scratch;
+        if save {
+            if let Err(err) = device.save(&committed, app_paths) {
+                error!("Failed to save controller state: {err}");
+            }
+        }
+    }
+
+    Reply {
+        result,
+        state: committed.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use crate::devices::{mock_call, MockDevices, MOCK_SERIAL};
+
+    /// Writes per second in these tests, slow enough to see the waits
+    const RATE: u32 = 5;
+
+    fn devices(name: &str) -> MockDevices {
+        let devices = MockDevices::new(name);
+        devices.worker.set_max_write_rate(RATE);
+        devices
+    }
+
+    fn volume(reply: &async_channel::Receiver<Reply>) -> u8 {
+        let reply = reply.recv_blocking().unwrap();
+        assert!(reply.result.is_ok(), "{:?}", reply.result);
+        reply.state.volume
+    }
+
+    #[test]
+    fn superseded_values_are_dropped() {
+        let devices = devices("worker-superseded");
+        let device = devices.device();
+
+        let first = devices.worker.send(&device, DeviceCommand::Volume(10));
+        let second = devices.worker.send(&device, DeviceCommand::Volume(20));
+        let third = devices.worker.send(&device, DeviceCommand::Volume(30));
+
+        assert_eq!(volume(&first), 10);
+        // The replaced caller hears about the value that was written instead
+        assert_eq!(volume(&second), 30);
+        assert_eq!(volume(&third), 30);
+        assert_eq!(
+            devices.new_calls(),
+            [mock_call("volume 10"), mock_call("volume 30")]
+        );
+    }
+
+    #[test]
+    fn writes_are_rate_limited_per_setting() {
+        let devices = devices("worker-rate");
+        let device = devices.device();
+
+        let start = Instant::now();
+        volume(&devices.worker.send(&device, DeviceCommand::Volume(10)));
+        volume(&devices.worker.send(&device, DeviceCommand::Volume(20)));
+        assert!(start.elapsed() >= min_interval(RATE));
+
+        // Another setting has its own limit
+        let start = Instant::now();
+        let reply = devices
+            .worker
+            .execute(&device, DeviceCommand::LightbarColour(vec![1, 2, 3, 4]));
+        assert!(reply.result.is_ok());
+        assert!(start.elapsed() < min_interval(RATE));
+    }
+
+    #[test]
+    fn pending_values_run_before_other_commands() {
+        let devices = devices("worker-flush");
+        let device = devices.device();
+
+        volume(&devices.worker.send(&device, DeviceCommand::Volume(10)));
+        let pending = devices.worker.send(&device, DeviceCommand::Volume(20));
+        let start = Instant::now();
+        let reply = devices
+            .worker
+            .execute(&device, DeviceCommand::PlayerLeds(3));
+        assert!(reply.result.is_ok());
+        assert!(start.elapsed() < min_interval(RATE));
+        assert_eq!(volume(&pending), 20);
+
+        assert_eq!(
+            devices.new_calls(),
+            [
+                mock_call("volume 10"),
+                mock_call("volume 20"),
+                mock_call("player-leds 3")
+            ]
+        );
+    }
+
+    #[test]
+    fn failures_and_transient_commands_leave_the_state_alone() {
+        let devices = MockDevices::with_backend(
+            "worker-uncommitted",
+            MockBackend::new()
+                .with_device(MOCK_SERIAL)
+                .failing_on("player-leds"),
+        );
+        let device = devices.device();
+        let before = devices.state();
+
+        let reply = devices
+            .worker
+            .execute(&device, DeviceCommand::PlayerLeds(3));
+        assert!(reply.result.is_err());
+        assert_eq!(reply.state.playerleds, before.playerleds);
+
+        let reply = devices
+            .worker
+            .send_transient(&device, DeviceCommand::Volume(50))
+            .recv_blocking()
+            .unwrap();
+        assert!(reply.result.is_ok());
+        assert_eq!(reply.state.volume, before.volume);
+        assert_eq!(devices.state().volume, before.volume);
+        assert_eq!(
+            devices.new_calls(),
+            [mock_call("player-leds 3"), mock_call("volume 50")]
+        );
+        // Nothing was committed, so nothing was saved
+        assert_eq!(
+            std::fs::read_dir(&devices.app_paths.config)
+                .unwrap()
+                .count(),
+            0
+        );
+
+        devices.worker.execute(&device, DeviceCommand::Volume(60));
+        assert_eq!(devices.state().volume, 60);
+        assert_eq!(
+            std::fs::read_dir(&devices.app_paths.config)
+                .unwrap()
+                .count(),
+            1
+        );
+    }
+}